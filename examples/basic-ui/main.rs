@@ -7,9 +7,11 @@ use yarge::helpers::*;
 use yarge::resources::Resources;
 use yarge::camera::*;
 use yarge::renderer;
+use yarge::renderer::clip::{ClipRect, ClipSpace};
 use yarge::{font, debug};
 use yarge::font::FontRenderer;
 use yarge::{Rectangle, RectangleProps};
+use yarge::nine_slice::{NineSlice};
 
 const WIDTH: u32 = 1024;
 const HEIGHT: u32 = 780;
@@ -55,7 +57,7 @@ fn run() -> Result<(), failure::Error> {
     viewport.set_used();
 
 
-    let top_bar_text = font::Text::new(
+    let mut top_bar_text = font::Text::new(
         "YARGE UI".to_string(),
         font::TextSettings {
             font: "dejavu".to_string(),
@@ -66,13 +68,26 @@ fn run() -> Result<(), failure::Error> {
         }
     );
 
-    let left_sidebar = Rectangle::new(&RectangleProps {
-        width: 280.0,
-        height: 400.0,
-        pos: (20.0, 100.0),
-        color: (0.75, 0.85, 0.45, 0.70),
+    let mut top_bar = Rectangle::new(&RectangleProps {
+        width: viewport.w,
+        height: 60.0,
+        pos: (0.0, 0.0),
+        color: (0.65, 0.65, 0.65, 1.0),
+    })?;
+
+    let mut main_content = Rectangle::new(&RectangleProps {
+        width: viewport.w - 340.0,
+        height: 600.0,
+        pos: (320.0, 100.0),
+        color: (0.2, 0.2, 0.2, 0.80),
     })?;
 
+    let mut left_sidebar = NineSlice::from_json(&res, "ui/panel.json".to_string())?;
+
+    left_sidebar.set_pos(20.0, 100.0);
+    left_sidebar.set_size(280.0, 400.0);
+    left_sidebar.set_color((190, 215, 115, 0.9));
+
     let left_sidebar_sub = Rectangle::new(&RectangleProps {
         width: 280.0,
         height: 200.0,
@@ -80,7 +95,7 @@ fn run() -> Result<(), failure::Error> {
         color: (0.85, 0.55, 0.25, 0.60),
     })?;
 
-    let left_sidebar_text = font::Text::new(
+    let mut left_sidebar_text = font::Text::new(
         "
     Bacon ipsum dolor amet kielbasa turkey venison buffalo
     filet mignon prosciutto boudin shoulder. Shoulder ground
@@ -109,37 +124,32 @@ fn run() -> Result<(), failure::Error> {
                     viewport.set_used();
 
                     ui_camera.update_viewport(viewport.w, viewport.h);
+                    top_bar.set_width(viewport.w);
+                    main_content.set_width(viewport.w - 340.0);
                 },
                _ => {},
             }
         }
 
-        // TODO: several issues, have to recreate this Rect in the loop every time
-        // just to get the width to change
-        // Rectangle should be able to have properties updated at any point, easily
-        // Also, renderer for batching can still only support Sprite, but should
-        // be able to support Rect or any other Renderable item
-        let top_bar = Rectangle::new(&RectangleProps {
-            width: viewport.w,
-            height: 60.0,
-            pos: (0.0, 0.0),
-            color: (0.65, 0.65, 0.65, 1.0),
-        })?;
-        let main_content = Rectangle::new(&RectangleProps {
-            width: viewport.w - 340.0,
-            height: 600.0,
-            pos: (320.0, 100.0),
-            color: (0.2, 0.2, 0.2, 0.80),
-        })?;
+        // rectangles and text go through the same batch as sprites, in submission order
+        font_renderer.prepare(&mut left_sidebar_text)?;
+        font_renderer.prepare(&mut top_bar_text)?;
 
         renderer.clear();
-        top_bar.render(&ui_camera);
-        left_sidebar.render(&ui_camera);
-        left_sidebar_sub.render(&ui_camera);
-        main_content.render(&ui_camera);
-
-        font_renderer.render(&left_sidebar_text, &ui_camera);
-        font_renderer.render(&top_bar_text, &ui_camera);
+        renderer.begin_scene_target()?;
+        renderer.begin_batch();
+        renderer.submit(&top_bar);
+        renderer.submit(&left_sidebar);
+        renderer.submit(&left_sidebar_sub);
+        renderer.submit(&main_content);
+        // keep the sidebar text inside its panel
+        renderer.push_clip_rect(ClipRect::new(20.0, 100.0, 280.0, 400.0), ClipSpace::Screen);
+        renderer.submit(&left_sidebar_text);
+        renderer.pop_clip_rect();
+        renderer.submit(&top_bar_text);
+        renderer.end_batch();
+        renderer.render(&ui_camera)?;
+        renderer.end_scene()?;
 
         window.gl_swap_window();
     }
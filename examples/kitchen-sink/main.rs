@@ -11,6 +11,12 @@ use yarge::camera::*;
 use yarge::sprite::*;
 use yarge::tilemaps::*;
 use yarge::renderer;
+use yarge::renderer::layers::{Layer, LayerKind};
+use yarge::renderer::render_texture::RenderTexture;
+use yarge::renderer::post_process::PostProcessChain;
+use yarge::postfx;
+use yarge::lighting::{Lighting, PointLight, SpotLight};
+use yarge::particles::Emitter;
 use yarge::textures;
 use yarge::{font, image, debug};
 use yarge::font::FontRenderer;
@@ -34,6 +40,8 @@ fn run() -> Result<(), failure::Error> {
 
     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
     gl_attr.set_context_version(3, 3);
+    // masks drawn straight to the window need a stencil buffer
+    gl_attr.set_stencil_size(8);
 
     let window = video_subsystem
         .window("Yarge | Kitchen Sink", WIDTH, HEIGHT)
@@ -45,7 +53,8 @@ fn run() -> Result<(), failure::Error> {
     let _gl_context = window.gl_create_context().unwrap();
     let _gl = gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void);
     let mut event_pump = sdl.event_pump().unwrap();
-    let mut viewport = Viewport::for_window(WIDTH as f32, HEIGHT as f32);
+    // the scene keeps its window size layout and is letterboxed when the window is resized
+    let viewport = Viewport::with_virtual_resolution(WIDTH as f32, HEIGHT as f32, WIDTH, HEIGHT, ScalingMode::Fit);
 
     let res = Resources::from_relative_path(Path::new("assets")).unwrap();
     let mut texture_manager = textures::TextureManager::new(&res);
@@ -62,6 +71,8 @@ fn run() -> Result<(), failure::Error> {
 
     renderer.set_clear_color(30, 30, 30, 1.0);
 
+    let background_layer = renderer.add_layer(Layer::new("background", LayerKind::Background, 0));
+
     let mut font_renderer = FontRenderer::new(&res, initial_dpi.0 / 100.0)?;
     font_renderer.add_font("dejavu".to_string(), "fonts/dejavu/DejaVuSansMono.ttf");
     font_renderer.add_font("cjk".to_string(), "fonts/wqy-microhei/WenQuanYiMicroHei.ttf");
@@ -76,6 +87,14 @@ fn run() -> Result<(), failure::Error> {
     //lighting_program.set_uniform_1f(uniform_intensity, 0.45);
     //lighting_program.set_uniform_2f(uniform_lightpos, &glm::vec2(0.1, 0.1));
 
+    // built in effects, P toggles the CRT effect
+    let mut post_process = PostProcessChain::new();
+    let mut crt_enabled = false;
+    post_process.add_effect(&postfx::Vignette::default())?;
+    post_process.add_effect(&postfx::Crt::default())?;
+    post_process.set_effect_enabled(postfx::Crt::NAME, crt_enabled);
+    renderer.set_post_process_chain(post_process)?;
+
     let triangle = Triangle::new()?;
     let rect1 = Rectangle::new(&RectangleProps {
         width: 256.0,
@@ -97,7 +116,11 @@ fn run() -> Result<(), failure::Error> {
     })?;
 
     let mut camera = Camera::new(viewport.w, viewport.h, Projection::Ortho)?;
-    let mut ui_camera = Camera::new(viewport.w, viewport.h, Projection::Ortho)?;
+    let ui_camera = Camera::new(viewport.w, viewport.h, Projection::Ortho)?;
+    // a fixed view of the scene in the bottom right corner, drawn over the main camera
+    let mut overview_camera = Camera::new(viewport.w, viewport.h, Projection::Ortho)?;
+
+    overview_camera.set_viewport_rect(ViewportRect::new(0.76, 0.76, 0.22, 0.22));
 
     let mut image2 = image::Image::new(
         &res,
@@ -191,7 +214,7 @@ fn run() -> Result<(), failure::Error> {
 
     let mut timer = Timer::new();
 
-    viewport.set_used();
+    renderer.use_viewport(&viewport);
 
     let mut i = 0;
 
@@ -231,7 +254,7 @@ fn run() -> Result<(), failure::Error> {
     mario_as_sprite.set_texture_scale((scale_ix, scale_iy));
     //mario_as_sprite.set_frame((0.0, 210.0));
 
-    let ninja_as_sprite = Sprite::from_texture(
+    let mut ninja_as_sprite = Sprite::from_texture(
         texture_manager.get("ninja"),
         SpriteProps {
             pos: (400.0, 40., 0.0),
@@ -240,9 +263,36 @@ fn run() -> Result<(), failure::Error> {
             texture_slot: 7
         },
     )?;
+    let mut ninja_rotation = 0.0;
+
+    ninja_as_sprite.set_origin_center();
+    ninja_as_sprite.set_flip_h(true);
 
     let tilemap = Tilemap::from_json(&res, "tilemaps/tilemap_test.json".to_string())?;
 
+    // the tilemap is drawn into an offscreen texture each frame and shown scaled down as a minimap
+    let minimap = RenderTexture::new(renderer.get_backend_mut(), WIDTH, HEIGHT)?;
+    let mut minimap_sprite = Sprite::from_texture(
+        &minimap.as_texture(),
+        SpriteProps {
+            pos: (WIDTH as f32 - 266.0, 10.0, 0.0),
+            dim: (256, 195),
+            ..Default::default()
+        }
+    )?;
+    minimap_sprite.set_texture_scale((4.0, 4.0));
+
+    let ui_layer = renderer.add_layer(Layer::new("ui", LayerKind::UI, 0));
+    renderer.set_layer_camera(&ui_layer, &ui_camera);
+    renderer.set_layer_lit(&ui_layer, false);
+
+    // a torch circling the scene with the ground tiles casting shadows, over a dim ambient light
+    renderer.set_instancing(true);
+    renderer.set_lighting(Lighting::new((0.35, 0.35, 0.45))?)?;
+    let ground_occluders = tilemap.solid_occluders("ground");
+    let mut torch_angle: f32 = 0.0;
+    let mut sparks = Emitter::from_json(&res, "particles/sparks.json", 1)?;
+
     let my_text = font::Text::new(
         "Hello OpenGL".to_string(),
         font::TextSettings {
@@ -274,11 +324,8 @@ fn run() -> Result<(), failure::Error> {
                     win_event: sdl2::event::WindowEvent::Resized(w, h),
                     ..
                 } => {
-                    viewport.update_size(w as f32, h as f32);
-                    viewport.set_used();
-
-                    camera.update_viewport(viewport.w, viewport.h);
-                    ui_camera.update_viewport(viewport.w, viewport.h);
+                    // the cameras stay at the virtual resolution, only the letterbox changes
+                    renderer.resize(w as f32, h as f32);
                 },
                 sdl2::event::Event::KeyDown { keycode, .. } => {
                     let dt = timer.delta_time();
@@ -312,6 +359,12 @@ fn run() -> Result<(), failure::Error> {
                         Some(sdl2::keyboard::Keycode::R) => {
                             camera.set_position(0.0, 0.0, 0.0);
                         },
+                        Some(sdl2::keyboard::Keycode::P) => {
+                            crt_enabled = !crt_enabled;
+                            if let Some(post_process) = renderer.get_post_process_chain_mut() {
+                                post_process.set_effect_enabled(postfx::Crt::NAME, crt_enabled);
+                            }
+                        },
                         _ => break,
                     }
                 },
@@ -326,9 +379,8 @@ fn run() -> Result<(), failure::Error> {
         //camera.set_position(tx, ty, 0.0);
         // render window contents here
         //camera.look_at((ipx, ipy, 0.0));
-        viewport.set_used();
 
-        triangle.render(); // not rendered because renderer.render calls clear... is that clear though?
+        triangle.render()?; // not rendered because renderer.render calls clear... is that clear though?
         renderer.clear();
 
         let delta_time = timer.delta_time();
@@ -339,9 +391,9 @@ fn run() -> Result<(), failure::Error> {
             image3.set_color((0, 0, 255, 1.0));
         }
 
-        rect1.render(&camera);
-        rect2.render(&camera);
-        rect3.render(&camera);
+        rect1.render(&camera)?;
+        rect2.render(&camera)?;
+        rect3.render(&camera)?;
         image.render(&camera);
         image2.render(&camera);
         spritesheet.render(&camera);
@@ -355,7 +407,12 @@ fn run() -> Result<(), failure::Error> {
 
         if i >= sprite_frames.len() - 1 { i = 0; }
 
-        renderer.begin_scene(&camera);
+        renderer.begin_batch();
+        renderer.submit_to_layer(&background_layer, &tilemap);
+        renderer.end_batch();
+        renderer.render_to_texture(&minimap, &camera)?;
+
+        renderer.begin_scene_target()?;
         renderer.begin_batch();
 
         for s in &vbs {
@@ -365,14 +422,47 @@ fn run() -> Result<(), failure::Error> {
         renderer.submit(&some_sprite);
         renderer.submit(&some_other_sprite);
         renderer.submit(&mario_as_sprite);
+        // half a radian a second, delta_time is in milliseconds
+        ninja_rotation += 0.5 * delta_time / 1000.0;
+        ninja_as_sprite.set_rotation(ninja_rotation);
         renderer.submit(&ninja_as_sprite);
         renderer.submit(&spritesheet_as_sprite);
+        renderer.draw_rounded_rect_outline((380.0, 20.0), (160.0, 90.0), 12.0, 3.0, (0.9, 0.9, 0.2, 1.0));
+        renderer.draw_circle((460.0, 65.0), 20.0, (0.2, 0.6, 0.9, 0.8));
+        renderer.draw_line((400.0, 95.0), (520.0, 35.0), 2.0, (0.9, 0.3, 0.3, 1.0));
+
+        renderer.submit_to_layer(&background_layer, &tilemap);
+        renderer.submit_to_layer(&ui_layer, &minimap_sprite);
+
+        torch_angle += 0.001 * delta_time;
+        let torch_pos = (WIDTH as f32 / 2.0 + torch_angle.cos() * 300.0, HEIGHT as f32 / 2.0 + torch_angle.sin() * 200.0);
+        renderer.submit_light(PointLight {
+            pos: torch_pos,
+            color: (1.0, 0.8, 0.5),
+            radius: 350.0,
+            ..Default::default()
+        });
+        renderer.submit_light(SpotLight {
+            pos: (100.0, 100.0),
+            direction: std::f32::consts::FRAC_PI_4,
+            color: (0.5, 0.7, 1.0),
+            radius: 600.0,
+            ..Default::default()
+        });
+        renderer.submit_occluders(ground_occluders.iter().cloned());
 
-        renderer.submit(&tilemap);
+        // the torch throws sparks, delta_time is in milliseconds
+        sparks.set_pos(torch_pos.0, torch_pos.1);
+        sparks.update(delta_time / 1000.0);
+        renderer.submit_with_blend(&sparks, sparks.get_blend_mode());
 
         renderer.end_batch();
-        renderer.render(&camera);
-        renderer.end_scene();
+        renderer.render(&camera)?;
+        // the ui is already on screen, the overview only shows the world
+        renderer.set_layer_visible(&ui_layer, false);
+        renderer.render(&overview_camera)?;
+        renderer.set_layer_visible(&ui_layer, true);
+        renderer.end_scene()?;
 
        let jp_text = font::Text::new(
            "こんにちは　世界".to_string(),
@@ -385,13 +475,15 @@ fn run() -> Result<(), failure::Error> {
            }
        );
 
-        font_renderer.render(&my_text, &ui_camera);
-        font_renderer.render(&my_text_b, &ui_camera);
-        font_renderer.render(&jp_text, &ui_camera);
+        font_renderer.render(&my_text, &ui_camera)?;
+        font_renderer.render(&my_text_b, &ui_camera)?;
+        font_renderer.render(&jp_text, &ui_camera)?;
 
         window.gl_swap_window();
     }
 
+    minimap.delete(renderer.get_backend_mut());
+
     Ok(())
 }
 
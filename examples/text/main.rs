@@ -122,9 +122,9 @@ Use the keyboard to input new text dynamically
 
         renderer.clear();
 
-        font_renderer.render(&instruction_text, &ui_camera);
-        font_renderer.render(&jp_text, &ui_camera);
-        font_renderer.render(&dynamic_text, &ui_camera);
+        font_renderer.render(&instruction_text, &ui_camera)?;
+        font_renderer.render(&jp_text, &ui_camera)?;
+        font_renderer.render(&dynamic_text, &ui_camera)?;
 
         window.gl_swap_window();
     }
@@ -21,10 +21,15 @@ fn generate_impl(ast: &syn::DeriveInput) -> quote::Tokens {
 
     quote! {
         impl #ident #generics #where_clause {
-            #[allow(unused_variables)]
             pub fn vertex_attrib_pointers() {
+                Self::vertex_attrib_pointers_at(0);
+            }
+
+            /// Same as `vertex_attrib_pointers` for vertices starting `base_offset` bytes into the buffer
+            #[allow(unused_variables)]
+            pub fn vertex_attrib_pointers_at(base_offset: usize) {
                 let stride = ::std::mem::size_of::<Self>();
-                let offset = 0;
+                let offset = base_offset;
 
                 #(#fields_vertex_attrib_pointer)*
             }
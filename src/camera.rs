@@ -1,8 +1,48 @@
+#[derive(Clone, Debug)]
 pub enum Projection {
     Ortho,
     Perspective,
 }
 
+/// Part of the screen (or render target) a camera draws into, from 0 to 1 with (0, 0) at the
+/// top left. Cameras with different rects can render the same scene for split-screen or minimaps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for ViewportRect {
+    fn default() -> ViewportRect {
+        ViewportRect::full()
+    }
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> ViewportRect {
+        ViewportRect { x, y, width, height }
+    }
+
+    pub fn full() -> ViewportRect {
+        ViewportRect::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// The rect inside the `glViewport` style (x, y, width, height) `target`, y going up from the bottom.
+    /// Edges are rounded on their own so rects that share an edge don't overlap or leave a gap
+    pub fn to_pixels(&self, target: [i32; 4]) -> [i32; 4] {
+        let [x, y, width, height] = target;
+        let to_x = |fraction: f32| x + (fraction * width as f32).round() as i32;
+        let to_y = |fraction: f32| y + height - (fraction * height as f32).round() as i32;
+        let (left, right) = (to_x(self.x), to_x(self.x + self.width));
+        let (bottom, top) = (to_y(self.y + self.height), to_y(self.y));
+
+        [left, bottom, right - left, top - bottom]
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     projection: glm::TMat4<f32>,
     view: glm::TMat4<f32>,
@@ -10,6 +50,7 @@ pub struct Camera {
     height: f32,
     kind: Projection,
     pos: glm::Vec3,
+    viewport: ViewportRect,
 }
 
 impl Camera {
@@ -25,6 +66,7 @@ impl Camera {
             height,
             kind,
             pos,
+            viewport: ViewportRect::full(),
         })
     }
 
@@ -44,6 +86,16 @@ impl Camera {
         (self.width, self.height)
     }
 
+    pub fn get_viewport_rect(&self) -> ViewportRect {
+        self.viewport
+    }
+
+    /// Where `Renderer2D::render` draws this camera's view, the whole screen by default.
+    /// The view is stretched to fit, so keep the camera's dimensions in the same aspect ratio
+    pub fn set_viewport_rect(&mut self, viewport: ViewportRect) {
+        self.viewport = viewport;
+    }
+
     pub fn get_position(&self) -> glm::Vec3 {
         self.pos
     }
@@ -95,3 +147,25 @@ impl Camera {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viewport_rect_is_measured_from_the_top_left() {
+        let top_right = ViewportRect::new(0.75, 0.0, 0.25, 0.25);
+
+        assert_eq!(ViewportRect::full().to_pixels([0, 0, 800, 600]), [0, 0, 800, 600]);
+        assert_eq!(top_right.to_pixels([0, 0, 800, 600]), [600, 450, 200, 150]);
+        assert_eq!(top_right.to_pixels([10, 20, 800, 600]), [610, 470, 200, 150]);
+    }
+
+    #[test]
+    fn split_viewports_share_their_edge() {
+        let left = ViewportRect::new(0.0, 0.0, 1.0 / 3.0, 1.0).to_pixels([0, 0, 100, 10]);
+        let right = ViewportRect::new(1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0).to_pixels([0, 0, 100, 10]);
+
+        assert_eq!(left[0] + left[2], right[0]);
+        assert_eq!(right[0] + right[2], 100);
+    }
+}
@@ -36,7 +36,7 @@ uniform sampler2D GlyphTexture;
 out vec4 Color;
 
 void main() {
-    Color = IN.Color * vec4(1.0, 1.0, 1.0, texture(GlyphTexture, IN.TexCoords).r);
+    Color = IN.Color * vec4(1.0, 1.0, 1.0, texture(GlyphTexture, IN.TexCoords).a);
     //Color = texture2D(GlyphTexture, IN.TexCoords) * vec4(IN.Color.x, IN.Color.y, IN.Color.z, IN.Color.w);
     //float alpha = texture(GlyphTexture, IN.TexCoords).r;
 
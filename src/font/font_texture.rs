@@ -1,8 +1,14 @@
+use crate::renderer::backend::{RenderBackend};
+
+use super::{Error};
+
 #[derive(Debug)]
 pub struct FontTexture {
     texture_handle: gl::types::GLuint,
     pub width: u32,
     pub height: u32,
+    // created through a `RenderBackend`, which glyphs are then uploaded through and which owns it
+    in_backend: bool,
 }
 
 #[derive(Debug)]
@@ -22,14 +28,39 @@ impl FontTexture {
             texture_handle,
             width: cache_width,
             height: cache_height,
+            in_backend: false,
         }
     }
 
-    //pub fn get_texture_handle(&self) -> gl::types::GLuint {
-    //    self.texture_handle
-    //}
+    /// Isn't freed when dropped, the backend owns it until it's given back with `delete`
+    pub fn with_backend(cache_width: u32, cache_height: u32, backend: &mut dyn RenderBackend) -> FontTexture {
+        let pixels = vec![0; (cache_width * cache_height * 4) as usize];
+
+        FontTexture {
+            texture_handle: backend.create_texture(cache_width, cache_height, &pixels),
+            width: cache_width,
+            height: cache_height,
+            in_backend: true,
+        }
+    }
+
+    pub fn get_texture_handle(&self) -> gl::types::GLuint {
+        self.texture_handle
+    }
+
+    /// Glyphs are stored white with their coverage as alpha, so the batch renderer can tint them
+    /// like any other texture. Textures made `with_backend` need that `backend` to upload them
+    pub fn update(&self, glyph: &GlyphTexture, backend: Option<&mut dyn RenderBackend>) -> Result<(), Error> {
+        let pixels = coverage_to_rgba(glyph.data);
+
+        if self.in_backend {
+            let backend = backend.ok_or(Error::NoBackend)?;
+
+            backend.update_texture(self.texture_handle, (glyph.left, glyph.bottom), (glyph.width, glyph.height), &pixels);
+
+            return Ok(());
+        }
 
-    pub fn update(&self, glyph: &GlyphTexture) {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.texture_handle);
 
@@ -41,13 +72,15 @@ impl FontTexture {
                 glyph.bottom as i32,// GLint yoffset,
                 glyph.width as i32,// GLsizei width,
                 glyph.height as i32,// GLsizei height,
-                gl::RED,// GLenum format, // gl::ALPHA may also work depending on shader
+                gl::RGBA,// GLenum format,
                 gl::UNSIGNED_BYTE,//?// GLenum type,
-                glyph.data.as_ptr() as *const gl::types::GLvoid,
+                pixels.as_ptr() as *const gl::types::GLvoid,
             );
 
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
+
+        Ok(())
     }
 
     //pub fn bind(&self) {
@@ -68,6 +101,14 @@ impl FontTexture {
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
     }
+
+    /// Frees a texture made `with_backend` through the backend it was made with,
+    /// gl textures are freed like when they are dropped
+    pub fn delete(self, backend: &mut dyn RenderBackend) {
+        if self.in_backend {
+            backend.delete_texture(self.texture_handle);
+        }
+    }
 }
 
 fn create_font_texture(cache_width: u32, cache_height: u32) -> gl::types::GLuint {
@@ -76,9 +117,6 @@ fn create_font_texture(cache_width: u32, cache_height: u32) -> gl::types::GLuint
     unsafe {
         gl::ActiveTexture(gl::TEXTURE0);
 
-        gl::Enable(gl::BLEND);
-        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-
         gl::GenTextures(1, &mut texture_handle);
         gl::BindTexture(gl::TEXTURE_2D, texture_handle);
 
@@ -106,11 +144,54 @@ fn create_font_texture(cache_width: u32, cache_height: u32) -> gl::types::GLuint
     texture_handle
 }
 
+fn coverage_to_rgba(coverage: &[u8]) -> Vec<u8> {
+    coverage.iter()
+        .flat_map(|&alpha| [255, 255, 255, alpha])
+        .collect()
+}
+
 impl Drop for FontTexture {
     fn drop(&mut self) {
+        if self.in_backend {
+            return;
+        }
+
         unsafe {
             gl::DeleteTextures(1, &self.texture_handle);
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::backend::{SoftwareBackend};
+
+    #[test]
+    fn coverage_is_stored_as_white_alpha() {
+        assert_eq!(coverage_to_rgba(&[0, 128]), vec![255, 255, 255, 0, 255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn backend_glyphs_need_their_backend() {
+        let mut backend = SoftwareBackend::new(1, 1);
+        let texture = FontTexture::with_backend(2, 2, &mut backend);
+        let glyph = GlyphTexture { left: 1, bottom: 0, width: 1, height: 1, data: &[200] };
+
+        assert!(matches!(texture.update(&glyph, None), Err(Error::NoBackend)));
+
+        texture.update(&glyph, Some(&mut backend)).unwrap();
+        backend.set_render_target(Some(texture.get_texture_handle()));
+
+        assert_eq!(backend.read_pixels().get_pixel(1, 0).0, [255, 255, 255, 200]);
+        backend.set_render_target(None);
+
+        let texture_handle = texture.get_texture_handle();
+
+        texture.delete(&mut backend);
+        backend.set_render_target(Some(texture_handle));
+
+        // a target that's gone falls back to the backend's own one
+        assert_eq!(backend.read_pixels().dimensions(), (1, 1));
+    }
+}
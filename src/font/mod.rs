@@ -11,6 +11,9 @@ use std::collections::HashMap;
 use crate::helpers::{self, data, buffer};
 use crate::resources::{Resources};
 use crate::camera::{Camera};
+use crate::renderer::backend::{RenderBackend};
+use crate::renderer::blend_mode::{BlendMode};
+use crate::renderer::renderable::{RenderVertex};
 
 use font_shaders::{VERTEX_SOURCE, FRAGMENT_SOURCE};
 use layout::{basic_layout};
@@ -18,7 +21,7 @@ use font_texture::{FontTexture, GlyphTexture};
 pub use text::{Text, TextSettings};
 
 #[derive(VertexAttribPointers)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 #[repr(C, packed)]
 pub struct GlyphVertex {
     #[location=0]
@@ -29,20 +32,52 @@ pub struct GlyphVertex {
     color: data::f32_f32_f32_f32,
 }
 
+impl RenderVertex for GlyphVertex {
+    fn position(&self) -> data::f32_f32_f32 {
+        self.pos
+    }
+    fn uv(&self) -> data::f32_f32 {
+        self.tex
+    }
+    fn color(&self) -> data::f32_f32_f32_f32 {
+        self.color
+    }
+    fn texture_scale(&self) -> data::f32_f32_f32 {
+        (1.0, 1.0, 1.0).into()
+    }
+}
+
 pub type GlyphVertices = Vec<GlyphVertex>;
 
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="No font {:?} was added to the FontRenderer", font)]
+    FontNotFound {
+        font: String,
+    },
+    #[fail(display="A FontRenderer made with_backend has no gl program, its texts are submitted to Renderer2D instead of rendered")]
+    NoGlPipeline,
+    #[fail(display="Glyphs of a FontRenderer made with_backend are uploaded through its RenderBackend, texts are prepared with prepare_with_backend")]
+    NoBackend,
+}
+
 pub struct FontRenderer<'a> {
     res: &'a Resources,
     pub fonts: HashMap<String, Font<'a>>,
     pub cache: Cache<'a>,
     scale_factor: f32,
+    pipeline: Option<GlyphPipeline>,
+    texture: FontTexture,
+    vertices: GlyphVertices,
+    indices: Vec<[i32; 6]>,
+}
+
+// the gl program and buffers `render` draws with, a FontRenderer made `with_backend` doesn't have them
+struct GlyphPipeline {
     program: helpers::Program,
-    vbo: buffer::DynamicArrayBuffer,
+    vbo: buffer::StreamArrayBuffer,
     vao: buffer::VertexArray,
     ibo: buffer::ElementArrayBuffer,
-    texture: FontTexture,
-    vertices: Vec<GlyphVertices>,
-    indices: Vec<[i32; 6]>,
     uniforms: HashMap<String, i32>,
 }
 
@@ -59,15 +94,7 @@ impl fmt::Debug for FontRenderer<'_> {
 
 impl<'a> FontRenderer<'a> {
     pub fn new(res: &'a Resources, display_dpi: f32) -> Result<FontRenderer, failure::Error> {
-        let scale_factor = display_dpi;
-        // TODO: research Signed Distance Field
-        let (cache_width, cache_height) = (
-            (512.0 * scale_factor) as u32,
-            (512.0 * scale_factor) as u32,
-        );
-        let cache = Cache::builder()
-            .dimensions(cache_width, cache_height)
-            .build();
+        let (cache_width, cache_height) = cache_dimensions(display_dpi);
         let shaders = vec![
             helpers::Shader::from_raw(&VERTEX_SOURCE, gl::VERTEX_SHADER)?,
             helpers::Shader::from_raw(&FRAGMENT_SOURCE, gl::FRAGMENT_SHADER)?,
@@ -76,19 +103,18 @@ impl<'a> FontRenderer<'a> {
             .expect("Failed to create Font Shader Program");
         let uniform_texture = program.get_uniform_location("GlyphTexture")?;
         let uniform_mvp = program.get_uniform_location("MVP")?;
-        let max_buffer_size = ((::std::mem::size_of::<GlyphVertex>()) * 4000) as gl::types::GLsizeiptr;
         let max_glyphs = 1000;
+        // room for a few texts in flight at once
+        let max_buffer_size = ::std::mem::size_of::<GlyphVertex>() * max_glyphs * 4 * 3;
 
-        let vbo = buffer::DynamicArrayBuffer::new(max_buffer_size);
+        let vbo = buffer::StreamArrayBuffer::new(max_buffer_size, buffer::StreamMode::detect());
         let vao = buffer::VertexArray::new();
         let ibo = buffer::ElementArrayBuffer::new();
 
         let indices = generate_batch_indices(max_glyphs);
 
-        vbo.bind();
-        vbo.set_buffer_data();
-
         vao.bind();
+        vbo.bind();
 
         GlyphVertex::vertex_attrib_pointers();
 
@@ -99,23 +125,58 @@ impl<'a> FontRenderer<'a> {
         vbo.unbind();
         vao.unbind();
 
-        Ok(FontRenderer {
-            res,
-            scale_factor: scale_factor as f32,
-            cache,
+        let pipeline = GlyphPipeline {
             program,
-            fonts: HashMap::new(),
             vbo,
             vao,
             ibo,
-            vertices: Vec::new(),
-            indices,
-            texture: FontTexture::new(cache_width, cache_height),
             uniforms: vec![
                 ("texture".to_owned(), uniform_texture),
                 ("mvp".to_owned(), uniform_mvp),
             ].into_iter().collect(),
-        })
+        };
+
+        Ok(FontRenderer::with_texture(
+            res,
+            display_dpi,
+            Some(pipeline),
+            FontTexture::new(cache_width, cache_height),
+            indices,
+        ))
+    }
+
+    /// Keeps its glyph atlas in `backend` instead of a gl texture, for texts submitted to a `Renderer2D`
+    /// made `with_backend`. Texts are prepared with `prepare_with_backend`, `prepare` and `render` fail.
+    /// The atlas isn't freed when the FontRenderer is dropped, it has to be given back with `delete`
+    pub fn with_backend(res: &'a Resources, display_dpi: f32, backend: &mut dyn RenderBackend) -> FontRenderer<'a> {
+        let (cache_width, cache_height) = cache_dimensions(display_dpi);
+        let texture = FontTexture::with_backend(cache_width, cache_height, backend);
+
+        FontRenderer::with_texture(res, display_dpi, None, texture, Vec::new())
+    }
+
+    fn with_texture(
+        res: &'a Resources,
+        display_dpi: f32,
+        pipeline: Option<GlyphPipeline>,
+        texture: FontTexture,
+        indices: Vec<[i32; 6]>,
+    ) -> FontRenderer<'a> {
+        // TODO: research Signed Distance Field
+        let cache = Cache::builder()
+            .dimensions(texture.width, texture.height)
+            .build();
+
+        FontRenderer {
+            res,
+            scale_factor: display_dpi,
+            cache,
+            fonts: HashMap::new(),
+            pipeline,
+            vertices: Vec::new(),
+            indices,
+            texture,
+        }
     }
 
     pub fn add_font(&mut self, font_name: String, font_path: &str) {
@@ -143,9 +204,105 @@ impl<'a> FontRenderer<'a> {
         self.scale_factor = display_dpi;
     }
 
-    pub fn render(&mut self, text: &Text, camera: &Camera) {
+    /// The glyph atlas texture, glyphs are white with their coverage in the alpha channel
+    pub fn get_texture_handle(&self) -> gl::types::GLuint {
+        self.texture.get_texture_handle()
+    }
+
+    /// Frees the glyph atlas of a FontRenderer made `with_backend` through that backend,
+    /// one made with `new` frees its own when it's dropped
+    pub fn delete(self, backend: &mut dyn RenderBackend) {
+        self.texture.delete(backend);
+    }
+
+    /// Lays out `text` and caches its glyphs so it can be submitted to `Renderer2D` like a sprite.
+    /// Has to be called again whenever the text or its settings change, and since glyphs can move
+    /// around in the cache, every frame before it's submitted. With `BatchMode::TextureArray` the
+    /// atlas changes whenever new glyphs are cached, so `Renderer2D::invalidate_texture` has to be
+    /// called with `get_texture_handle` after preparing. Fails for a FontRenderer made `with_backend`
+    pub fn prepare(&mut self, text: &mut Text) -> Result<(), failure::Error> {
+        text.glyphs = self.layout_glyphs(text, None)?;
+        text.texture = self.texture.get_texture_handle();
+
+        Ok(())
+    }
+
+    /// `prepare` for a FontRenderer made `with_backend`, newly cached glyphs are uploaded through `backend`
+    pub fn prepare_with_backend(&mut self, text: &mut Text, backend: &mut dyn RenderBackend) -> Result<(), failure::Error> {
+        text.glyphs = self.layout_glyphs(text, Some(backend))?;
+        text.texture = self.texture.get_texture_handle();
+
+        Ok(())
+    }
+
+    /// Draws `text` straight away, fails without drawing when it has more glyphs than the vertex buffer holds.
+    /// Only a FontRenderer made with `new` can, `with_backend` ones fail with `Error::NoGlPipeline`
+    pub fn render(&mut self, text: &Text, camera: &Camera) -> Result<(), failure::Error> {
+        if self.pipeline.is_none() {
+            return Err(Error::NoGlPipeline.into());
+        }
+
+        let vertices = self.layout_glyphs(text, None)?;
+
+        // nothing was uploaded, drawing would read whatever is left in the buffer
+        let pipeline = match self.pipeline.as_mut() {
+            Some(pipeline) if !vertices.is_empty() => pipeline,
+            _ => return Ok(()),
+        };
+
+        pipeline.vbo.bind();
+        let base_vertex = pipeline.vbo.upload(&vertices)? / ::std::mem::size_of::<GlyphVertex>();
+
+        let glyph_count = vertices.len() / 4;
+
+        // the indices only depend on the glyph count, so they're uploaded again only when it grows
+        if glyph_count > self.indices.len() {
+            self.indices = generate_batch_indices(glyph_count);
+            pipeline.ibo.bind();
+            pipeline.ibo.static_draw_data(&self.indices);
+            pipeline.ibo.unbind();
+        }
+
+        self.vertices = vertices;
+
+        self.texture.unbind();
+
+        self.texture.bind_to_unit(0);
+
+        let mvp = camera.get_projection() * camera.get_view();
+
+        BlendMode::Alpha.apply();
+        pipeline.program.set_used();
+        pipeline.program.set_uniform_1i(*pipeline.uniforms.get("texture").unwrap(), 0);
+        pipeline.program.set_uniform_mat4f(*pipeline.uniforms.get("mvp").unwrap(), &mvp);
+
+        // the element buffer isn't part of the vao, so it's bound after it
+        pipeline.vao.bind();
+        pipeline.ibo.bind();
+
+        unsafe {
+            gl::DrawElementsBaseVertex(
+                gl::TRIANGLES,
+                glyph_count as i32 * 6,
+                gl::UNSIGNED_INT,
+                ::std::ptr::null(),
+                base_vertex as i32,
+            );
+        }
+
+        pipeline.vbo.fence();
+        pipeline.vao.unbind();
+        pipeline.ibo.unbind();
+        pipeline.vbo.unbind();
+
+        Ok(())
+    }
+
+    // lays out and caches the glyphs of `text`, four vertices per glyph in the batch renderer's quad order.
+    // Stops uploading glyphs at the first one that can't be
+    fn layout_glyphs(&mut self, text: &Text, mut backend: Option<&mut dyn RenderBackend>) -> Result<Vec<GlyphVertex>, failure::Error> {
         let font = self.fonts.get(&text.settings.font)
-            .unwrap_or_else(|| panic!("No Font {:#?} Found", text.settings.font));
+            .ok_or_else(|| Error::FontNotFound { font: text.settings.font.clone() })?;
         let text_color = (
             text.settings.color.0 as f32 / 255.0,
             text.settings.color.1 as f32 / 255.0,
@@ -168,6 +325,7 @@ impl<'a> FontRenderer<'a> {
         }
 
         let font_texture = &self.texture;
+        let mut uploaded = Ok(());
         self.cache.cache_queued(|rect, data| {
             let glyph_texture = GlyphTexture {
                 left: rect.min.x,
@@ -177,15 +335,18 @@ impl<'a> FontRenderer<'a> {
                 data,
             };
 
-            font_texture.update(&glyph_texture);
-        }).unwrap();
+            if uploaded.is_ok() {
+                uploaded = font_texture.update(&glyph_texture, backend.as_mut().map(|backend| &mut **backend as &mut dyn RenderBackend));
+            }
+        })?;
+        uploaded?;
 
         let origin = point(0.0, 0.0);
         let (text_offset_x, text_offset_y) = text.settings.pos;
-        let vertices: Vec<Vec<GlyphVertex>> = glyphs
+        let vertices = glyphs
             .iter()
             .filter_map(|g| self.cache.rect_for(0 /* font_id */, g).ok().unwrap())
-            .map(|(uv_rect, screen_rect)| {
+            .flat_map(|(uv_rect, screen_rect)| {
                 let gl_rect = Rect {
                     min: origin + vector(
                         screen_rect.min.x as f32 + text_offset_x as f32,
@@ -226,58 +387,19 @@ impl<'a> FontRenderer<'a> {
             })
             .collect();
 
-        self.vbo.bind();
-        self.vbo.reset_buffer_offset();
-
-        if !vertices.is_empty() {
-            for v in &vertices {
-                self.vbo.upload_draw_data(v);
-                self.vbo.set_buffer_offset(self.vbo.buffer_offset + ((::std::mem::size_of::<GlyphVertex>()) * 4) as isize);
-            }
-        }
-
-        if !vertices.is_empty() {
-            self.vertices = vertices;
-            self.indices = generate_batch_indices(self.vertices.len());
-            self.ibo.bind();
-            self.ibo.static_draw_data(&self.indices);
-            self.ibo.unbind();
-        }
-
-        self.texture.unbind();
-
-        self.texture.bind_to_unit(0);
-
-        let mvp = camera.get_projection() * camera.get_view();
-
-        self.program.set_used();
-        self.program.set_uniform_1i(*self.uniforms.get("texture").unwrap(), 0);
-        self.program.set_uniform_mat4f(*self.uniforms.get("mvp").unwrap(), &mvp);
-
-        self.ibo.bind();
-        self.vao.bind();
-
-        unsafe {
-            gl::DrawElements(
-                gl::TRIANGLES,
-                self.indices.len() as i32 * 6,
-                gl::UNSIGNED_INT,
-                self.indices.as_ptr() as *const gl::types::GLvoid,
-            );
-        }
-
-        self.vao.unbind();
-        self.ibo.unbind();
-
-        self.vbo.reset_buffer_offset();
-        self.vbo.unbind();
+        Ok(vertices)
     }
 
+
     pub fn cache_scale_tolerance(&self) -> f32 {
         self.cache.scale_tolerance()
     }
 }
 
+fn cache_dimensions(display_dpi: f32) -> (u32, u32) {
+    ((512.0 * display_dpi) as u32, (512.0 * display_dpi) as u32)
+}
+
 // TODO: probably temporary, just trying to get some text on screen
 fn generate_batch_indices(vertices_len: usize) -> Vec<[i32; 6]> {
     let mut offset: i32 = 0;
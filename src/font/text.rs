@@ -1,5 +1,8 @@
 use rusttype::{Scale};
 
+use crate::renderer::renderable::{Renderable2D, VertexSink};
+use super::GlyphVertex;
+
 pub struct FontSize {
     pub scale: Scale
 }
@@ -39,6 +42,8 @@ impl Default for TextSettings {
 pub struct Text {
     pub text: String,
     pub settings: TextSettings,
+    pub(super) glyphs: Vec<GlyphVertex>,
+    pub(super) texture: u32,
 }
 
 impl Default for Text {
@@ -46,6 +51,8 @@ impl Default for Text {
         Self {
             text: "".to_string(),
             settings: TextSettings { ..Default::default() },
+            glyphs: Vec::new(),
+            texture: 0,
         }
     }
 }
@@ -54,7 +61,20 @@ impl Text {
     pub fn new(text: String, settings: TextSettings) -> Text {
         Text {
             text,
-            settings
+            settings,
+            glyphs: Vec::new(),
+            texture: 0,
         }
     }
 }
+
+/// Draws the glyphs from the last `FontRenderer::prepare`, nothing until it has been prepared
+impl Renderable2D for Text {
+    fn texture(&self) -> u32 {
+        self.texture
+    }
+
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&self.glyphs);
+    }
+}
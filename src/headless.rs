@@ -0,0 +1,192 @@
+use image::{RgbaImage};
+use khronos_egl as egl;
+
+use crate::helpers::buffer::{self, FrameBuffer};
+use crate::textures::snapshot;
+
+/// EGL_PLATFORM_SURFACELESS_MESA, lets Mesa (e.g. llvmpipe) create a display without a window system
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="Could not load libEGL: {}", message)]
+    LoadFailed { message: String },
+    #[fail(display="EGL call {} failed: {}", call, message)]
+    EglCallFailed { call: &'static str, message: String },
+    #[fail(display="No EGL config supports an OpenGL 3.3 context")]
+    NoConfig,
+}
+
+fn egl_error(call: &'static str) -> impl Fn(egl::Error) -> Error {
+    move |error| Error::EglCallFailed { call, message: error.to_string() }
+}
+
+/// An OpenGL 3.3 context with no window, rendering into an offscreen framebuffer instead.
+/// While it's alive everything that would draw to the window (`Renderer2D::render`, post processing,
+/// direct draws) goes to the framebuffer, which can be read back with `read_pixels`
+pub struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context,
+    frame_buffer: Option<FrameBuffer>,
+    width: u32,
+    height: u32,
+}
+
+impl std::fmt::Debug for HeadlessContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeadlessContext")
+            .field("frame_buffer", &self.frame_buffer)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish()
+    }
+}
+
+impl HeadlessContext {
+    /// Creates a surfaceless EGL context, loads the GL functions and makes it current on this thread
+    pub fn new(width: u32, height: u32) -> Result<HeadlessContext, failure::Error> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+            .map_err(|error| Error::LoadFailed { message: error.to_string() })?;
+        let display = egl.get_platform_display(PLATFORM_SURFACELESS_MESA, std::ptr::null_mut(), &[egl::ATTRIB_NONE])
+            .map_err(egl_error("eglGetPlatformDisplay"))?;
+
+        egl.initialize(display).map_err(egl_error("eglInitialize"))?;
+
+        let config_attributes = [
+            egl::SURFACE_TYPE, egl::PBUFFER_BIT,
+            egl::RENDERABLE_TYPE, egl::OPENGL_BIT,
+            egl::RED_SIZE, 8,
+            egl::GREEN_SIZE, 8,
+            egl::BLUE_SIZE, 8,
+            egl::ALPHA_SIZE, 8,
+            egl::NONE,
+        ];
+        let config = egl.choose_first_config(display, &config_attributes)
+            .map_err(egl_error("eglChooseConfig"))?
+            .ok_or(Error::NoConfig)?;
+
+        egl.bind_api(egl::OPENGL_API).map_err(egl_error("eglBindAPI"))?;
+
+        let context_attributes = [
+            egl::CONTEXT_MAJOR_VERSION, 3,
+            egl::CONTEXT_MINOR_VERSION, 3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ];
+        let context = egl.create_context(display, config, None, &context_attributes)
+            .map_err(egl_error("eglCreateContext"))?;
+
+        egl.make_current(display, None, None, Some(context)).map_err(egl_error("eglMakeCurrent"))?;
+
+        gl::load_with(|name| {
+            egl.get_proc_address(name)
+                .map_or(std::ptr::null(), |function| function as *const std::os::raw::c_void)
+        });
+
+        let frame_buffer = FrameBuffer::new(width, height)?;
+        buffer::set_default_framebuffer(frame_buffer.get_handle());
+        frame_buffer.bind();
+
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+        }
+
+        Ok(HeadlessContext {
+            egl,
+            display,
+            context,
+            frame_buffer: Some(frame_buffer),
+            width,
+            height,
+        })
+    }
+
+    pub fn get_dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if let Some(frame_buffer) = &self.frame_buffer {
+            frame_buffer.set_size(width, height);
+        }
+
+        self.width = width;
+        self.height = height;
+
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+        }
+    }
+
+    /// Everything rendered so far, top row first like a screenshot of a window
+    pub fn read_pixels(&self) -> RgbaImage {
+        if let Some(frame_buffer) = &self.frame_buffer {
+            frame_buffer.bind();
+        }
+
+        unsafe {
+            gl::Finish();
+        }
+
+        snapshot::read_pixels(0, 0, self.width, self.height)
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        // GL objects have to go before the context does
+        self.frame_buffer = None;
+        buffer::set_default_framebuffer(0);
+
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::renderer::Renderer2D;
+
+    // skipped on machines where EGL can't make a context
+    #[test]
+    fn can_read_back_clear_color() {
+        let context = match HeadlessContext::new(4, 2) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+
+        unsafe {
+            gl::ClearColor(1.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        let pixels = context.read_pixels();
+
+        assert_eq!(pixels.dimensions(), (4, 2));
+        assert!(pixels.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn renderer_draws_into_headless_target() {
+        let context = match HeadlessContext::new(8, 8) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(8.0, 8.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::new().unwrap();
+
+        renderer.set_clear_color(0, 0, 255, 1.0);
+        renderer.clear();
+        renderer.begin_scene_target().unwrap();
+        renderer.begin_batch();
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+        renderer.end_scene().unwrap();
+
+        assert!(context.read_pixels().pixels().all(|pixel| pixel.0 == [0, 0, 255, 255]));
+    }
+}
@@ -1,3 +1,8 @@
+use std::cell::{Cell};
+use std::collections::{VecDeque};
+
+use super::system::{SystemInfo};
+
 pub trait BufferType {
     const BUFFER_TYPE: gl::types::GLuint;
 }
@@ -16,6 +21,8 @@ pub type ArrayBuffer = Buffer<BufferTypeArray>;
 pub type ElementArrayBuffer = Buffer<BufferTypeElementArray>;
 pub type DynamicArrayBuffer = DynamicBuffer<BufferTypeArray>;
 pub type DynamicElementArrayBuffer = DynamicBuffer<BufferTypeElementArray>;
+pub type StreamArrayBuffer = StreamBuffer<BufferTypeArray>;
+pub type StreamElementArrayBuffer = StreamBuffer<BufferTypeElementArray>;
 
 #[derive(Debug, Default)]
 pub struct Buffer<B> where B: BufferType {
@@ -118,16 +125,29 @@ impl<B> DynamicBuffer<B> where B: BufferType {
         }
     }
 
-    pub fn upload_draw_data<T>(&self, data: &[T]/*, offset: isize*/) {
+    /// Writes `data` at `buffer_offset`, fails without writing when it would go past the end of the buffer
+    pub fn upload_draw_data<T>(&self, data: &[T]/*, offset: isize*/) -> Result<(), Error> {
+        let size = ::std::mem::size_of_val(data);
+
+        if self.buffer_offset < 0 || self.buffer_offset as usize + size > self.max_buffer_size as usize {
+            return Err(Error::DynamicBufferOverflow {
+                requested: size,
+                offset: self.buffer_offset,
+                capacity: self.max_buffer_size as usize,
+            });
+        }
+
         // need to cast isize to ? offset type = gl::types::GLintptr
         unsafe {
             gl::BufferSubData(
                 B::BUFFER_TYPE,
                 self.buffer_offset, // start at 0 go up by size of data, need to keep track of this?
-                (data.len() * ::std::mem::size_of::<T>()) as gl::types::GLsizeiptr, // data_size_in_bytes
+                size as gl::types::GLsizeiptr, // data_size_in_bytes
                 data.as_ptr() as *const gl::types::GLvoid,
             );
         }
+
+        Ok(())
     }
 
     pub fn set_buffer_offset(&mut self, offset: isize) {
@@ -147,6 +167,239 @@ impl<B> Drop for DynamicBuffer<B> where B: BufferType {
     }
 }
 
+/// How a `StreamBuffer` gets memory to write into without waiting on draws still reading the old data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamMode {
+    /// Writes unsynchronized into the part of the buffer no draw has used yet, and orphans the buffer
+    /// (`glBufferData` with no data) when the ring wraps so the driver hands out fresh storage
+    Orphaning,
+    /// Mapped once with `glBufferStorage` and written straight through the pointer, a wrap only
+    /// waits on the fences of draws that read the part being overwritten
+    Persistent,
+}
+
+impl StreamMode {
+    /// `Persistent` when the context has buffer storage, otherwise `Orphaning`
+    pub fn detect() -> StreamMode {
+        if SystemInfo::supports_buffer_storage() {
+            StreamMode::Persistent
+        } else {
+            StreamMode::Orphaning
+        }
+    }
+}
+
+// the write position of a `StreamBuffer`, apart from the gl calls so the wrapping can be tested
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct StreamRing {
+    capacity: usize,
+    head: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Reservation {
+    offset: usize,
+    // bytes the head moved, including alignment and the end of the buffer skipped by a wrap
+    advanced: usize,
+    wrapped: bool,
+}
+
+impl StreamRing {
+    fn new(capacity: usize) -> StreamRing {
+        StreamRing { capacity, head: 0 }
+    }
+
+    // `size` bytes starting at a multiple of `align`, back at the start when they don't fit before the end
+    fn reserve(&mut self, size: usize, align: usize) -> Result<Reservation, Error> {
+        if size > self.capacity {
+            return Err(Error::StreamBufferFull { requested: size, capacity: self.capacity });
+        }
+
+        let align = align.max(1);
+        let offset = match self.head % align {
+            0 => self.head,
+            rest => self.head + align - rest,
+        };
+        let reservation = if offset + size <= self.capacity {
+            Reservation { offset, advanced: offset + size - self.head, wrapped: false }
+        } else {
+            Reservation { offset: 0, advanced: self.capacity - self.head + size, wrapped: true }
+        };
+
+        self.head = reservation.offset + size;
+
+        Ok(reservation)
+    }
+}
+
+// whether `len` bytes of the ring from `start`, going round past the end, overlap `range`
+fn ring_overlaps(start: usize, len: usize, capacity: usize, range: (usize, usize)) -> bool {
+    let overlaps = |(a, b): (usize, usize)| a < range.1 && range.0 < b;
+
+    match (len >= capacity, start + len > capacity) {
+        (true, _) => true,
+        (false, false) => overlaps((start, start + len)),
+        (false, true) => overlaps((start, capacity)) || overlaps((0, start + len - capacity)),
+    }
+}
+
+// bytes written between two `StreamBuffer::fence` calls, free again once the fence is signalled
+#[derive(Debug)]
+struct PendingFence {
+    sync: gl::types::GLsync,
+    start: usize,
+    len: usize,
+}
+
+/// Ring buffer for data that is rewritten every draw. Each upload goes after the last one so draws
+/// still reading earlier data aren't waited on, and uploads bigger than the buffer are an error
+/// instead of writing past its end. With `StreamMode::Persistent`, `fence` has to be called after
+/// the draws that read the uploads, the ring only waits on those fences when it comes back round
+#[derive(Debug)]
+pub struct StreamBuffer<B> where B: BufferType {
+    vbo: gl::types::GLuint,
+    mode: StreamMode,
+    ring: StreamRing,
+    mapped: *mut u8,
+    unfenced: Option<(usize, usize)>,
+    pending: VecDeque<PendingFence>,
+    _marker: ::std::marker::PhantomData<B>,
+}
+
+impl<B> StreamBuffer<B> where B: BufferType {
+    /// `capacity` in bytes, a few batches worth so writing doesn't wrap onto data that's still being drawn
+    pub fn new(capacity: usize, mode: StreamMode) -> StreamBuffer<B> {
+        let mut vbo: gl::types::GLuint = 0;
+        let mut mapped = ::std::ptr::null_mut();
+
+        unsafe {
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindBuffer(B::BUFFER_TYPE, vbo);
+
+            match mode {
+                StreamMode::Orphaning => {
+                    gl::BufferData(B::BUFFER_TYPE, capacity as gl::types::GLsizeiptr, ::std::ptr::null(), gl::STREAM_DRAW);
+                },
+                StreamMode::Persistent => {
+                    let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+
+                    gl::BufferStorage(B::BUFFER_TYPE, capacity as gl::types::GLsizeiptr, ::std::ptr::null(), flags);
+                    mapped = gl::MapBufferRange(B::BUFFER_TYPE, 0, capacity as gl::types::GLsizeiptr, flags) as *mut u8;
+                },
+            }
+        }
+
+        StreamBuffer {
+            vbo,
+            mode,
+            ring: StreamRing::new(capacity),
+            mapped,
+            unfenced: None,
+            pending: VecDeque::new(),
+            _marker: ::std::marker::PhantomData,
+        }
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindBuffer(B::BUFFER_TYPE, self.vbo);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindBuffer(B::BUFFER_TYPE, 0);
+        }
+    }
+
+    pub fn get_mode(&self) -> StreamMode {
+        self.mode
+    }
+
+    /// Size of the buffer in bytes
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity
+    }
+
+    /// Writes `data` after the previous upload and returns its offset in bytes, always a multiple of
+    /// the size of `T` so it can be used as a base vertex. The buffer has to be bound in orphaning mode
+    pub fn upload<T>(&mut self, data: &[T]) -> Result<usize, Error> {
+        let size = ::std::mem::size_of_val(data);
+        let reservation = self.ring.reserve(size, ::std::mem::size_of::<T>())?;
+        let (offset, end) = (reservation.offset, reservation.offset + size);
+
+        self.unfenced = match self.unfenced {
+            Some((start, len)) => Some((start, len + reservation.advanced)),
+            None => Some((reservation.offset, size)),
+        };
+
+        unsafe {
+            match self.mode {
+                StreamMode::Orphaning => {
+                    if reservation.wrapped {
+                        gl::BufferData(B::BUFFER_TYPE, self.ring.capacity as gl::types::GLsizeiptr, ::std::ptr::null(), gl::STREAM_DRAW);
+                    }
+
+                    if size > 0 {
+                        let flags = gl::MAP_WRITE_BIT | gl::MAP_UNSYNCHRONIZED_BIT | gl::MAP_INVALIDATE_RANGE_BIT;
+                        let target = gl::MapBufferRange(B::BUFFER_TYPE, offset as gl::types::GLintptr, size as gl::types::GLsizeiptr, flags);
+
+                        ::std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, target as *mut u8, size);
+                        gl::UnmapBuffer(B::BUFFER_TYPE);
+                    }
+                },
+                StreamMode::Persistent => {
+                    self.wait_for_range(offset, end);
+                    ::std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.mapped.add(offset), size);
+                },
+            }
+        }
+
+        Ok(offset)
+    }
+
+    /// Marks everything uploaded since the last fence as in use by the draws issued so far
+    pub fn fence(&mut self) {
+        if self.mode != StreamMode::Persistent {
+            return;
+        }
+
+        if let Some((start, len)) = self.unfenced.take() {
+            let sync = unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+
+            self.pending.push_back(PendingFence { sync, start, len });
+        }
+    }
+
+    // waits for and drops the fences of draws that read any of `start..end`
+    fn wait_for_range(&mut self, start: usize, end: usize) {
+        let capacity = self.ring.capacity;
+
+        while let Some(index) = self.pending.iter().position(|pending| ring_overlaps(pending.start, pending.len, capacity, (start, end))) {
+            // fences are signalled in order, so everything before an overlapping fence is done too
+            for pending in self.pending.drain(..=index) {
+                unsafe {
+                    while gl::ClientWaitSync(pending.sync, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000) == gl::TIMEOUT_EXPIRED {}
+                    gl::DeleteSync(pending.sync);
+                }
+            }
+        }
+    }
+}
+
+impl<B> Drop for StreamBuffer<B> where B: BufferType {
+    fn drop(&mut self) {
+        unsafe {
+            for pending in self.pending.drain(..) {
+                gl::DeleteSync(pending.sync);
+            }
+
+            // deleting the buffer unmaps it
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct VertexArray {
     vao: gl::types::GLuint,
@@ -184,24 +437,57 @@ impl Drop for VertexArray {
     }
 }
 
+thread_local! {
+    /// Framebuffer `FrameBuffer::unbind` goes back to, 0 is the window. Per thread like the GL context
+    static DEFAULT_FRAMEBUFFER: Cell<gl::types::GLuint> = const { Cell::new(0) };
+}
+
+/// Redirects everything that would draw to the window into `fbo`, used for headless rendering
+pub fn set_default_framebuffer(fbo: gl::types::GLuint) {
+    DEFAULT_FRAMEBUFFER.with(|default| default.set(fbo));
+}
+
+pub fn default_framebuffer() -> gl::types::GLuint {
+    DEFAULT_FRAMEBUFFER.with(|default| default.get())
+}
+
 #[derive(Debug, Fail)]
 pub enum Error {
     #[fail(display="Failed to create FrameBuffer")]
-    FailedToCreateFrameBuffer
+    FailedToCreateFrameBuffer,
+    #[fail(display="Can't upload {} bytes to a StreamBuffer of {} bytes", requested, capacity)]
+    StreamBufferFull {
+        requested: usize,
+        capacity: usize,
+    },
+    #[fail(display="Can't upload {} bytes at offset {} of a DynamicBuffer of {} bytes", requested, offset, capacity)]
+    DynamicBufferOverflow {
+        requested: usize,
+        offset: isize,
+        capacity: usize,
+    },
 }
 
+/// Colour texture plus a depth-stencil renderbuffer, the stencil is used for sprite masks
 #[derive(Debug)]
 pub struct FrameBuffer {
     fbo: gl::types::GLuint,
+    depth_stencil: gl::types::GLuint,
     pub texture: FrameBufferTexture,
 }
 
 impl FrameBuffer {
     pub fn new(screen_width: u32, screen_height: u32) -> Result<FrameBuffer, Error> {
         let mut fbo: gl::types::GLuint =  0;
+        let mut depth_stencil: gl::types::GLuint = 0;
         let texture = FrameBufferTexture::new(screen_width, screen_height);
 
         unsafe {
+            gl::GenRenderbuffers(1, &mut depth_stencil);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, depth_stencil);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, screen_width as i32, screen_height as i32);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
             gl::GenFramebuffers(1, &mut fbo);
             gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
             gl::FramebufferTexture2D(
@@ -211,23 +497,42 @@ impl FrameBuffer {
                 texture.get_texture_handle(),
                 0
             );
-            // TODO: might need this later? for depth-testing/3D
-            // but also probably want it to be a RenderBuffer or FrameBuffer<RenderBuffer>
-            //gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, rbo_depth);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                depth_stencil
+            );
 
             if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
                 return Err(Error::FailedToCreateFrameBuffer);
             }
 
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0)
+            gl::BindFramebuffer(gl::FRAMEBUFFER, default_framebuffer())
         }
 
         Ok(FrameBuffer {
             fbo,
+            depth_stencil,
             texture,
         })
     }
 
+    pub fn get_handle(&self) -> gl::types::GLuint {
+        self.fbo
+    }
+
+    /// Resizes the colour texture and the depth-stencil buffer together
+    pub fn set_size(&self, width: u32, height: u32) {
+        self.texture.set_size(width, height);
+
+        unsafe {
+            gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_stencil);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width as i32, height as i32);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        }
+    }
+
     pub fn bind(&self) {
         unsafe {
             gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
@@ -236,7 +541,7 @@ impl FrameBuffer {
 
     pub fn unbind(&self) {
         unsafe {
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, default_framebuffer());
         }
     }
 }
@@ -245,6 +550,7 @@ impl Drop for FrameBuffer {
     fn drop(&mut self) {
         unsafe {
             gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.depth_stencil);
         }
     }
 }
@@ -291,6 +597,16 @@ impl FrameBufferTexture {
         }
     }
 
+    /// Min and mag filter, `gl::LINEAR` by default
+    pub fn set_filter(&self, filter: gl::types::GLenum) {
+        self.bind();
+        unsafe {
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+        }
+        self.unbind();
+    }
+
     pub fn bind_to_unit(&self, slot: u32) {
         unsafe {
             gl::ActiveTexture(gl::TEXTURE0 + slot as gl::types::GLuint);
@@ -346,3 +662,39 @@ impl Drop for FrameBufferTexture {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_aligns_and_wraps() {
+        let mut ring = StreamRing::new(100);
+
+        assert_eq!(ring.reserve(30, 1).unwrap(), Reservation { offset: 0, advanced: 30, wrapped: false });
+        assert_eq!(ring.reserve(40, 16).unwrap(), Reservation { offset: 32, advanced: 42, wrapped: false });
+        // 72 + 40 doesn't fit, so it starts again from 0 and skips the end
+        assert_eq!(ring.reserve(40, 1).unwrap(), Reservation { offset: 0, advanced: 68, wrapped: true });
+        assert_eq!(ring.head, 40);
+    }
+
+    #[test]
+    fn ring_rejects_uploads_bigger_than_capacity() {
+        let mut ring = StreamRing::new(64);
+
+        match ring.reserve(65, 1) {
+            Err(Error::StreamBufferFull { requested: 65, capacity: 64 }) => (),
+            other => panic!("expected StreamBufferFull, got {:?}", other),
+        }
+        assert_eq!(ring.head, 0);
+    }
+
+    #[test]
+    fn fenced_ranges_overlap_round_the_end() {
+        assert!(ring_overlaps(10, 20, 100, (25, 40)));
+        assert!(!ring_overlaps(10, 20, 100, (30, 40)));
+        // 90..100 and 0..10
+        assert!(ring_overlaps(90, 20, 100, (5, 8)));
+        assert!(!ring_overlaps(90, 20, 100, (10, 90)));
+        assert!(ring_overlaps(50, 100, 100, (0, 1)));
+    }
+}
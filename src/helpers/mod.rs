@@ -7,5 +7,5 @@ mod shader;
 pub mod viewport;
 
 pub use self::color_buffer::ColorBuffer;
-pub use self::shader::{Shader, Program, Error};
-pub use self::viewport::Viewport;
+pub use self::shader::{Shader, Program, UniformValue, Error};
+pub use self::viewport::{Viewport, ScalingMode};
@@ -48,6 +48,17 @@ pub enum Error {
     },
 }
 
+/// A typed uniform value that can be stored and set later, ie by post processing passes
+#[derive(Clone, Debug, PartialEq)]
+pub enum UniformValue {
+    Int(i32),
+    Float(f32),
+    Vec2(f32, f32),
+    Vec4(f32, f32, f32, f32),
+    Mat3(glm::TMat3<f32>),
+    Mat4(glm::TMat4<f32>),
+}
+
 // TODO: not really sure if this should be clonable, but it seems to work??
 #[derive(Debug, Clone)]
 pub struct Program {
@@ -229,6 +240,17 @@ impl Program {
         }
     }
 
+    pub fn set_uniform(&self, location: i32, value: &UniformValue) {
+        match value {
+            UniformValue::Int(v) => self.set_uniform_1i(location, *v),
+            UniformValue::Float(v) => self.set_uniform_1f(location, *v),
+            UniformValue::Vec2(x, y) => self.set_uniform_2f(location, &glm::vec2(*x, *y)),
+            UniformValue::Vec4(x, y, z, w) => self.set_uniform_4f(location, (*x, *y, *z, *w)),
+            UniformValue::Mat3(m) => self.set_uniform_mat3f(location, m),
+            UniformValue::Mat4(m) => self.set_uniform_mat4f(location, m),
+        }
+    }
+
     pub fn set_used(&self) {
         unsafe {
             gl::UseProgram(self.id);
@@ -11,5 +11,35 @@ impl SystemInfo {
 
         max_textures
     }
-}
 
+    pub fn get_max_array_texture_layers() -> gl::types::GLint {
+        let mut max_layers: gl::types::GLint = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::MAX_ARRAY_TEXTURE_LAYERS, &mut max_layers);
+        }
+
+        max_layers
+    }
+
+    /// Whether buffers can be persistently mapped, GL 4.4 or `GL_ARB_buffer_storage`
+    pub fn supports_buffer_storage() -> bool {
+        let (mut major, mut minor, mut extension_count): (gl::types::GLint, gl::types::GLint, gl::types::GLint) = (0, 0, 0);
+
+        unsafe {
+            gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+            gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+        }
+
+        if (major, minor) >= (4, 4) {
+            return true;
+        }
+
+        (0..extension_count).any(|i| unsafe {
+            let name = gl::GetStringi(gl::EXTENSIONS, i as gl::types::GLuint);
+
+            !name.is_null() && std::ffi::CStr::from_ptr(name as *const std::os::raw::c_char).to_bytes() == b"GL_ARB_buffer_storage"
+        })
+    }
+}
@@ -1,23 +1,145 @@
+/// How a virtual resolution is scaled up to the window
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ScalingMode {
+    /// Largest whole number scale that fits, pixel perfect with black bars around it
+    #[default]
+    Integer,
+    /// Keeps the aspect ratio and fits the window, black bars on two sides
+    Fit,
+    /// Keeps the aspect ratio and covers the whole window, cropping the sides that don't fit
+    Fill,
+}
+
+#[derive(Clone, Debug)]
 pub struct Viewport {
     pub x: f32,
     pub y: f32,
     pub w: f32,
     pub h: f32,
+    virtual_resolution: Option<(u32, u32)>,
+    scaling_mode: ScalingMode,
 }
 
 impl Viewport {
     pub fn for_window(w: f32, h: f32) -> Viewport {
-        Viewport { x: 0.0, y: 0.0, w, h }
+        Viewport { x: 0.0, y: 0.0, w, h, virtual_resolution: None, scaling_mode: ScalingMode::default() }
+    }
+
+    /// A window viewport that draws into the part `width` x `height` is scaled up to with `scaling_mode`.
+    /// Give it to `Renderer2D::use_viewport` so the scene is rendered at that size
+    pub fn with_virtual_resolution(w: f32, h: f32, width: u32, height: u32, scaling_mode: ScalingMode) -> Viewport {
+        Viewport { virtual_resolution: Some((width, height)), scaling_mode, ..Viewport::for_window(w, h) }
+    }
+
+    pub fn get_virtual_resolution(&self) -> Option<(u32, u32)> {
+        self.virtual_resolution
+    }
+
+    pub fn get_scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    pub fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) {
+        self.scaling_mode = scaling_mode;
     }
 
+    /// The scaled area moves with the window size, `Renderer2D::resize` calls this and draws into it.
+    /// Call `set_used` after this when drawing without a `Renderer2D`
     pub fn update_size(&mut self, w: f32, h: f32) {
         self.w = w;
         self.h = h;
     }
 
+    /// `glViewport` style (x, y, width, height) of the part of the window drawn to, y going up from
+    /// the bottom. The whole viewport without a virtual resolution, can be bigger than it with `Fill`
+    pub fn scaled_rect(&self) -> [i32; 4] {
+        let (x, y, w, h) = (self.x as i32, self.y as i32, self.w as i32, self.h as i32);
+        let (width, height) = match self.virtual_resolution {
+            Some(resolution) => resolution,
+            None => return [x, y, w, h],
+        };
+        let (width, height) = (width.max(1) as f32, height.max(1) as f32);
+        let (scale_x, scale_y) = (self.w / width, self.h / height);
+        let scale = match self.scaling_mode {
+            ScalingMode::Integer => scale_x.min(scale_y).floor().max(1.0),
+            ScalingMode::Fit => scale_x.min(scale_y),
+            ScalingMode::Fill => scale_x.max(scale_y),
+        };
+        let (scaled_w, scaled_h) = ((width * scale).round() as i32, (height * scale).round() as i32);
+
+        [x + (w - scaled_w) / 2, y + (h - scaled_h) / 2, scaled_w, scaled_h]
+    }
+
+    /// Converts a position in window pixels (from the top left, like mouse events) into the
+    /// virtual resolution, or the window itself without one. `None` when it's on the black bars
+    pub fn to_logical(&self, window_x: f32, window_y: f32) -> Option<(f32, f32)> {
+        let [x, y, width, height] = self.scaled_rect();
+        let (logical_w, logical_h) = match self.virtual_resolution {
+            Some((logical_w, logical_h)) => (logical_w as f32, logical_h as f32),
+            None => (width as f32, height as f32),
+        };
+        let left = x as f32;
+        let top = self.y + self.h - (y + height) as f32;
+        let logical_x = (window_x - left) / width as f32 * logical_w;
+        let logical_y = (window_y - top) / height as f32 * logical_h;
+
+        if logical_x < 0.0 || logical_y < 0.0 || logical_x >= logical_w || logical_y >= logical_h {
+            return None;
+        }
+
+        Some((logical_x, logical_y))
+    }
+
+    /// Makes `scaled_rect` the gl viewport
     pub fn set_used(&self) {
+        let [x, y, width, height] = self.scaled_rect();
+
         unsafe {
-            gl::Viewport(self.x as i32, self.y as i32, self.w as i32, self.h as i32);
+            gl::Viewport(x, y, width, height);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_scaling_uses_whole_multiples() {
+        let viewport = Viewport::with_virtual_resolution(1000.0, 600.0, 320, 180, ScalingMode::Integer);
+
+        assert_eq!(viewport.scaled_rect(), [20, 30, 960, 540]);
+    }
+
+    #[test]
+    fn fit_and_fill_keep_the_aspect_ratio() {
+        let mut viewport = Viewport::with_virtual_resolution(1000.0, 600.0, 320, 180, ScalingMode::Fit);
+
+        assert_eq!(viewport.scaled_rect(), [0, 18, 1000, 563]);
+
+        viewport.set_scaling_mode(ScalingMode::Fill);
+
+        assert_eq!(viewport.scaled_rect(), [-33, 0, 1067, 600]);
+    }
+
+    #[test]
+    fn resizing_recomputes_the_letterbox() {
+        let mut viewport = Viewport::with_virtual_resolution(640.0, 360.0, 320, 180, ScalingMode::Integer);
+
+        assert_eq!(viewport.scaled_rect(), [0, 0, 640, 360]);
+
+        viewport.update_size(700.0, 400.0);
+
+        assert_eq!(viewport.scaled_rect(), [30, 20, 640, 360]);
+    }
+
+    #[test]
+    fn window_positions_convert_to_logical() {
+        let viewport = Viewport::with_virtual_resolution(1000.0, 600.0, 320, 180, ScalingMode::Integer);
+
+        assert_eq!(viewport.to_logical(20.0, 30.0), Some((0.0, 0.0)));
+        assert_eq!(viewport.to_logical(500.0, 300.0), Some((160.0, 90.0)));
+        assert_eq!(viewport.to_logical(10.0, 300.0), None);
+        assert_eq!(Viewport::for_window(800.0, 600.0).to_logical(400.0, 100.0), Some((400.0, 100.0)));
+    }
+}
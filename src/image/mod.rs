@@ -5,6 +5,7 @@ use crate::resources::*;
 use crate::textures::texture::{Texture};
 use crate::textures::transform::{TextureTransform};
 use crate::camera::{Camera};
+use crate::renderer::blend_mode::{BlendMode};
 
 use image_shaders::{VERTEX_SOURCE, FRAGMENT_SOURCE};
 
@@ -37,7 +38,7 @@ impl Default for ImageProps {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Direction {
     Normal,
     Flipped,
@@ -237,6 +238,7 @@ impl Image {
         let mvp = camera.get_projection() * camera.get_view() * model;//self.model;
         let texcoord_transform = self.texture_transform.get_transform();
 
+        BlendMode::Alpha.apply();
         self.texture.bind_to_unit(self.image.texture_slot);
         self.program.set_used();
         self.program.set_uniform_4f(self.uniform_color, self.color);
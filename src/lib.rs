@@ -18,7 +18,13 @@ pub mod renderer;
 pub mod debug;
 pub mod sprite;
 pub mod tilemaps;
+pub mod nine_slice;
 pub mod font;
+pub mod postfx;
+pub mod lighting;
+pub mod particles;
+#[cfg(feature = "headless")]
+pub mod headless;
 
 pub use triangle::{Triangle};
 pub use rectangle::{Rectangle, RectangleProps};
@@ -0,0 +1,103 @@
+/// Positions are in world space, lights are drawn as a quad over the camera's view
+pub const LIGHT_VERTEX_SOURCE: &str = r#"
+#version 330 core
+
+layout (location = 0) in vec2 Position;
+
+uniform mat4 MVP;
+
+out vec2 WorldPosition;
+
+void main() {
+    gl_Position = MVP * vec4(Position, 0.0, 1.0);
+    WorldPosition = Position;
+}
+"#;
+
+/// `LightKind` 0 is a point light, 1 a spot light and 2 a directional light
+pub const LIGHT_FRAGMENT_SOURCE: &str = r#"
+#version 330 core
+
+uniform int LightKind;
+uniform vec2 LightPosition;
+uniform vec2 LightDirection;
+uniform vec4 LightColor;
+uniform float Radius;
+uniform float ConeCos;
+uniform float Height;
+uniform int UseNormals;
+uniform sampler2D NormalBuffer;
+uniform vec2 Resolution;
+
+in vec2 WorldPosition;
+
+out vec4 Color;
+
+void main() {
+    vec2 toLight;
+    float attenuation = 1.0;
+
+    if (LightKind == 2) {
+        toLight = -LightDirection;
+    } else {
+        toLight = LightPosition - WorldPosition;
+        attenuation = clamp(1.0 - length(toLight) / Radius, 0.0, 1.0);
+        attenuation *= attenuation;
+
+        if (LightKind == 1) {
+            float angle = dot(normalize(-toLight), LightDirection);
+
+            attenuation *= smoothstep(ConeCos, mix(ConeCos, 1.0, 0.2), angle);
+        }
+    }
+
+    float diffuse = 1.0;
+
+    if (UseNormals == 1) {
+        // world y goes down the screen, normal maps point green up
+        vec3 normal = normalize(texture(NormalBuffer, gl_FragCoord.xy / Resolution).xyz * 2.0 - 1.0);
+        vec3 lightDirection = normalize(vec3(toLight.x, -toLight.y, Height));
+
+        diffuse = max(dot(normal, lightDirection), 0.0);
+    }
+
+    Color = vec4(LightColor.rgb * LightColor.a * attenuation * diffuse, 1.0);
+}
+"#;
+
+/// Shadows only go into the stencil buffer
+pub const SHADOW_FRAGMENT_SOURCE: &str = r#"
+#version 330 core
+
+out vec4 Color;
+
+void main() {
+    Color = vec4(0.0);
+}
+"#;
+
+pub const COMPOSITE_FRAGMENT_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D LightBuffer;
+uniform vec2 Resolution;
+
+out vec4 Color;
+
+void main() {
+    Color = texture(LightBuffer, gl_FragCoord.xy / Resolution);
+}
+"#;
+
+/// Batch material for the normal buffer, sprites are drawn with their normal map as their texture
+pub const NORMAL_FRAGMENT_SOURCE: &str = r#"
+void main() {
+    vec4 normal = batchTexture(IN.TexCoord);
+
+    if (normal.a * IN.TexColor.a < 0.5) {
+        discard;
+    }
+
+    Color = vec4(normal.rgb, 1.0);
+}
+"#;
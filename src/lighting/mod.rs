@@ -0,0 +1,575 @@
+//! 2D lights for `Renderer2D::set_lighting`. Lights are added up in a light buffer that starts at the
+//! ambient colour and is multiplied over the scene, optionally using normal maps for the sprite
+//! textures and casting shadows from `Occluder`s. Lights and occluders are submitted every frame
+//! like sprites with `Renderer2D::submit_light` and `Renderer2D::submit_occluders`.
+
+mod lighting_shaders;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::helpers::{self, data, UniformValue};
+use crate::helpers::buffer::{ArrayBuffer, FrameBuffer, VertexArray};
+use crate::renderer::batch_mode::{BatchMode};
+use crate::renderer::blend_mode::{BlendMode};
+use crate::renderer::material::{Material};
+use crate::textures::texture::{Texture};
+use lighting_shaders::*;
+
+/// How far shadows are stretched away from their occluder, in world units
+const SHADOW_LENGTH: f32 = 100_000.0;
+
+#[derive(VertexAttribPointers)]
+#[derive(Debug)]
+#[repr(C, packed)]
+struct LightVertex {
+    #[location=0]
+    pos: data::f32_f32,
+}
+
+/// Light that fades out towards its radius in every direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    pub pos: (f32, f32),
+    pub color: (f32, f32, f32),
+    pub intensity: f32,
+    pub radius: f32,
+    /// Distance above the scene, lower lights hit normal mapped surfaces at a flatter angle
+    pub height: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for PointLight {
+    fn default() -> PointLight {
+        PointLight {
+            pos: (0.0, 0.0),
+            color: (1.0, 1.0, 1.0),
+            intensity: 1.0,
+            radius: 200.0,
+            height: 40.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+/// Point light limited to a cone, `direction` and `cone` (half the cone's width) are in radians
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpotLight {
+    pub pos: (f32, f32),
+    pub direction: f32,
+    pub cone: f32,
+    pub color: (f32, f32, f32),
+    pub intensity: f32,
+    pub radius: f32,
+    pub height: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for SpotLight {
+    fn default() -> SpotLight {
+        SpotLight {
+            pos: (0.0, 0.0),
+            direction: 0.0,
+            cone: std::f32::consts::FRAC_PI_6,
+            color: (1.0, 1.0, 1.0),
+            intensity: 1.0,
+            radius: 300.0,
+            height: 40.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+/// Light without a position that reaches everything from `direction` (radians), like the sun
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: f32,
+    pub color: (f32, f32, f32),
+    pub intensity: f32,
+    /// How steeply the light comes down onto normal mapped surfaces, 1.0 is 45 degrees
+    pub height: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> DirectionalLight {
+        DirectionalLight {
+            direction: std::f32::consts::FRAC_PI_2,
+            color: (1.0, 1.0, 1.0),
+            intensity: 0.5,
+            height: 1.0,
+            casts_shadows: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Light {
+        Light::Point(light)
+    }
+}
+
+impl From<SpotLight> for Light {
+    fn from(light: SpotLight) -> Light {
+        Light::Spot(light)
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Light {
+        Light::Directional(light)
+    }
+}
+
+impl Light {
+    pub fn casts_shadows(&self) -> bool {
+        match self {
+            Light::Point(light) => light.casts_shadows,
+            Light::Spot(light) => light.casts_shadows,
+            Light::Directional(light) => light.casts_shadows,
+        }
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        let color = |(r, g, b): (f32, f32, f32), intensity: f32| UniformValue::Vec4(r, g, b, intensity);
+        let direction = |angle: f32| UniformValue::Vec2(angle.cos(), angle.sin());
+
+        match self {
+            Light::Point(light) => vec![
+                ("LightKind", UniformValue::Int(0)),
+                ("LightPosition", UniformValue::Vec2(light.pos.0, light.pos.1)),
+                ("LightColor", color(light.color, light.intensity)),
+                ("Radius", UniformValue::Float(light.radius)),
+                ("Height", UniformValue::Float(light.height)),
+            ],
+            Light::Spot(light) => vec![
+                ("LightKind", UniformValue::Int(1)),
+                ("LightPosition", UniformValue::Vec2(light.pos.0, light.pos.1)),
+                ("LightDirection", direction(light.direction)),
+                ("LightColor", color(light.color, light.intensity)),
+                ("Radius", UniformValue::Float(light.radius)),
+                ("ConeCos", UniformValue::Float(light.cone.cos())),
+                ("Height", UniformValue::Float(light.height)),
+            ],
+            Light::Directional(light) => vec![
+                ("LightKind", UniformValue::Int(2)),
+                ("LightDirection", direction(light.direction)),
+                ("LightColor", color(light.color, light.intensity)),
+                ("Height", UniformValue::Float(light.height)),
+            ],
+        }
+    }
+
+    // the direction a shadow is stretched in from `point`
+    fn shadow_direction(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let (light_x, light_y) = match self {
+            Light::Point(light) => light.pos,
+            Light::Spot(light) => light.pos,
+            Light::Directional(light) => return (light.direction.cos(), light.direction.sin()),
+        };
+        let (dx, dy) = (x - light_x, y - light_y);
+        let length = (dx * dx + dy * dy).sqrt().max(0.0001);
+
+        (dx / length, dy / length)
+    }
+}
+
+/// Polygon in world space that blocks light, the points go around its outline
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occluder {
+    pub points: Vec<(f32, f32)>,
+}
+
+impl Occluder {
+    pub fn new(points: Vec<(f32, f32)>) -> Occluder {
+        Occluder { points }
+    }
+
+    pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Occluder {
+        Occluder::new(vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)])
+    }
+}
+
+/// Ambient colour, normal maps and the buffers lights are drawn into, see `Renderer2D::set_lighting`
+#[derive(Debug)]
+pub struct Lighting {
+    ambient: (f32, f32, f32),
+    light_program: LightingProgram,
+    shadow_program: LightingProgram,
+    composite_program: LightingProgram,
+    normal_material: Material,
+    normal_maps: HashMap<u32, u32>,
+    flat_normal: Texture,
+    light_buffer: FrameBuffer,
+    normal_buffer: FrameBuffer,
+    size: (u32, u32),
+    vbo: ArrayBuffer,
+    vao: VertexArray,
+}
+
+impl Lighting {
+    /// `ambient` is the light everything gets without any lights, black is completely dark
+    pub fn new(ambient: (f32, f32, f32)) -> Result<Lighting, failure::Error> {
+        let vbo = ArrayBuffer::new();
+        let vao = VertexArray::new();
+
+        vao.bind();
+        vbo.bind();
+
+        LightVertex::vertex_attrib_pointers();
+
+        vbo.unbind();
+        vao.unbind();
+
+        Ok(Lighting {
+            ambient,
+            light_program: create_program("light", LIGHT_FRAGMENT_SOURCE)?,
+            shadow_program: create_program("shadow", SHADOW_FRAGMENT_SOURCE)?,
+            composite_program: create_program("composite", COMPOSITE_FRAGMENT_SOURCE)?,
+            normal_material: Material::from_fragment_source("lighting/normals", NORMAL_FRAGMENT_SOURCE, &[], BatchMode::TextureUnits)?,
+            normal_maps: HashMap::new(),
+            flat_normal: Texture::from_pixels(1, 1, &[128, 128, 255, 255]),
+            light_buffer: FrameBuffer::new(1, 1)?,
+            normal_buffer: FrameBuffer::new(1, 1)?,
+            size: (1, 1),
+            vbo,
+            vao,
+        })
+    }
+
+    pub fn get_ambient(&self) -> (f32, f32, f32) {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: (f32, f32, f32)) {
+        self.ambient = ambient;
+    }
+
+    /// Lights sprites drawn with `texture_handle` using `normal_map_handle`, which has to line up with
+    /// the texture and outlive the lighting. Textures without one are lit as if they were flat.
+    /// Only used with `BatchMode::TextureUnits`
+    pub fn set_normal_map(&mut self, texture_handle: u32, normal_map_handle: u32) {
+        self.normal_maps.insert(texture_handle, normal_map_handle);
+    }
+
+    pub fn remove_normal_map(&mut self, texture_handle: u32) {
+        self.normal_maps.remove(&texture_handle);
+    }
+
+    pub(crate) fn has_normal_maps(&self) -> bool {
+        !self.normal_maps.is_empty()
+    }
+
+    pub(crate) fn normal_material(&self) -> &Material {
+        &self.normal_material
+    }
+
+    /// The normal map to draw in place of a batch texture
+    pub(crate) fn normal_texture(&self, texture_handle: i32) -> i32 {
+        match self.normal_maps.get(&(texture_handle as u32)) {
+            Some(normal_map_handle) => *normal_map_handle as i32,
+            None => self.flat_normal.get_texture_handle() as i32,
+        }
+    }
+
+    /// Light and normal buffers are the size of the scene's render target
+    pub(crate) fn resize(&mut self, size: (u32, u32)) {
+        if self.size != size {
+            self.light_buffer.set_size(size.0, size.1);
+            self.normal_buffer.set_size(size.0, size.1);
+            self.size = size;
+        }
+    }
+
+    /// Binds and clears the part of the normal buffer in `viewport` to flat normals,
+    /// the batches are drawn into it with `normal_material` next
+    pub(crate) fn begin_normals(&self, viewport: [i32; 4]) {
+        self.normal_buffer.bind();
+        clear_viewport(viewport, (0.5, 0.5, 1.0, 1.0));
+    }
+
+    /// Adds up `lights` in the light buffer for the part of the scene in `viewport`, which is
+    /// `view_corners` of the world through `mvp`
+    pub(crate) fn render_lights(
+        &self,
+        mvp: &glm::Mat4,
+        viewport: [i32; 4],
+        lights: &[Light],
+        occluders: &[Occluder],
+        use_normals: bool,
+    ) {
+        let view = quad_vertices(view_corners(mvp));
+        let (width, height) = self.size;
+        let (r, g, b) = self.ambient;
+
+        self.light_buffer.bind();
+        clear_viewport(viewport, (r, g, b, 1.0));
+
+        unsafe {
+            gl::Enable(gl::SCISSOR_TEST);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.normal_buffer.texture.get_texture_handle());
+        }
+
+        self.vao.bind();
+        self.vbo.bind();
+
+        for light in lights {
+            let shadows: Vec<LightVertex> = if light.casts_shadows() {
+                occluders.iter().flat_map(|occluder| shadow_triangles(light, occluder)).map(light_vertex).collect()
+            } else {
+                Vec::new()
+            };
+
+            if shadows.is_empty() {
+                unsafe {
+                    gl::Disable(gl::STENCIL_TEST);
+                }
+            } else {
+                self.draw_shadows(mvp, &shadows);
+            }
+
+            self.light_program.set_used();
+            self.light_program.set_uniforms(&[
+                ("MVP", UniformValue::Mat4(*mvp)),
+                ("NormalBuffer", UniformValue::Int(0)),
+                ("UseNormals", UniformValue::Int(use_normals as i32)),
+                ("Resolution", UniformValue::Vec2(width as f32, height as f32)),
+            ]);
+            self.light_program.set_uniforms(&light.uniforms());
+            BlendMode::Additive.apply();
+            draw_triangles(&self.vbo, &view);
+        }
+
+        self.vbo.unbind();
+        self.vao.unbind();
+
+        unsafe {
+            gl::Disable(gl::STENCIL_TEST);
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+
+        BlendMode::default().apply();
+        self.light_buffer.unbind();
+    }
+
+    // marks the shadowed part of the light buffer in its stencil, the light is then only drawn outside of it
+    fn draw_shadows(&self, mvp: &glm::Mat4, shadows: &[LightVertex]) {
+        unsafe {
+            gl::Enable(gl::STENCIL_TEST);
+            gl::StencilMask(0xFF);
+            gl::Clear(gl::STENCIL_BUFFER_BIT);
+            gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+            gl::StencilFunc(gl::ALWAYS, 1, 0xFF);
+            gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+        }
+
+        self.shadow_program.set_used();
+        self.shadow_program.set_uniforms(&[("MVP", UniformValue::Mat4(*mvp))]);
+        draw_triangles(&self.vbo, shadows);
+
+        unsafe {
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::StencilFunc(gl::EQUAL, 0, 0xFF);
+            gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+        }
+    }
+
+    /// Multiplies the light buffer over the framebuffer that is bound, only the colour is changed.
+    /// With `stencil_bits` only where the stencil has those bits set
+    pub(crate) fn composite(&self, mvp: &glm::Mat4, stencil_bits: Option<u8>) {
+        let (width, height) = self.size;
+
+        self.composite_program.set_used();
+        self.composite_program.set_uniforms(&[
+            ("MVP", UniformValue::Mat4(*mvp)),
+            ("LightBuffer", UniformValue::Int(0)),
+            ("Resolution", UniformValue::Vec2(width as f32, height as f32)),
+        ]);
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.light_buffer.texture.get_texture_handle());
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::DST_COLOR, gl::ZERO);
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::FALSE);
+
+            if let Some(bits) = stencil_bits {
+                gl::Enable(gl::STENCIL_TEST);
+                gl::StencilFunc(gl::EQUAL, bits as i32, bits as u32);
+                gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+            }
+        }
+
+        self.vao.bind();
+        self.vbo.bind();
+        draw_triangles(&self.vbo, &quad_vertices(view_corners(mvp)));
+        self.vbo.unbind();
+        self.vao.unbind();
+
+        unsafe {
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::Disable(gl::STENCIL_TEST);
+        }
+
+        BlendMode::default().apply();
+    }
+}
+
+// a lighting program and the locations of its uniforms, which are looked up the first time they are set
+#[derive(Debug)]
+struct LightingProgram {
+    program: helpers::Program,
+    locations: RefCell<HashMap<&'static str, i32>>,
+}
+
+impl LightingProgram {
+    fn set_used(&self) {
+        self.program.set_used();
+    }
+
+    fn set_uniforms(&self, uniforms: &[(&'static str, UniformValue)]) {
+        let program = &self.program;
+        let mut locations = self.locations.borrow_mut();
+
+        for (name, value) in uniforms {
+            let location = *locations.entry(name).or_insert_with(|| program.get_uniform_location(name).unwrap_or(-1));
+
+            if location >= 0 {
+                program.set_uniform(location, value);
+            }
+        }
+    }
+}
+
+fn create_program(name: &str, fragment_source: &str) -> Result<LightingProgram, failure::Error> {
+    let shaders = [
+        helpers::Shader::from_raw(LIGHT_VERTEX_SOURCE, gl::VERTEX_SHADER)?,
+        helpers::Shader::from_raw(fragment_source, gl::FRAGMENT_SHADER)?,
+    ];
+    let program = helpers::Program::from_shaders(&shaders[..], &format!("internal/shaders/lighting/{}", name))
+        .map_err(|message| helpers::Error::LinkError { name: name.into(), message })?;
+
+    Ok(LightingProgram { program, locations: RefCell::new(HashMap::new()) })
+}
+
+// clears the part of the bound framebuffer in `viewport` without touching the clear colour
+fn clear_viewport([x, y, width, height]: [i32; 4], (r, g, b, a): (f32, f32, f32, f32)) {
+    let mut clear_color: [gl::types::GLfloat; 4] = [0.0; 4];
+
+    unsafe {
+        gl::GetFloatv(gl::COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+        gl::Enable(gl::SCISSOR_TEST);
+        gl::Scissor(x, y, width, height);
+        gl::ClearColor(r, g, b, a);
+        gl::StencilMask(0xFF);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        gl::ClearColor(clear_color[0], clear_color[1], clear_color[2], clear_color[3]);
+    }
+}
+
+fn draw_triangles(vbo: &ArrayBuffer, vertices: &[LightVertex]) {
+    vbo.static_draw_data(vertices);
+
+    unsafe {
+        gl::DrawArrays(gl::TRIANGLES, 0, vertices.len() as i32);
+    }
+}
+
+fn light_vertex(pos: (f32, f32)) -> LightVertex {
+    LightVertex { pos: pos.into() }
+}
+
+fn quad_vertices([top_left, top_right, bottom_left, bottom_right]: [(f32, f32); 4]) -> Vec<LightVertex> {
+    [top_left, top_right, bottom_left, bottom_left, top_right, bottom_right].iter()
+        .map(|&pos| light_vertex(pos))
+        .collect()
+}
+
+// world positions of the corners of the view through `mvp`: top left, top right, bottom left, bottom right
+fn view_corners(mvp: &glm::Mat4) -> [(f32, f32); 4] {
+    let inverse = glm::inverse(mvp);
+    let corner = |x: f32, y: f32| {
+        let world = inverse * glm::vec4(x, y, 0.0, 1.0);
+
+        (world.x / world.w, world.y / world.w)
+    };
+
+    [corner(-1.0, 1.0), corner(1.0, 1.0), corner(-1.0, -1.0), corner(1.0, -1.0)]
+}
+
+// every edge of the occluder stretched away from the light as two triangles
+fn shadow_triangles(light: &Light, occluder: &Occluder) -> Vec<(f32, f32)> {
+    let points = &occluder.points;
+    let extrude = |point: (f32, f32)| {
+        let (dx, dy) = light.shadow_direction(point);
+
+        (point.0 + dx * SHADOW_LENGTH, point.1 + dy * SHADOW_LENGTH)
+    };
+
+    if points.len() < 2 {
+        return Vec::new();
+    }
+
+    (0..points.len())
+        .flat_map(|i| {
+            let (start, end) = (points[i], points[(i + 1) % points.len()]);
+            let (far_start, far_end) = (extrude(start), extrude(end));
+
+            vec![start, end, far_end, start, far_end, far_start]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadows_stretch_away_from_point_lights() {
+        let light = Light::from(PointLight { pos: (0.0, 0.0), ..Default::default() });
+        let triangles = shadow_triangles(&light, &Occluder::new(vec![(10.0, -5.0), (10.0, 5.0)]));
+
+        // a line has an edge in each direction
+        assert_eq!(triangles.len(), 12);
+        assert_eq!(&triangles[..2], &[(10.0, -5.0), (10.0, 5.0)]);
+        assert!(triangles[2].0 > 10_000.0 && triangles[2].1 > 1_000.0);
+        assert!(triangles[5].0 > 10_000.0 && triangles[5].1 < -1_000.0);
+    }
+
+    #[test]
+    fn directional_shadows_are_parallel() {
+        let light = Light::from(DirectionalLight { direction: 0.0, ..Default::default() });
+        let triangles = shadow_triangles(&light, &Occluder::rect(0.0, 0.0, 2.0, 2.0));
+
+        assert_eq!(triangles.len(), 4 * 6);
+        assert_eq!(triangles[2], (2.0 + SHADOW_LENGTH, 0.0));
+        assert_eq!(triangles[5], (SHADOW_LENGTH, 0.0));
+    }
+
+    #[test]
+    fn view_corners_undo_the_camera() {
+        let projection = glm::ortho(0.0, 200.0, 100.0, 0.0, -10.0, 100.0);
+        let view = glm::translate(&glm::identity(), &glm::vec3(-50.0, 0.0, 0.0));
+        let corners = view_corners(&(projection * view));
+        let rounded: Vec<(f32, f32)> = corners.iter().map(|(x, y)| (x.round(), y.round())).collect();
+
+        assert_eq!(rounded, vec![(50.0, 0.0), (250.0, 0.0), (50.0, 100.0), (250.0, 100.0)]);
+    }
+
+    #[test]
+    fn lights_set_their_kind() {
+        let spot = Light::from(SpotLight { cone: 0.0, ..Default::default() });
+
+        assert!(spot.uniforms().contains(&("LightKind", UniformValue::Int(1))));
+        assert!(spot.uniforms().contains(&("ConeCos", UniformValue::Float(1.0))));
+        assert!(!Light::from(DirectionalLight::default()).casts_shadows());
+    }
+}
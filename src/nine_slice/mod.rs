@@ -0,0 +1,285 @@
+use serde::{Serialize, Deserialize};
+
+use crate::resources::*;
+use crate::textures::texture::{Texture};
+use crate::renderer::renderable::{Renderable2D, BasicVertex, VertexSink};
+use crate::sprite::{normalize_color};
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="NineSlice insets {:?} don't fit in a {}x{} texture", insets, width, height)]
+    InsetsTooLarge {
+        insets: NineSliceInsets,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Size of the borders in texture pixels, the corners keep this size when the slice is resized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NineSliceInsets {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl NineSliceInsets {
+    pub fn uniform(inset: u32) -> NineSliceInsets {
+        NineSliceInsets { left: inset, right: inset, top: inset, bottom: inset }
+    }
+}
+
+/// How the edges and centre fill the space between the corners
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SliceMode {
+    #[default]
+    Stretch,
+    /// Repeats the middle of the texture at its own size, the last tile in each direction is cut short
+    Tile,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NineSliceProps {
+    pub pos: (f32, f32),
+    pub size: (f32, f32),
+    pub insets: NineSliceInsets,
+    pub mode: SliceMode,
+    pub color: (u8, u8, u8, f32),
+}
+
+impl Default for NineSliceProps {
+    fn default() -> NineSliceProps {
+        NineSliceProps {
+            pos: (0.0, 0.0),
+            size: (0.0, 0.0),
+            insets: NineSliceInsets::default(),
+            mode: SliceMode::default(),
+            color: (255, 255, 255, 1.0),
+        }
+    }
+}
+
+/// JSON file describing a nine slice texture, e.g.
+/// `{ "image": "images/panel.png", "insets": { "left": 8, "right": 8, "top": 8, "bottom": 8 }, "mode": "tile" }`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct NineSliceDescriptor {
+    pub image: String,
+    pub insets: NineSliceInsets,
+    #[serde(default)]
+    pub mode: SliceMode,
+}
+
+/// A bordered texture that can be resized without stretching its corners, for UI panels and buttons
+#[derive(Debug)]
+pub struct NineSlice {
+    texture: Texture,
+    props: NineSliceProps,
+    vertices: Vec<BasicVertex>,
+}
+
+impl Renderable2D for NineSlice {
+    fn texture(&self) -> u32 {
+        self.texture.texture_handle
+    }
+
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&self.vertices);
+    }
+}
+
+impl NineSlice {
+    pub fn new(res: &Resources, image_path: String, props: NineSliceProps) -> Result<NineSlice, failure::Error> {
+        NineSlice::with_texture(Texture::new(res, image_path)?, props)
+    }
+
+    /// Uses a texture owned elsewhere, which has to outlive the NineSlice
+    pub fn from_texture(texture: &Texture, props: NineSliceProps) -> Result<NineSlice, failure::Error> {
+        let (width, height) = texture.get_dimensions();
+
+        NineSlice::with_texture(Texture::from_handle(texture.texture_handle, width, height), props)
+    }
+
+    /// Loads a `NineSliceDescriptor`, the slice starts at (0, 0) the size of its texture
+    pub fn from_json(res: &Resources, file_path: String) -> Result<NineSlice, failure::Error> {
+        let json = res.load_from_json(&file_path)?;
+        let descriptor: NineSliceDescriptor = serde_json::from_value(json)?;
+        let texture = Texture::new(res, descriptor.image)?;
+        let (width, height) = texture.get_dimensions();
+
+        NineSlice::with_texture(texture, NineSliceProps {
+            size: (width as f32, height as f32),
+            insets: descriptor.insets,
+            mode: descriptor.mode,
+            ..Default::default()
+        })
+    }
+
+    fn with_texture(texture: Texture, props: NineSliceProps) -> Result<NineSlice, failure::Error> {
+        let (width, height) = texture.get_dimensions();
+        let insets = props.insets;
+
+        if insets.left + insets.right > width || insets.top + insets.bottom > height {
+            return Err(Error::InsetsTooLarge { insets, width, height }.into());
+        }
+
+        Ok(NineSlice {
+            vertices: generate_vertices(&props, (width, height)),
+            texture,
+            props,
+        })
+    }
+
+    pub fn get_props(&self) -> &NineSliceProps {
+        &self.props
+    }
+
+    pub fn set_pos(&mut self, x: f32, y: f32) {
+        self.props.pos = (x, y);
+        self.update();
+    }
+
+    pub fn set_size(&mut self, width: f32, height: f32) {
+        self.props.size = (width, height);
+        self.update();
+    }
+
+    pub fn set_color(&mut self, color: (u8, u8, u8, f32)) {
+        self.props.color = color;
+        self.update();
+    }
+
+    pub fn set_mode(&mut self, mode: SliceMode) {
+        self.props.mode = mode;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        self.vertices = generate_vertices(&self.props, self.texture.get_dimensions());
+    }
+}
+
+// one column or row of quads: (start, end) on screen and (start, end) in uvs
+type Span = ((f32, f32), (f32, f32));
+
+// splits one axis into the two borders and the stretched or tiled middle. Borders are scaled
+// down together when the slice is smaller than both of them
+fn axis_spans(pos: f32, size: f32, (inset_start, inset_end): (u32, u32), texture_size: u32, mode: SliceMode) -> Vec<Span> {
+    let texture_size = texture_size as f32;
+    let (inset_start, inset_end) = (inset_start as f32, inset_end as f32);
+    let borders = inset_start + inset_end;
+    let scale = if borders > size && borders > 0.0 { size / borders } else { 1.0 };
+    let start = pos + inset_start * scale;
+    let end = pos + size - inset_end * scale;
+    let uv_start = inset_start / texture_size;
+    let uv_end = 1.0 - inset_end / texture_size;
+    let source = texture_size - borders;
+    let mut spans = vec![((pos, start), (0.0, uv_start))];
+
+    match mode {
+        SliceMode::Tile if source > 0.0 => {
+            let mut tile_start = start;
+
+            while tile_start < end {
+                let tile_end = (tile_start + source).min(end);
+                let covered = (tile_end - tile_start) / texture_size;
+
+                spans.push(((tile_start, tile_end), (uv_start, uv_start + covered)));
+                tile_start = tile_end;
+            }
+        },
+        _ => spans.push(((start, end), (uv_start, uv_end))),
+    }
+
+    spans.push(((end, pos + size), (uv_end, 1.0)));
+
+    spans
+}
+
+fn generate_vertices(props: &NineSliceProps, (texture_width, texture_height): (u32, u32)) -> Vec<BasicVertex> {
+    let (x, y) = props.pos;
+    let (width, height) = props.size;
+    let insets = props.insets;
+    let color = normalize_color(props.color);
+    let columns = axis_spans(x, width, (insets.left, insets.right), texture_width, props.mode);
+    let rows = axis_spans(y, height, (insets.top, insets.bottom), texture_height, props.mode);
+    let mut vertices = Vec::with_capacity(columns.len() * rows.len() * 4);
+
+    for &((y1, y2), (v1, v2)) in &rows {
+        for &((x1, x2), (u1, u2)) in &columns {
+            vertices.extend_from_slice(&[
+                BasicVertex { pos: (x1, y1, 0.0), uv: (u1, v1), color },
+                BasicVertex { pos: (x2, y1, 0.0), uv: (u2, v1), color },
+                BasicVertex { pos: (x1, y2, 0.0), uv: (u1, v2), color },
+                BasicVertex { pos: (x2, y2, 0.0), uv: (u2, v2), color },
+            ]);
+        }
+    }
+
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stretch_keeps_border_size() {
+        let spans = axis_spans(10.0, 100.0, (4, 8), 32, SliceMode::Stretch);
+
+        assert_eq!(spans, vec![
+            ((10.0, 14.0), (0.0, 0.125)),
+            ((14.0, 102.0), (0.125, 0.75)),
+            ((102.0, 110.0), (0.75, 1.0)),
+        ]);
+    }
+
+    #[test]
+    fn tile_repeats_middle_and_cuts_the_last_tile() {
+        // 16 pixels of source between the borders, 36 pixels to fill
+        let spans = axis_spans(0.0, 52.0, (8, 8), 32, SliceMode::Tile);
+
+        assert_eq!(spans, vec![
+            ((0.0, 8.0), (0.0, 0.25)),
+            ((8.0, 24.0), (0.25, 0.75)),
+            ((24.0, 40.0), (0.25, 0.75)),
+            ((40.0, 44.0), (0.25, 0.375)),
+            ((44.0, 52.0), (0.75, 1.0)),
+        ]);
+    }
+
+    #[test]
+    fn borders_shrink_when_slice_is_too_small() {
+        let spans = axis_spans(0.0, 6.0, (4, 8), 32, SliceMode::Stretch);
+
+        assert_eq!(spans[0].0, (0.0, 2.0));
+        assert_eq!(spans[1].0, (2.0, 2.0));
+        assert_eq!(spans[2].0, (2.0, 6.0));
+    }
+
+    #[test]
+    fn stretch_emits_nine_quads() {
+        let props = NineSliceProps {
+            size: (64.0, 64.0),
+            insets: NineSliceInsets::uniform(4),
+            color: (255, 0, 0, 0.5),
+            ..Default::default()
+        };
+        let vertices = generate_vertices(&props, (16, 16));
+
+        assert_eq!(vertices.len(), 9 * 4);
+        assert!(vertices.iter().all(|vertex| vertex.color == (1.0, 0.0, 0.0, 0.5)));
+        assert_eq!(vertices[4 * 4 + 3].pos, (60.0, 60.0, 0.0));
+    }
+
+    #[test]
+    fn descriptor_mode_defaults_to_stretch() {
+        let descriptor: NineSliceDescriptor = serde_json::from_str(
+            r#"{ "image": "images/panel.png", "insets": { "left": 1, "right": 2, "top": 3, "bottom": 4 } }"#
+        ).unwrap();
+
+        assert_eq!(descriptor.mode, SliceMode::Stretch);
+        assert_eq!(descriptor.insets, NineSliceInsets { left: 1, right: 2, top: 3, bottom: 4 });
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Serialize, Deserialize};
+
+/// Values that a `Curve` can blend between
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for (f32, f32, f32, f32) {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        (self.0.lerp(other.0, t), self.1.lerp(other.1, t), self.2.lerp(other.2, t), self.3.lerp(other.3, t))
+    }
+}
+
+/// Value over a particle's life, from 0.0 when it is spawned to 1.0 when it dies. Points are
+/// `(life, value)` pairs, in JSON `[[0.0, 8.0], [1.0, 0.0]]`, linearly blended between
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<(f32, T)>", into = "Vec<(f32, T)>")]
+#[serde(bound(serialize = "T: Clone + Serialize", deserialize = "T: Lerp + Deserialize<'de>"))]
+pub struct Curve<T> {
+    points: Vec<(f32, T)>,
+}
+
+impl<T: Lerp> Curve<T> {
+    /// Points are sorted by their life
+    pub fn new(mut points: Vec<(f32, T)>) -> Curve<T> {
+        points.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        Curve { points }
+    }
+
+    pub fn constant(value: T) -> Curve<T> {
+        Curve::new(vec![(0.0, value)])
+    }
+
+    pub fn linear(start: T, end: T) -> Curve<T> {
+        Curve::new(vec![(0.0, start), (1.0, end)])
+    }
+
+    /// `None` for a curve without points
+    pub fn evaluate(&self, life: f32) -> Option<T> {
+        let after = self.points.iter().position(|(point_life, _)| *point_life > life);
+
+        match after {
+            Some(0) => self.points.first().map(|(_, value)| *value),
+            Some(i) => {
+                let (start_life, start) = self.points[i - 1];
+                let (end_life, end) = self.points[i];
+
+                Some(start.lerp(end, (life - start_life) / (end_life - start_life)))
+            },
+            None => self.points.last().map(|(_, value)| *value),
+        }
+    }
+}
+
+// loaded points go through `new` so they are sorted too
+impl<T: Lerp> From<Vec<(f32, T)>> for Curve<T> {
+    fn from(points: Vec<(f32, T)>) -> Curve<T> {
+        Curve::new(points)
+    }
+}
+
+impl<T> From<Curve<T>> for Vec<(f32, T)> {
+    fn from(curve: Curve<T>) -> Vec<(f32, T)> {
+        curve.points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_blend_between_points() {
+        let curve = Curve::new(vec![(1.0, 0.0), (0.0, 8.0), (0.5, 4.0)]);
+
+        assert_eq!(curve.evaluate(0.0), Some(8.0));
+        assert_eq!(curve.evaluate(0.25), Some(6.0));
+        assert_eq!(curve.evaluate(0.75), Some(2.0));
+        assert_eq!(curve.evaluate(2.0), Some(0.0));
+        assert_eq!(Curve::constant(3.0).evaluate(0.5), Some(3.0));
+        assert_eq!(Curve::<f32>::new(Vec::new()).evaluate(0.5), None);
+    }
+
+    #[test]
+    fn curves_load_from_pairs() {
+        let curve: Curve<(f32, f32, f32, f32)> = serde_json::from_str("[[0.0, [1.0, 1.0, 1.0, 1.0]], [1.0, [1.0, 0.0, 0.0, 0.0]]]").unwrap();
+
+        assert_eq!(curve.evaluate(0.5), Some((1.0, 0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn loaded_curves_are_sorted() {
+        let curve: Curve<f32> = serde_json::from_str("[[1.0, 0.0], [0.0, 8.0], [0.5, 4.0]]").unwrap();
+
+        assert_eq!(curve, Curve::new(vec![(0.0, 8.0), (0.5, 4.0), (1.0, 0.0)]));
+        assert_eq!(curve.evaluate(0.25), Some(6.0));
+        assert_eq!(serde_json::to_string(&curve).unwrap(), "[[0.0,8.0],[0.5,4.0],[1.0,0.0]]");
+    }
+}
@@ -0,0 +1,481 @@
+//! Particle emitters simulated on the cpu and drawn as quads by the batch renderer. An `Emitter`
+//! is a `Renderable2D`, so it is submitted like a sprite with its blend mode:
+//! `renderer.submit_with_blend(&emitter, emitter.get_blend_mode())`. Simulation only depends on
+//! the seed and the time steps, so it runs without a gl context.
+
+mod curve;
+mod rng;
+
+pub use curve::{Curve, Lerp};
+pub use rng::{Rng};
+
+use serde::{Serialize, Deserialize};
+
+use crate::resources::*;
+use crate::textures::texture::{Texture};
+use crate::renderer::blend_mode::{BlendMode};
+use crate::renderer::renderable::{Renderable2D, BasicVertex, VertexSink};
+
+/// `count` particles at once, `time` seconds after the emitter started
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Burst {
+    pub time: f32,
+    pub count: u32,
+}
+
+/// How particles pick from `EmitterConfig::frames`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameMode {
+    /// One random frame for the particle's whole life
+    #[default]
+    Random,
+    /// Goes through the frames in order over the particle's life, like an animation
+    OverLife,
+}
+
+/// Everything about how an emitter spawns and moves its particles. Ranges are `(min, max)`,
+/// angles are in radians and times in seconds. Loadable from JSON with `EmitterConfig::from_json`,
+/// missing fields keep their default, e.g.
+/// `{ "rate": 40.0, "lifetime": [0.5, 1.0], "speed": [50.0, 80.0], "spread": 0.3, "blend_mode": "additive" }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterConfig {
+    /// Particles spawned every second while emitting
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    /// Seconds the emitter emits for, forever when `None`
+    pub duration: Option<f32>,
+    /// Starts emitting again, bursts included, after `duration`
+    pub looping: bool,
+    pub max_particles: usize,
+    pub lifetime: (f32, f32),
+    /// Particles start this far from the emitter at most
+    pub spawn_radius: f32,
+    pub speed: (f32, f32),
+    pub direction: f32,
+    /// Particles go up to this far either side of `direction`
+    pub spread: f32,
+    pub gravity: (f32, f32),
+    /// Part of the velocity lost every second
+    pub drag: f32,
+    pub rotation: (f32, f32),
+    pub angular_velocity: (f32, f32),
+    pub color: Curve<(f32, f32, f32, f32)>,
+    /// Width and height of the particles
+    pub size: Curve<f32>,
+    /// Parts of the texture (x, y, width, height) in pixels, the whole texture when empty
+    pub frames: Vec<(u32, u32, u32, u32)>,
+    pub frame_mode: FrameMode,
+    pub blend_mode: BlendMode,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> EmitterConfig {
+        EmitterConfig {
+            rate: 10.0,
+            bursts: Vec::new(),
+            duration: None,
+            looping: false,
+            max_particles: 1000,
+            lifetime: (1.0, 1.0),
+            spawn_radius: 0.0,
+            speed: (50.0, 50.0),
+            direction: -std::f32::consts::FRAC_PI_2,
+            spread: std::f32::consts::PI,
+            gravity: (0.0, 0.0),
+            drag: 0.0,
+            rotation: (0.0, 0.0),
+            angular_velocity: (0.0, 0.0),
+            color: Curve::constant((1.0, 1.0, 1.0, 1.0)),
+            size: Curve::constant(8.0),
+            frames: Vec::new(),
+            frame_mode: FrameMode::default(),
+            blend_mode: BlendMode::default(),
+        }
+    }
+}
+
+impl EmitterConfig {
+    pub fn from_json(res: &Resources, file_path: &str) -> Result<EmitterConfig, failure::Error> {
+        let json = res.load_from_json(file_path)?;
+
+        Ok(serde_json::from_value(json)?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub pos: (f32, f32),
+    pub velocity: (f32, f32),
+    pub rotation: f32,
+    pub angular_velocity: f32,
+    pub age: f32,
+    pub lifetime: f32,
+    pub frame: usize,
+}
+
+impl Particle {
+    /// 0.0 when spawned to 1.0 when it dies
+    pub fn life(&self) -> f32 {
+        (self.age / self.lifetime).min(1.0)
+    }
+}
+
+/// Spawns, moves and draws particles following an `EmitterConfig`
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    config: EmitterConfig,
+    pos: (f32, f32),
+    rng: Rng,
+    particles: Vec<Particle>,
+    elapsed: f32,
+    emit_remainder: f32,
+    emitting: bool,
+    texture_handle: u32,
+    texture_size: (u32, u32),
+}
+
+impl Renderable2D for Emitter {
+    fn texture(&self) -> u32 {
+        self.texture_handle
+    }
+
+    fn write_vertices(&self, out: &mut VertexSink) {
+        for particle in &self.particles {
+            out.extend(&self.particle_vertices(particle));
+        }
+    }
+}
+
+impl Emitter {
+    /// Untextured until `set_texture`, particles are then drawn as coloured squares
+    pub fn new(config: EmitterConfig, seed: u64) -> Emitter {
+        Emitter {
+            config,
+            pos: (0.0, 0.0),
+            rng: Rng::new(seed),
+            particles: Vec::new(),
+            elapsed: 0.0,
+            emit_remainder: 0.0,
+            emitting: true,
+            texture_handle: 0,
+            texture_size: (1, 1),
+        }
+    }
+
+    pub fn from_json(res: &Resources, file_path: &str, seed: u64) -> Result<Emitter, failure::Error> {
+        Ok(Emitter::new(EmitterConfig::from_json(res, file_path)?, seed))
+    }
+
+    /// Draws the particles with `texture`, which has to outlive the emitter
+    pub fn set_texture(&mut self, texture: &Texture) {
+        self.texture_handle = texture.get_texture_handle();
+        self.texture_size = texture.get_dimensions();
+    }
+
+    pub fn get_config(&self) -> &EmitterConfig {
+        &self.config
+    }
+
+    /// Changes apply to particles spawned after this
+    pub fn get_config_mut(&mut self) -> &mut EmitterConfig {
+        &mut self.config
+    }
+
+    pub fn get_blend_mode(&self) -> BlendMode {
+        self.config.blend_mode
+    }
+
+    pub fn get_pos(&self) -> (f32, f32) {
+        self.pos
+    }
+
+    /// Only new particles spawn at the new position, the ones already out stay where they are
+    pub fn set_pos(&mut self, x: f32, y: f32) {
+        self.pos = (x, y);
+    }
+
+    pub fn get_particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn is_emitting(&self) -> bool {
+        self.emitting
+    }
+
+    /// Starts emitting from the beginning of the emitter's duration again
+    pub fn start(&mut self) {
+        self.emitting = true;
+        self.elapsed = 0.0;
+        self.emit_remainder = 0.0;
+    }
+
+    /// Stops spawning, the particles already out live out their lifetime
+    pub fn stop(&mut self) {
+        self.emitting = false;
+    }
+
+    /// Done emitting and every particle has died
+    pub fn is_finished(&self) -> bool {
+        !self.emitting && self.particles.is_empty()
+    }
+
+    /// Spawns `count` particles right away, even when stopped
+    pub fn burst(&mut self, count: u32) {
+        for _ in 0..count {
+            self.spawn();
+        }
+    }
+
+    /// Moves the simulation on by `delta_time` seconds
+    pub fn update(&mut self, delta_time: f32) {
+        let (gravity_x, gravity_y) = self.config.gravity;
+        let drag = (1.0 - self.config.drag * delta_time).max(0.0);
+
+        for particle in &mut self.particles {
+            particle.age += delta_time;
+            particle.velocity.0 = (particle.velocity.0 + gravity_x * delta_time) * drag;
+            particle.velocity.1 = (particle.velocity.1 + gravity_y * delta_time) * drag;
+            particle.pos.0 += particle.velocity.0 * delta_time;
+            particle.pos.1 += particle.velocity.1 * delta_time;
+            particle.rotation += particle.angular_velocity * delta_time;
+        }
+
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+
+        if self.emitting {
+            self.emit(delta_time);
+        }
+    }
+
+    // spawns the particles from the rate and the bursts in the next `delta_time` seconds
+    fn emit(&mut self, delta_time: f32) {
+        let start = self.elapsed;
+        let end = match self.config.duration {
+            Some(duration) => (start + delta_time).min(duration),
+            None => start + delta_time,
+        };
+        let burst_count: u32 = self.config.bursts.iter()
+            .filter(|burst| burst.time >= start && burst.time < end)
+            .map(|burst| burst.count)
+            .sum();
+
+        self.emit_remainder += self.config.rate * (end - start).max(0.0);
+
+        let rate_count = self.emit_remainder.floor();
+
+        self.emit_remainder -= rate_count;
+        self.burst(burst_count + rate_count as u32);
+        self.elapsed = end;
+
+        if let Some(duration) = self.config.duration {
+            if self.elapsed >= duration {
+                if self.config.looping {
+                    self.elapsed = 0.0;
+                } else {
+                    self.emitting = false;
+                }
+            }
+        }
+    }
+
+    fn spawn(&mut self) {
+        if self.particles.len() >= self.config.max_particles {
+            return;
+        }
+
+        let config = &self.config;
+        let rng = &mut self.rng;
+        let spawn_angle = rng.range((0.0, std::f32::consts::PI * 2.0));
+        // square root so particles are spread evenly over the circle instead of bunching in the middle
+        let spawn_distance = config.spawn_radius * rng.next_f32().sqrt();
+        let angle = config.direction + rng.range((-config.spread, config.spread));
+        let speed = rng.range(config.speed);
+        let particle = Particle {
+            pos: (self.pos.0 + spawn_angle.cos() * spawn_distance, self.pos.1 + spawn_angle.sin() * spawn_distance),
+            velocity: (angle.cos() * speed, angle.sin() * speed),
+            rotation: rng.range(config.rotation),
+            angular_velocity: rng.range(config.angular_velocity),
+            age: 0.0,
+            lifetime: rng.range(config.lifetime).max(0.0001),
+            frame: match config.frame_mode {
+                FrameMode::Random if !config.frames.is_empty() => rng.next_u32() as usize % config.frames.len(),
+                _ => 0,
+            },
+        };
+
+        self.particles.push(particle);
+    }
+
+    // uvs of the part of the texture a particle shows
+    fn frame_uvs(&self, particle: &Particle) -> [(f32, f32); 2] {
+        let frames = &self.config.frames;
+        let frame = match self.config.frame_mode {
+            FrameMode::OverLife => ((particle.life() * frames.len() as f32) as usize).min(frames.len().saturating_sub(1)),
+            FrameMode::Random => particle.frame,
+        };
+        let (width, height) = (self.texture_size.0.max(1) as f32, self.texture_size.1.max(1) as f32);
+
+        match frames.get(frame) {
+            Some(&(x, y, frame_width, frame_height)) => [
+                (x as f32 / width, y as f32 / height),
+                ((x + frame_width) as f32 / width, (y + frame_height) as f32 / height),
+            ],
+            None => [(0.0, 0.0), (1.0, 1.0)],
+        }
+    }
+
+    // a quad around the particle's position: top left, top right, bottom left, bottom right
+    fn particle_vertices(&self, particle: &Particle) -> [BasicVertex; 4] {
+        let life = particle.life();
+        let half_size = self.config.size.evaluate(life).unwrap_or(0.0) / 2.0;
+        let color = self.config.color.evaluate(life).unwrap_or((1.0, 1.0, 1.0, 1.0));
+        let [(u, v), (u2, v2)] = self.frame_uvs(particle);
+        let (sin, cos) = particle.rotation.sin_cos();
+        let corners = [
+            (-half_size, -half_size, u, v),
+            (half_size, -half_size, u2, v),
+            (-half_size, half_size, u, v2),
+            (half_size, half_size, u2, v2),
+        ];
+
+        corners.map(|(x, y, tu, tv)| BasicVertex {
+            pos: (particle.pos.0 + x * cos - y * sin, particle.pos.1 + x * sin + y * cos, 0.0),
+            uv: (tu, tv),
+            color,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(emitter: &Emitter) -> Vec<(f32, f32)> {
+        emitter.get_particles().iter().map(|particle| particle.pos).collect()
+    }
+
+    #[test]
+    fn same_seed_same_simulation() {
+        let config = EmitterConfig { rate: 30.0, spawn_radius: 4.0, gravity: (0.0, 98.0), ..Default::default() };
+        let mut a = Emitter::new(config.clone(), 42);
+        let mut b = Emitter::new(config.clone(), 42);
+        let mut c = Emitter::new(config, 43);
+
+        for _ in 0..20 {
+            a.update(1.0 / 60.0);
+            b.update(1.0 / 60.0);
+            c.update(1.0 / 60.0);
+        }
+
+        assert!(!a.get_particles().is_empty());
+        assert_eq!(positions(&a), positions(&b));
+        assert_ne!(positions(&a), positions(&c));
+    }
+
+    #[test]
+    fn rate_carries_over_between_updates() {
+        let mut emitter = Emitter::new(EmitterConfig { rate: 10.0, lifetime: (10.0, 10.0), ..Default::default() }, 0);
+
+        for _ in 0..4 {
+            emitter.update(0.05);
+        }
+
+        assert_eq!(emitter.get_particles().len(), 2);
+    }
+
+    #[test]
+    fn bursts_fire_once_inside_the_duration() {
+        let config = EmitterConfig {
+            rate: 0.0,
+            bursts: vec![Burst { time: 0.0, count: 5 }, Burst { time: 0.5, count: 3 }, Burst { time: 2.0, count: 100 }],
+            duration: Some(1.0),
+            lifetime: (10.0, 10.0),
+            ..Default::default()
+        };
+        let mut emitter = Emitter::new(config, 0);
+
+        emitter.update(0.25);
+        assert_eq!(emitter.get_particles().len(), 5);
+
+        emitter.update(0.5);
+        emitter.update(0.5);
+        emitter.update(0.5);
+        assert_eq!(emitter.get_particles().len(), 8);
+        assert!(!emitter.is_emitting());
+    }
+
+    #[test]
+    fn particles_die_after_their_lifetime() {
+        let mut emitter = Emitter::new(EmitterConfig { rate: 0.0, lifetime: (0.5, 1.0), ..Default::default() }, 0);
+
+        emitter.burst(10);
+        emitter.stop();
+        emitter.update(0.4);
+        assert_eq!(emitter.get_particles().len(), 10);
+
+        emitter.update(0.7);
+        assert!(emitter.is_finished());
+    }
+
+    #[test]
+    fn gravity_and_drag_change_velocity() {
+        let config = EmitterConfig { rate: 0.0, speed: (0.0, 0.0), gravity: (0.0, 10.0), drag: 0.5, ..Default::default() };
+        let mut emitter = Emitter::new(config, 0);
+
+        emitter.burst(1);
+        emitter.update(0.1);
+
+        let particle = emitter.get_particles()[0];
+
+        assert!((particle.velocity.1 - 0.95).abs() < 0.0001);
+        assert!((particle.pos.1 - 0.095).abs() < 0.0001);
+    }
+
+    #[test]
+    fn quads_follow_size_color_and_frames() {
+        let config = EmitterConfig {
+            rate: 0.0,
+            speed: (0.0, 0.0),
+            size: Curve::linear(4.0, 0.0),
+            color: Curve::linear((1.0, 1.0, 1.0, 1.0), (1.0, 0.0, 0.0, 0.0)),
+            frames: vec![(0, 0, 8, 8), (8, 0, 8, 8)],
+            frame_mode: FrameMode::OverLife,
+            ..Default::default()
+        };
+        let mut emitter = Emitter::new(config, 0);
+
+        emitter.texture_size = (16, 8);
+        emitter.set_pos(10.0, 10.0);
+        emitter.burst(1);
+
+        let particle = emitter.get_particles()[0];
+        let vertices = emitter.particle_vertices(&particle);
+
+        assert_eq!(vertices[0].pos, (8.0, 8.0, 0.0));
+        assert_eq!(vertices[3].pos, (12.0, 12.0, 0.0));
+        assert_eq!((vertices[0].uv, vertices[3].uv), ((0.0, 0.0), (0.5, 1.0)));
+
+        emitter.update(0.75);
+
+        let vertices = emitter.particle_vertices(&emitter.get_particles()[0]);
+
+        assert_eq!(vertices[0].pos, (9.5, 9.5, 0.0));
+        assert_eq!(vertices[0].color, (1.0, 0.25, 0.25, 0.25));
+        assert_eq!((vertices[0].uv, vertices[3].uv), ((0.5, 0.0), (1.0, 1.0)));
+    }
+
+    #[test]
+    fn configs_load_with_defaults() {
+        let config: EmitterConfig = serde_json::from_str(
+            r#"{ "rate": 40.0, "lifetime": [0.5, 1.0], "blend_mode": "additive", "frame_mode": "over_life" }"#
+        ).unwrap();
+
+        assert_eq!(config.rate, 40.0);
+        assert_eq!(config.lifetime, (0.5, 1.0));
+        assert_eq!(config.blend_mode, BlendMode::Additive);
+        assert_eq!(config.frame_mode, FrameMode::OverLife);
+        assert_eq!(config.max_particles, EmitterConfig::default().max_particles);
+    }
+}
@@ -0,0 +1,64 @@
+/// Small seeded random number generator (xorshift64*), so emitters with the same seed
+/// always simulate the same particles
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift never leaves a zero state, so the seed is mixed into a non zero one
+        let state = (seed ^ 0x9E37_79B9_7F4A_7C15).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+
+        Rng { state: if state == 0 { 0x2545_F491_4F6C_DD1D } else { state } }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+
+    /// Between 0.0 and 1.0, never 1.0
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Between `min` and `max`, `min` when they are the same
+    pub fn range(&mut self, (min, max): (f32, f32)) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+        let a_numbers: Vec<u32> = (0..8).map(|_| a.next_u32()).collect();
+        let b_numbers: Vec<u32> = (0..8).map(|_| b.next_u32()).collect();
+        let c_numbers: Vec<u32> = (0..8).map(|_| c.next_u32()).collect();
+
+        assert_eq!(a_numbers, b_numbers);
+        assert_ne!(a_numbers, c_numbers);
+    }
+
+    #[test]
+    fn ranges_stay_inside() {
+        let mut rng = Rng::new(0);
+
+        for _ in 0..1000 {
+            let value = rng.range((-2.0, 3.0));
+
+            assert!((-2.0..3.0).contains(&value));
+        }
+
+        assert_eq!(rng.range((1.5, 1.5)), 1.5);
+    }
+}
@@ -0,0 +1,431 @@
+//! Ready made effects for `PostProcessChain::add_effect`. Parameters are public, change them and call
+//! `PostProcessChain::update_effect` to push them to the chain. Each effect is named after itself
+//! (e.g. "vignette") so only one of each kind can be in a chain at once.
+
+mod postfx_shaders;
+
+use crate::helpers::{UniformValue};
+use crate::renderer::post_process::{PostEffect, PostProcessPass};
+use crate::textures::texture::{Texture};
+use postfx_shaders::*;
+
+fn color_uniform(color: (f32, f32, f32, f32)) -> UniformValue {
+    let (r, g, b, a) = color;
+
+    UniformValue::Vec4(r, g, b, a)
+}
+
+/// Blurs everything brighter than `threshold` and adds it back over the scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub intensity: f32,
+    pub blur_radius: f32,
+}
+
+impl Bloom {
+    pub const NAME: &'static str = "bloom";
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 0.7,
+            intensity: 1.0,
+            blur_radius: 2.0,
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        Bloom::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        let threshold = PostProcessPass::from_fragment_source("bloom/threshold", BLOOM_THRESHOLD_SOURCE)?;
+        let mut blur_h = PostProcessPass::from_fragment_source("bloom/blur_h", BLUR_SOURCE)?;
+        let mut blur_v = PostProcessPass::from_fragment_source("bloom/blur_v", BLUR_SOURCE)?;
+        let mut combine = PostProcessPass::from_fragment_source("bloom/combine", BLOOM_COMBINE_SOURCE)?;
+
+        blur_h.set_uniform("Direction", UniformValue::Vec2(1.0, 0.0));
+        blur_v.set_uniform("Direction", UniformValue::Vec2(0.0, 1.0));
+        // combines with the scene as it was before any post processing, so bloom should be added first
+        combine.use_scene_texture(true);
+
+        Ok(vec![threshold, blur_h, blur_v, combine])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("Threshold", UniformValue::Float(self.threshold)),
+            ("Intensity", UniformValue::Float(self.intensity)),
+            ("Radius", UniformValue::Float(self.blur_radius)),
+        ]
+    }
+}
+
+/// Darkens (or tints with `color`) the edges of the screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vignette {
+    pub intensity: f32,
+    /// Distance from the centre where the vignette starts, 1.0 is the corners
+    pub radius: f32,
+    pub softness: f32,
+    pub color: (f32, f32, f32, f32),
+}
+
+impl Vignette {
+    pub const NAME: &'static str = "vignette";
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            intensity: 0.8,
+            radius: 0.5,
+            softness: 0.5,
+            color: (0.0, 0.0, 0.0, 1.0),
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        Vignette::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Vignette::NAME, VIGNETTE_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("Intensity", UniformValue::Float(self.intensity)),
+            ("Radius", UniformValue::Float(self.radius)),
+            ("Softness", UniformValue::Float(self.softness)),
+            ("VignetteColor", color_uniform(self.color)),
+        ]
+    }
+}
+
+/// Old monitor look with curved edges and scanlines
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crt {
+    pub curvature: f32,
+    pub scanline_intensity: f32,
+    /// Number of scanlines over the height of the screen, 0.0 uses one for every two pixels
+    pub scanline_count: f32,
+}
+
+impl Crt {
+    pub const NAME: &'static str = "crt";
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            curvature: 0.1,
+            scanline_intensity: 0.3,
+            scanline_count: 0.0,
+        }
+    }
+}
+
+impl PostEffect for Crt {
+    fn name(&self) -> &str {
+        Crt::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Crt::NAME, CRT_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("Curvature", UniformValue::Float(self.curvature)),
+            ("ScanlineIntensity", UniformValue::Float(self.scanline_intensity)),
+            ("ScanlineCount", UniformValue::Float(self.scanline_count)),
+        ]
+    }
+}
+
+/// Splits the red and blue channels apart towards the edges of the screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChromaticAberration {
+    /// Offset in pixels at the edges of the screen
+    pub offset: f32,
+}
+
+impl ChromaticAberration {
+    pub const NAME: &'static str = "chromatic_aberration";
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        ChromaticAberration {
+            offset: 3.0,
+        }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn name(&self) -> &str {
+        ChromaticAberration::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(ChromaticAberration::NAME, CHROMATIC_ABERRATION_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("Offset", UniformValue::Float(self.offset)),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grayscale {
+    pub intensity: f32,
+}
+
+impl Grayscale {
+    pub const NAME: &'static str = "grayscale";
+
+    fn color_matrix() -> glm::TMat3<f32> {
+        glm::mat3(
+            0.2126, 0.7152, 0.0722,
+            0.2126, 0.7152, 0.0722,
+            0.2126, 0.7152, 0.0722,
+        )
+    }
+}
+
+impl Default for Grayscale {
+    fn default() -> Self {
+        Grayscale {
+            intensity: 1.0,
+        }
+    }
+}
+
+impl PostEffect for Grayscale {
+    fn name(&self) -> &str {
+        Grayscale::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Grayscale::NAME, COLOR_MATRIX_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("ColorMatrix", UniformValue::Mat3(Grayscale::color_matrix())),
+            ("Intensity", UniformValue::Float(self.intensity)),
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sepia {
+    pub intensity: f32,
+}
+
+impl Sepia {
+    pub const NAME: &'static str = "sepia";
+
+    fn color_matrix() -> glm::TMat3<f32> {
+        glm::mat3(
+            0.393, 0.769, 0.189,
+            0.349, 0.686, 0.168,
+            0.272, 0.534, 0.131,
+        )
+    }
+}
+
+impl Default for Sepia {
+    fn default() -> Self {
+        Sepia {
+            intensity: 1.0,
+        }
+    }
+}
+
+impl PostEffect for Sepia {
+    fn name(&self) -> &str {
+        Sepia::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Sepia::NAME, COLOR_MATRIX_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("ColorMatrix", UniformValue::Mat3(Sepia::color_matrix())),
+            ("Intensity", UniformValue::Float(self.intensity)),
+        ]
+    }
+}
+
+/// Remaps colours through a lookup table laid out as a horizontal strip of `lut_size` slices
+/// (e.g. 256x16 for a 16 colour LUT), red increasing left to right within a slice, green top to
+/// bottom and blue by slice. The LUT texture is not owned and has to outlive the effect's passes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorGrade {
+    pub lut_texture: gl::types::GLuint,
+    pub lut_size: f32,
+    pub intensity: f32,
+}
+
+impl ColorGrade {
+    pub const NAME: &'static str = "color_grade";
+
+    pub fn from_texture(lut: &Texture) -> ColorGrade {
+        let (_, height) = lut.get_dimensions();
+
+        ColorGrade {
+            lut_texture: lut.get_texture_handle(),
+            lut_size: height as f32,
+            intensity: 1.0,
+        }
+    }
+}
+
+impl PostEffect for ColorGrade {
+    fn name(&self) -> &str {
+        ColorGrade::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(ColorGrade::NAME, COLOR_GRADE_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("LutSize", UniformValue::Float(self.lut_size)),
+            ("Intensity", UniformValue::Float(self.intensity)),
+        ]
+    }
+
+    fn textures(&self) -> Vec<(&'static str, gl::types::GLuint)> {
+        vec![("Lut", self.lut_texture)]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pixelate {
+    /// Size in screen pixels of each block
+    pub pixel_size: f32,
+}
+
+impl Pixelate {
+    pub const NAME: &'static str = "pixelate";
+}
+
+impl Default for Pixelate {
+    fn default() -> Self {
+        Pixelate {
+            pixel_size: 4.0,
+        }
+    }
+}
+
+impl PostEffect for Pixelate {
+    fn name(&self) -> &str {
+        Pixelate::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Pixelate::NAME, PIXELATE_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("PixelSize", UniformValue::Float(self.pixel_size)),
+        ]
+    }
+}
+
+/// Fades the screen to `color`, animate `amount` from 0.0 to 1.0 for scene transitions
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fade {
+    pub color: (f32, f32, f32, f32),
+    pub amount: f32,
+}
+
+impl Fade {
+    pub const NAME: &'static str = "fade";
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Fade {
+            color: (0.0, 0.0, 0.0, 1.0),
+            amount: 0.0,
+        }
+    }
+}
+
+impl PostEffect for Fade {
+    fn name(&self) -> &str {
+        Fade::NAME
+    }
+
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error> {
+        Ok(vec![PostProcessPass::from_fragment_source(Fade::NAME, FADE_SOURCE)?])
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)> {
+        vec![
+            ("FadeColor", color_uniform(self.color)),
+            ("Amount", UniformValue::Float(self.amount)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uniform<'a>(uniforms: &'a [(&'static str, UniformValue)], name: &str) -> Option<&'a UniformValue> {
+        uniforms.iter().find(|(uniform_name, _)| *uniform_name == name).map(|(_, value)| value)
+    }
+
+    #[test]
+    fn effect_uniforms_follow_parameters() {
+        let fade = Fade { color: (1.0, 0.5, 0.0, 1.0), amount: 0.25 };
+        let uniforms = fade.uniforms();
+
+        assert_eq!(uniform(&uniforms, "FadeColor"), Some(&UniformValue::Vec4(1.0, 0.5, 0.0, 1.0)));
+        assert_eq!(uniform(&uniforms, "Amount"), Some(&UniformValue::Float(0.25)));
+
+        let bloom = Bloom { threshold: 0.9, ..Bloom::default() };
+
+        assert_eq!(uniform(&bloom.uniforms(), "Threshold"), Some(&UniformValue::Float(0.9)));
+    }
+
+    #[test]
+    fn grayscale_matrix_keeps_white_white() {
+        let white = Grayscale::color_matrix() * glm::vec3(1.0, 1.0, 1.0);
+
+        assert!(glm::distance(&white, &glm::vec3(1.0, 1.0, 1.0)) < 0.0001);
+    }
+
+    #[test]
+    fn sepia_matrix_tints_gray_brown() {
+        let tinted = Sepia::color_matrix() * glm::vec3(0.5, 0.5, 0.5);
+
+        assert!(tinted.x > tinted.y);
+        assert!(tinted.y > tinted.z);
+    }
+
+    #[test]
+    fn color_grade_exposes_lut_texture() {
+        let grade = ColorGrade { lut_texture: 7, lut_size: 16.0, intensity: 1.0 };
+
+        assert_eq!(grade.textures(), vec![("Lut", 7)]);
+        assert_eq!(uniform(&grade.uniforms(), "LutSize"), Some(&UniformValue::Float(16.0)));
+    }
+}
@@ -0,0 +1,251 @@
+pub const BLOOM_THRESHOLD_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform float Threshold;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec4 color = texture(RenderTexture, IN.UV);
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - Threshold, 0.0) / max(brightness, 0.0001);
+
+    Color = vec4(color.rgb * contribution, 1.0);
+}
+"#;
+
+pub const BLUR_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform vec2 Resolution;
+uniform vec2 Direction;
+uniform float Radius;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+    vec2 step = Direction * Radius / Resolution;
+    vec3 result = texture(RenderTexture, IN.UV).rgb * WEIGHTS[0];
+
+    for (int i = 1; i < 5; i++) {
+        result += texture(RenderTexture, IN.UV + step * float(i)).rgb * WEIGHTS[i];
+        result += texture(RenderTexture, IN.UV - step * float(i)).rgb * WEIGHTS[i];
+    }
+
+    Color = vec4(result, 1.0);
+}
+"#;
+
+pub const BLOOM_COMBINE_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform sampler2D SceneTexture;
+uniform float Intensity;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec4 scene = texture(SceneTexture, IN.UV);
+    vec3 bloom = texture(RenderTexture, IN.UV).rgb;
+
+    Color = vec4(scene.rgb + bloom * Intensity, scene.a);
+}
+"#;
+
+pub const VIGNETTE_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform float Intensity;
+uniform float Radius;
+uniform float Softness;
+uniform vec4 VignetteColor;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec4 color = texture(RenderTexture, IN.UV);
+    float distance = length(IN.UV - vec2(0.5)) * 1.41421356;
+    float amount = smoothstep(Radius, Radius + Softness, distance) * Intensity * VignetteColor.a;
+
+    Color = vec4(mix(color.rgb, VignetteColor.rgb, amount), color.a);
+}
+"#;
+
+pub const CRT_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform vec2 Resolution;
+uniform float Curvature;
+uniform float ScanlineIntensity;
+uniform float ScanlineCount;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec2 centered = IN.UV * 2.0 - 1.0;
+    vec2 offset = centered.yx * centered.yx * Curvature;
+    vec2 uv = (centered + centered * offset) * 0.5 + 0.5;
+
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        Color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec4 color = texture(RenderTexture, uv);
+    float lines = ScanlineCount > 0.0 ? ScanlineCount : Resolution.y * 0.5;
+    float scanline = 0.5 + 0.5 * sin(uv.y * lines * 6.28318530);
+
+    Color = vec4(color.rgb * (1.0 - ScanlineIntensity * (1.0 - scanline)), color.a);
+}
+"#;
+
+pub const CHROMATIC_ABERRATION_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform vec2 Resolution;
+uniform float Offset;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec2 direction = IN.UV - vec2(0.5);
+    vec2 shift = direction * 2.0 * Offset / Resolution;
+    vec4 color = texture(RenderTexture, IN.UV);
+
+    Color = vec4(
+        texture(RenderTexture, IN.UV + shift).r,
+        color.g,
+        texture(RenderTexture, IN.UV - shift).b,
+        color.a
+    );
+}
+"#;
+
+pub const COLOR_MATRIX_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform mat3 ColorMatrix;
+uniform float Intensity;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec4 color = texture(RenderTexture, IN.UV);
+    vec3 filtered = clamp(ColorMatrix * color.rgb, 0.0, 1.0);
+
+    Color = vec4(mix(color.rgb, filtered, Intensity), color.a);
+}
+"#;
+
+pub const COLOR_GRADE_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform sampler2D Lut;
+uniform float LutSize;
+uniform float Intensity;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+vec3 lookup(vec3 color) {
+    float blue = color.b * (LutSize - 1.0);
+    float slice = floor(blue);
+    float next_slice = min(slice + 1.0, LutSize - 1.0);
+    float x = (color.r * (LutSize - 1.0) + 0.5) / (LutSize * LutSize);
+    float y = (color.g * (LutSize - 1.0) + 0.5) / LutSize;
+
+    vec3 a = texture(Lut, vec2(x + slice / LutSize, y)).rgb;
+    vec3 b = texture(Lut, vec2(x + next_slice / LutSize, y)).rgb;
+
+    return mix(a, b, blue - slice);
+}
+
+void main() {
+    vec4 color = texture(RenderTexture, IN.UV);
+    vec3 graded = lookup(clamp(color.rgb, 0.0, 1.0));
+
+    Color = vec4(mix(color.rgb, graded, Intensity), color.a);
+}
+"#;
+
+pub const PIXELATE_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform vec2 Resolution;
+uniform float PixelSize;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec2 cell = vec2(max(PixelSize, 1.0)) / Resolution;
+    vec2 uv = (floor(IN.UV / cell) + 0.5) * cell;
+
+    Color = texture(RenderTexture, uv);
+}
+"#;
+
+pub const FADE_SOURCE: &str = r#"
+#version 330 core
+
+uniform sampler2D RenderTexture;
+uniform vec4 FadeColor;
+uniform float Amount;
+
+in VS_OUTPUT {
+    vec2 UV;
+} IN;
+
+out vec4 Color;
+
+void main() {
+    vec4 color = texture(RenderTexture, IN.UV);
+
+    Color = vec4(mix(color.rgb, FadeColor.rgb, clamp(Amount, 0.0, 1.0) * FadeColor.a), color.a);
+}
+"#;
@@ -1,7 +1,10 @@
 mod rect_shaders;
 
+use std::cell::OnceCell;
 use crate::helpers::{self, data, buffer};
 use crate::camera::{Camera};
+use crate::renderer::blend_mode::{BlendMode};
+use crate::renderer::renderable::{Renderable2D, BasicVertex, VertexSink};
 use rect_shaders::{VERTEX_SOURCE, FRAGMENT_SOURCE};
 
 #[derive(VertexAttribPointers)]
@@ -31,8 +34,9 @@ impl Default for RectangleProps {
     }
 }
 
+// the gl objects `Rectangle::render` draws with, batched rectangles never need them
 #[derive(Debug)]
-pub struct Rectangle {
+struct RectanglePipeline {
     program: helpers::Program,
     _vbo: buffer::ArrayBuffer,
     vao: buffer::VertexArray,
@@ -40,12 +44,10 @@ pub struct Rectangle {
     uniform_mvp: i32,
     uniform_color: i32,
     indicies: Vec<u32>,
-    props: RectangleProps,
-    model: glm::TMat4<f32>,
 }
 
-impl Rectangle {
-    pub fn new(props: &RectangleProps) -> Result<Rectangle, failure::Error> {
+impl RectanglePipeline {
+    fn new() -> Result<RectanglePipeline, failure::Error> {
         let shaders = vec![
             helpers::Shader::from_raw(&VERTEX_SOURCE, gl::VERTEX_SHADER)?,
             helpers::Shader::from_raw(&FRAGMENT_SOURCE, gl::FRAGMENT_SHADER)?,
@@ -54,20 +56,12 @@ impl Rectangle {
             .expect("Failed to create Rectangle Shader Program");
         let uniform_mvp = program.get_uniform_location("MVP")?;
         let uniform_color = program.get_uniform_location("Color")?;
-        let pos = props.pos;
-        //let (x, y) = pos;
-        let (x, y) = (0.0, 0.0); // TODO: this kinda feels weird. Not sure the best way to approach this?
-        let width = props.width;
-        let height = props.height;
-        let color = props.color;
-        let model = glm::translate(&glm::identity(), &glm::vec3(pos.0, pos.1, 0.0));
-        let x2 = x + (width as f32);
-        let y2 = y + (height as f32);
+        // a unit quad, the model scales it to the rectangle's size so it can change without a new vbo
         let vertices: Vec<Vertex> = vec![
-           Vertex { pos: (x, y, 0.0).into() }, // bottom right
-           Vertex { pos: (x2, y, 0.0).into() }, // bottom left
-           Vertex { pos: (x, y2, 0.0).into() }, // top
-           Vertex { pos: (x2, y2, 0.0).into() },
+           Vertex { pos: (0.0, 0.0, 0.0).into() }, // bottom right
+           Vertex { pos: (1.0, 0.0, 0.0).into() }, // bottom left
+           Vertex { pos: (0.0, 1.0, 0.0).into() }, // top
+           Vertex { pos: (1.0, 1.0, 0.0).into() },
         ];
         let indicies = vec![
             0, 1, 2,
@@ -92,7 +86,7 @@ impl Rectangle {
         vbo.unbind();
         vao.unbind();
 
-        Ok(Rectangle {
+        Ok(RectanglePipeline {
             program,
             _vbo: vbo,
             vao,
@@ -100,7 +94,28 @@ impl Rectangle {
             uniform_mvp,
             uniform_color,
             indicies,
-            model,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Rectangle {
+    pipeline: OnceCell<RectanglePipeline>,
+    props: RectangleProps,
+    model: glm::TMat4<f32>,
+}
+
+impl Rectangle {
+    /// Doesn't touch gl, the shader program `render` draws with is only built the first time it's called
+    pub fn new(props: &RectangleProps) -> Result<Rectangle, failure::Error> {
+        let pos = props.pos;
+        let width = props.width;
+        let height = props.height;
+        let color = props.color;
+
+        Ok(Rectangle {
+            pipeline: OnceCell::new(),
+            model: rectangle_model(pos, width, height),
             props: RectangleProps {
                 width,
                 height,
@@ -115,37 +130,141 @@ impl Rectangle {
     }
 
     pub fn set_pos(&mut self, x: f32, y: f32) {
-        let pos = glm::vec3(x, y, 0.0);
         self.props.pos = (x, y);
-        self.model = glm::translate(&glm::identity(), &pos);
+        self.update_model();
+    }
+
+    pub fn get_size(&self) -> (f32, f32) {
+        (self.props.width, self.props.height)
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.props.width = width;
+        self.update_model();
+    }
+
+    pub fn set_height(&mut self, height: f32) {
+        self.props.height = height;
+        self.update_model();
+    }
+
+    pub fn set_size(&mut self, width: f32, height: f32) {
+        self.props.width = width;
+        self.props.height = height;
+        self.update_model();
+    }
+
+    pub fn get_color(&self) -> (f32, f32, f32, f32) {
+        self.props.color
     }
 
     pub fn set_color(&mut self, color: (f32, f32, f32, f32)) {
         self.props.color = color;
     }
 
-    pub fn render(&self, camera: &Camera) {
+    fn update_model(&mut self) {
+        self.model = rectangle_model(self.props.pos, self.props.width, self.props.height);
+    }
+
+    /// Draws the rectangle straight away with its own shader program, fails when the program can't be built
+    pub fn render(&self, camera: &Camera) -> Result<(), failure::Error> {
+        let pipeline = match self.pipeline.get() {
+            Some(pipeline) => pipeline,
+            None => {
+                let pipeline = RectanglePipeline::new()?;
+
+                self.pipeline.get_or_init(|| pipeline)
+            },
+        };
         let model = self.model;
         let mvp = camera.get_projection() * camera.get_view() * model;
 
-        self.program.set_used();
-        self.program.set_uniform_4f(self.uniform_color, self.props.color);
-        self.program.set_uniform_mat4f(self.uniform_mvp, &mvp);
+        BlendMode::Alpha.apply();
+        pipeline.program.set_used();
+        pipeline.program.set_uniform_4f(pipeline.uniform_color, self.props.color);
+        pipeline.program.set_uniform_mat4f(pipeline.uniform_mvp, &mvp);
 
-        self.ibo.bind();
-        self.vao.bind();
+        pipeline.ibo.bind();
+        pipeline.vao.bind();
 
         unsafe {
             gl::DrawElements(
                 gl::TRIANGLES,
                 6,
                 gl::UNSIGNED_INT,
-                self.indicies.as_ptr() as *const gl::types::GLvoid
+                pipeline.indicies.as_ptr() as *const gl::types::GLvoid
             );
         }
 
-        self.vao.unbind();
-        self.ibo.unbind();
+        pipeline.vao.unbind();
+        pipeline.ibo.unbind();
+
+        Ok(())
+    }
+}
+
+
+/// Batched with the renderer's white texture, so it takes its colour straight from the vertices
+impl Renderable2D for Rectangle {
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&rectangle_vertices(&self.props));
     }
 }
 
+fn rectangle_model(pos: (f32, f32), width: f32, height: f32) -> glm::TMat4<f32> {
+    let model = glm::translate(&glm::identity(), &glm::vec3(pos.0, pos.1, 0.0));
+
+    glm::scale(&model, &glm::vec3(width, height, 1.0))
+}
+
+fn rectangle_vertices(props: &RectangleProps) -> [BasicVertex; 4] {
+    let (x, y) = props.pos;
+    let x2 = x + props.width;
+    let y2 = y + props.height;
+    let color = props.color;
+
+    [
+        BasicVertex { pos: (x, y, 0.0), uv: (0.0, 0.0), color },
+        BasicVertex { pos: (x2, y, 0.0), uv: (1.0, 0.0), color },
+        BasicVertex { pos: (x, y2, 0.0), uv: (0.0, 1.0), color },
+        BasicVertex { pos: (x2, y2, 0.0), uv: (1.0, 1.0), color },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertices_cover_rectangle() {
+        let props = RectangleProps {
+            width: 30.0,
+            height: 10.0,
+            pos: (5.0, 2.0),
+            color: (1.0, 0.0, 0.0, 1.0),
+        };
+        let positions: Vec<(f32, f32, f32)> = rectangle_vertices(&props).iter().map(|vertex| vertex.pos).collect();
+
+        assert_eq!(positions, vec![(5.0, 2.0, 0.0), (35.0, 2.0, 0.0), (5.0, 12.0, 0.0), (35.0, 12.0, 0.0)]);
+        assert!(rectangle_vertices(&props).iter().all(|vertex| vertex.color == props.color));
+    }
+
+    // no gl context in unit tests, only rendering it straight away needs one
+    #[test]
+    fn can_be_batched_without_gl() {
+        let rectangle = Rectangle::new(&RectangleProps::default()).unwrap();
+        let mut sink = VertexSink::new();
+
+        rectangle.write_vertices(&mut sink);
+
+        assert_eq!(sink.vertices().len(), 4);
+    }
+
+    #[test]
+    fn model_scales_unit_quad_to_size() {
+        let model = rectangle_model((5.0, 2.0), 30.0, 10.0);
+        let corner = model * glm::vec4(1.0, 1.0, 0.0, 1.0);
+
+        assert_eq!((corner.x, corner.y), (35.0, 12.0));
+    }
+}
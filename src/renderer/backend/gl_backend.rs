@@ -0,0 +1,338 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use image::{RgbaImage};
+
+use crate::helpers::{self, buffer, system};
+use crate::textures::{snapshot, texture};
+use super::{RenderBackend, BackendKind, BatchDraw};
+use super::super::{BatchVertex, STREAM_BATCHES, generate_texture_slots};
+use super::super::batch_mode::{BatchMode};
+use super::super::batch_shaders::{create_fragment_source, create_array_fragment_source, create_vertex_source, create_instanced_vertex_source};
+use super::super::blend_mode::{BlendMode};
+use super::super::instancing::{InstancedQuad};
+use super::super::mask::{MaskState};
+use super::super::material::{BatchUniforms};
+
+/// Draws batches with OpenGL into whatever framebuffer is bound, what `Renderer2D::new` uses
+#[derive(Debug)]
+pub struct GlBackend {
+    batch_mode: BatchMode,
+    max_textures: gl::types::GLint,
+    texture_slots: Vec<i32>,
+    program: helpers::Program,
+    uniforms: BatchUniforms,
+    instanced_program: helpers::Program,
+    instanced_uniforms: BatchUniforms,
+    instanced_quad: InstancedQuad,
+    vbo: buffer::StreamArrayBuffer,
+    vao: buffer::VertexArray,
+    ibo: buffer::StreamElementArrayBuffer,
+    // framebuffers of `create_render_target` by their colour texture, with their size
+    render_targets: HashMap<u32, (buffer::FrameBuffer, [i32; 4])>,
+    // the viewport to go back to when drawing into the default framebuffer again
+    screen_viewport: Option<[i32; 4]>,
+}
+
+impl GlBackend {
+    /// Batches of up to `max_sprites` quads, their textures bound the way `batch_mode` says
+    pub fn new(batch_mode: BatchMode, max_sprites: usize) -> Result<GlBackend, failure::Error> {
+        let max_buffer_size = ::std::mem::size_of::<BatchVertex>() * max_sprites * 4;
+        let max_index_size = ::std::mem::size_of::<[u32; 6]>() * max_sprites;
+        let stream_mode = buffer::StreamMode::detect();
+        let max_textures = system::SystemInfo::get_max_textures();
+        let vert_src = create_vertex_source();
+        let frag_src = match batch_mode {
+            BatchMode::TextureUnits => create_fragment_source(max_textures),
+            BatchMode::TextureArray { .. } => create_array_fragment_source(max_textures),
+        };
+        let shaders = [
+            helpers::Shader::from_raw(&vert_src, gl::VERTEX_SHADER)?,
+            helpers::Shader::from_raw(&frag_src, gl::FRAGMENT_SHADER)?,
+        ];
+        let program = helpers::Program::from_shaders(&shaders[..], "internal/shaders/batch")
+            .expect("Failed to load Batch Renderer Shader Program");
+        let uniforms = BatchUniforms::locate(&program);
+        let instanced_shaders = [
+            helpers::Shader::from_raw(&create_instanced_vertex_source(), gl::VERTEX_SHADER)?,
+            helpers::Shader::from_raw(&frag_src, gl::FRAGMENT_SHADER)?,
+        ];
+        let instanced_program = helpers::Program::from_shaders(&instanced_shaders[..], "internal/shaders/batch/instanced")
+            .expect("Failed to load Instanced Batch Renderer Shader Program");
+        let instanced_uniforms = BatchUniforms::locate(&instanced_program);
+
+        let vbo = buffer::StreamArrayBuffer::new(max_buffer_size * STREAM_BATCHES, stream_mode);
+        let vao = buffer::VertexArray::new();
+
+        vao.bind();
+        vbo.bind();
+
+        BatchVertex::vertex_attrib_pointers();
+
+        // the element buffer binding is part of the vao state, creating it binds it
+        // so it stays bound to the vao
+        let ibo = buffer::StreamElementArrayBuffer::new(max_index_size * STREAM_BATCHES, stream_mode);
+
+        vao.unbind();
+        ibo.unbind();
+        vbo.unbind();
+
+        Ok(GlBackend {
+            batch_mode,
+            max_textures,
+            texture_slots: generate_texture_slots(max_textures),
+            program,
+            uniforms,
+            instanced_program,
+            instanced_uniforms,
+            instanced_quad: InstancedQuad::new(max_sprites, stream_mode),
+            vbo,
+            vao,
+            ibo,
+            render_targets: HashMap::new(),
+            screen_viewport: None,
+        })
+    }
+
+    fn draw_vertices(&mut self, draw: &BatchDraw) -> Result<(), buffer::Error> {
+        self.vao.bind();
+        self.vbo.bind();
+        // batches never hold more than max_sprites, which the stream buffers are sized for
+        let uploaded = self.vbo.upload(draw.vertices).and_then(|vertex_offset| {
+            // the element buffer is part of the vao, binding it again just makes it the upload target
+            self.ibo.bind();
+
+            Ok((vertex_offset, self.ibo.upload(draw.indices)?))
+        });
+
+        if let Ok((vertex_offset, index_offset)) = uploaded {
+            unsafe {
+                gl::DrawElementsBaseVertex(
+                    gl::TRIANGLES,
+                    draw.indices.len() as i32,
+                    gl::UNSIGNED_INT,
+                    index_offset as *const gl::types::GLvoid,
+                    (vertex_offset / ::std::mem::size_of::<BatchVertex>()) as i32,
+                );
+            }
+        }
+
+        self.vbo.fence();
+        self.ibo.fence();
+        self.vbo.unbind();
+        self.vao.unbind();
+
+        uploaded.map(|_| ())
+    }
+}
+
+impl RenderBackend for GlBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::OpenGl
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn max_textures(&self) -> usize {
+        self.max_textures as usize
+    }
+
+    fn supports_instancing(&self) -> bool {
+        true
+    }
+
+    fn create_texture(&mut self, width: u32, height: u32, pixels: &[u8]) -> u32 {
+        texture::create_texture(width, height, pixels)
+    }
+
+    fn update_texture(&mut self, texture: u32, (x, y): (u32, u32), (width, height): (u32, u32), pixels: &[u8]) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const gl::types::GLvoid,
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+
+    fn delete_texture(&mut self, texture: u32) {
+        // render targets delete their texture with the framebuffer
+        if self.render_targets.remove(&texture).is_some() {
+            return;
+        }
+
+        unsafe {
+            gl::DeleteTextures(1, &texture);
+        }
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        let mut viewport: [gl::types::GLint; 4] = [0; 4];
+
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+
+        viewport
+    }
+
+    fn set_viewport(&mut self, [x, y, width, height]: [i32; 4]) {
+        unsafe {
+            gl::Viewport(x, y, width, height);
+        }
+    }
+
+    fn set_clear_color(&mut self, (r, g, b, a): (f32, f32, f32, f32)) {
+        unsafe {
+            gl::ClearColor(r, g, b, a);
+        }
+    }
+
+    fn clear(&mut self, scissor: Option<[i32; 4]>) {
+        unsafe {
+            if let Some([x, y, width, height]) = scissor {
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(x, y, width, height);
+            }
+
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+    }
+
+    fn clear_stencil(&mut self) {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::StencilMask(0xFF);
+            gl::ClearStencil(0);
+            gl::Clear(gl::STENCIL_BUFFER_BIT);
+        }
+    }
+
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<u32, failure::Error> {
+        let frame_buffer = buffer::FrameBuffer::new(width, height)?;
+        let texture = frame_buffer.texture.get_texture_handle();
+
+        self.render_targets.insert(texture, (frame_buffer, [0, 0, width as i32, height as i32]));
+
+        Ok(texture)
+    }
+
+    fn set_render_target(&mut self, texture: Option<u32>) {
+        let target = texture.and_then(|texture| self.render_targets.get(&texture))
+            .map(|(frame_buffer, viewport)| (frame_buffer.get_handle(), *viewport));
+
+        match target {
+            Some((fbo, viewport)) => {
+                if self.screen_viewport.is_none() {
+                    self.screen_viewport = Some(self.viewport());
+                }
+
+                unsafe {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                }
+
+                self.set_viewport(viewport);
+            },
+            None => {
+                unsafe {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, buffer::default_framebuffer());
+                }
+
+                if let Some(viewport) = self.screen_viewport.take() {
+                    self.set_viewport(viewport);
+                }
+            },
+        }
+    }
+
+    fn read_pixels(&mut self) -> RgbaImage {
+        let [x, y, width, height] = self.viewport();
+
+        unsafe {
+            gl::Finish();
+        }
+
+        snapshot::read_pixels(x, y, width.max(0) as u32, height.max(0) as u32)
+    }
+
+    fn draw_batch(&mut self, draw: &BatchDraw) -> Result<(), failure::Error> {
+        let instanced = !draw.instances.is_empty();
+        let (program, uniforms) = match draw.material {
+            Some(material) => material.program(instanced),
+            None if instanced => (&self.instanced_program, self.instanced_uniforms),
+            None => (&self.program, self.uniforms),
+        };
+        let [x, y, width, height] = draw.scissor;
+
+        program.set_used();
+        program.set_uniform_mat4f(uniforms.mvp, &draw.mvp);
+        program.set_uniform_1f(uniforms.alpha_cutoff, draw.mask.alpha_cutoff());
+
+        match self.batch_mode {
+            BatchMode::TextureUnits => program.set_uniform_1iv(uniforms.textures, &self.texture_slots),
+            BatchMode::TextureArray { .. } => {
+                program.set_uniform_1i(uniforms.textures, 0);
+                program.set_uniform_1iv(uniforms.unit_textures, &self.texture_slots[1..]);
+            },
+        }
+
+        if let Some(material) = draw.material {
+            material.apply(instanced, self.max_textures as usize);
+        }
+
+        draw.blend_mode.apply();
+        draw.mask.apply();
+
+        unsafe {
+            gl::Enable(gl::SCISSOR_TEST);
+            gl::Scissor(x, y, width, height);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+
+        for (i, handle) in draw.textures.iter().enumerate() {
+            // in texture array mode the array is always the first texture, the rest didn't fit in it
+            let texture_target = match self.batch_mode {
+                BatchMode::TextureArray { .. } if i == 0 => gl::TEXTURE_2D_ARRAY,
+                _ => gl::TEXTURE_2D,
+            };
+
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + i as u32);
+                gl::BindTexture(texture_target, *handle as u32);
+            }
+        }
+
+        if instanced {
+            self.instanced_quad.draw(draw.instances)?;
+        } else {
+            self.draw_vertices(draw)?;
+        }
+
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
+        }
+
+        MaskState::Off.apply();
+        BlendMode::default().apply();
+    }
+}
@@ -0,0 +1,119 @@
+mod gl_backend;
+mod software;
+#[cfg(feature = "wgpu")]
+mod wgpu_backend;
+#[cfg(feature = "wgpu")]
+mod wgpu_shaders;
+
+pub use gl_backend::{GlBackend};
+pub use software::{SoftwareBackend};
+#[cfg(feature = "wgpu")]
+pub use wgpu_backend::{WgpuBackend, Error as WgpuError};
+
+use std::any::Any;
+
+use image::{RgbaImage};
+
+use super::BatchVertex;
+use super::blend_mode::{BlendMode};
+use super::instancing::{InstanceData};
+use super::mask::{MaskState};
+use super::material::{Material};
+
+/// Which graphics api a `RenderBackend` draws with. Lighting, materials, post-processing, scene
+/// targets, instancing and `BatchMode::TextureArray` are only there with `OpenGl`, the `Renderer2D`
+/// calls that set them up fail with `Error::OpenGlOnly` otherwise
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    OpenGl,
+    Software,
+    Wgpu,
+}
+
+/// What `Renderer2D` draws its batches with. The backend keeps the batch programs and vertex
+/// buffers, texture handles are what `Renderable2D::texture` returns with 0 meaning untextured.
+/// Viewports and scissor boxes are (x, y, width, height) with y going up from the bottom like `glViewport`
+pub trait RenderBackend {
+    fn kind(&self) -> BackendKind;
+
+    /// The backend itself, to get back to its own type with `downcast_ref`
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Textures one batch can use at once
+    fn max_textures(&self) -> usize;
+
+    /// Whether batches of `QuadInstance`s can be drawn, `Renderer2D::set_instancing` does nothing otherwise
+    fn supports_instancing(&self) -> bool;
+
+    /// `pixels` are `width * height` RGBA bytes, top row first
+    fn create_texture(&mut self, width: u32, height: u32, pixels: &[u8]) -> u32;
+
+    /// Replaces the `size` pixels at `position` of `texture`, `pixels` are RGBA bytes like `create_texture`
+    fn update_texture(&mut self, texture: u32, position: (u32, u32), size: (u32, u32), pixels: &[u8]);
+
+    fn delete_texture(&mut self, texture: u32);
+
+    fn viewport(&self) -> [i32; 4];
+
+    fn set_viewport(&mut self, viewport: [i32; 4]);
+
+    fn set_clear_color(&mut self, color: (f32, f32, f32, f32));
+
+    /// Clears the colour of the bound target to the clear colour, only inside `scissor` when there is one
+    fn clear(&mut self, scissor: Option<[i32; 4]>);
+
+    /// Clears the masks drawn so far
+    fn clear_stencil(&mut self);
+
+    /// A transparent `width` x `height` texture for `set_render_target`, which can be drawn with like any other
+    fn create_render_target(&mut self, width: u32, height: u32) -> Result<u32, failure::Error> {
+        Ok(self.create_texture(width, height, &vec![0; (width * height * 4) as usize]))
+    }
+
+    /// Draws into `texture`, made by `create_render_target`, from now on. `None` goes back to the target
+    /// the backend was made with. The viewport is set to all of the new target, which can't be sampled
+    /// by the batches drawn into it. `OpenGl` goes back to the viewport it had before
+    fn set_render_target(&mut self, texture: Option<u32>);
+
+    /// What has been drawn inside the viewport of the target, top row first
+    fn read_pixels(&mut self) -> RgbaImage;
+
+    /// Fails without drawing when the batch doesn't fit in the backend's buffers
+    fn draw_batch(&mut self, draw: &BatchDraw) -> Result<(), failure::Error>;
+
+    /// Called after the last batch of a `Renderer2D::render`, puts back the default blend mode and
+    /// turns off scissor and masks
+    fn reset_state(&mut self);
+
+    /// Hands everything drawn since the last call to the gpu. `Wgpu` records its batches until then,
+    /// `Renderer2D` calls it at the end of `render` and `render_to_texture`. `read_pixels` submits first
+    fn submit(&mut self) {}
+}
+
+/// A batch and everything it's drawn with, given to `RenderBackend::draw_batch`
+#[derive(Debug)]
+pub struct BatchDraw<'a> {
+    pub(super) mvp: glm::Mat4,
+    pub(super) vertices: &'a [BatchVertex],
+    pub(super) indices: &'a [u32],
+    // drawn instead of vertices and indices when not empty
+    pub(super) instances: &'a [InstanceData],
+    // texture handles by batch slot, a vertex's texture index picks one
+    pub(super) textures: &'a [i32],
+    pub(super) blend_mode: BlendMode,
+    pub(super) mask: MaskState,
+    pub(super) scissor: [i32; 4],
+    // only ever set with `OpenGl`, `Renderer2D::add_material` fails on the other backends
+    pub(super) material: Option<&'a Material>,
+}
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="{} needs the OpenGl backend, the renderer draws with {:?}", feature, backend)]
+    OpenGlOnly {
+        feature: &'static str,
+        backend: BackendKind,
+    },
+}
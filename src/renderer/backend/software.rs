@@ -0,0 +1,469 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage};
+
+use super::{RenderBackend, BackendKind, BatchDraw};
+use super::super::BatchVertex;
+use super::super::blend_mode::{BlendMode};
+use super::super::mask::{MaskState, COVERAGE_BIT, MASK_BITS};
+
+// texture slots a batch gets, the same as most gl drivers have
+const MAX_TEXTURES: usize = 16;
+
+/// Rasterizes batches on the cpu into an `RgbaImage`, so scenes can be rendered and checked in tests
+/// without a gl context. Draws the textured and coloured triangles of the batch shader with its blend
+/// modes, clip rects and masks, sampling textures nearest and clamped to their edges like gl textures.
+/// Materials aren't run, their batches get the default shading, and there is no instancing
+#[derive(Debug)]
+pub struct SoftwareBackend {
+    target: RgbaImage,
+    stencil: Vec<u8>,
+    viewport: [i32; 4],
+    clear_color: (f32, f32, f32, f32),
+    textures: HashMap<u32, RgbaImage>,
+    next_texture: u32,
+    // the texture being drawn into, moved out of `textures` into `target` while `screen` holds the
+    // backend's own target
+    render_target: Option<u32>,
+    screen: Option<RgbaImage>,
+}
+
+impl SoftwareBackend {
+    /// A `width` x `height` target, transparent black until it is cleared
+    pub fn new(width: u32, height: u32) -> SoftwareBackend {
+        SoftwareBackend {
+            target: RgbaImage::new(width, height),
+            stencil: vec![0; (width * height) as usize],
+            viewport: [0, 0, width as i32, height as i32],
+            clear_color: (0.0, 0.0, 0.0, 0.0),
+            textures: HashMap::new(),
+            next_texture: 1,
+            render_target: None,
+            screen: None,
+        }
+    }
+
+    /// Everything drawn into the current target so far, top row first
+    pub fn get_image(&self) -> &RgbaImage {
+        &self.target
+    }
+
+    // the part of `rect` inside the target, as x and y ranges of window pixels
+    fn pixels_in(&self, [x, y, width, height]: [i32; 4]) -> (std::ops::Range<i32>, std::ops::Range<i32>) {
+        let (target_width, target_height) = self.target.dimensions();
+
+        (x.max(0)..(x + width).min(target_width as i32), y.max(0)..(y + height).min(target_height as i32))
+    }
+
+    // index of the window pixel `x`, `y` in the stencil and its coordinates in the image, whose rows go down
+    fn pixel_index(&self, x: i32, y: i32) -> (usize, u32, u32) {
+        let (width, height) = self.target.dimensions();
+        let row = height - 1 - y as u32;
+
+        ((row * width + x as u32) as usize, x as u32, row)
+    }
+
+    fn sample(&self, draw: &BatchDraw, tex_index: f32, (u, v): (f32, f32)) -> [f32; 4] {
+        let texture = draw.textures.get((tex_index + 0.5).max(0.0) as usize)
+            .and_then(|&handle| self.textures.get(&(handle as u32)));
+
+        match texture {
+            Some(texture) => {
+                let (width, height) = texture.dimensions();
+                let x = ((u * width as f32).floor().max(0.0) as u32).min(width - 1);
+                let y = ((v * height as f32).floor().max(0.0) as u32).min(height - 1);
+
+                to_color(texture.get_pixel(x, y))
+            },
+            // what the batch shader returns for a slot without a texture
+            None => [1.0; 4],
+        }
+    }
+
+    fn draw_triangle(&mut self, draw: &BatchDraw, corners: [ScreenVertex; 3]) {
+        let [a, b, c] = match edge(&corners[0], &corners[1], (corners[2].x, corners[2].y)) {
+            area if area > 0.0 => corners,
+            area if area < 0.0 => [corners[0], corners[2], corners[1]],
+            _ => return,
+        };
+        let area = edge(&a, &b, (c.x, c.y));
+        let [x, y, width, height] = intersect(draw.scissor, self.viewport);
+        let min_x = (a.x.min(b.x).min(c.x).floor() as i32).max(x);
+        let max_x = (a.x.max(b.x).max(c.x).ceil() as i32).min(x + width);
+        let min_y = (a.y.min(b.y).min(c.y).floor() as i32).max(y);
+        let max_y = (a.y.max(b.y).max(c.y).ceil() as i32).min(y + height);
+        let (xs, ys) = self.pixels_in([min_x, min_y, max_x - min_x, max_y - min_y]);
+        let alpha_cutoff = draw.mask.alpha_cutoff();
+
+        for py in ys {
+            for px in xs.clone() {
+                let point = (px as f32 + 0.5, py as f32 + 0.5);
+                let weights = [edge(&b, &c, point), edge(&c, &a, point), edge(&a, &b, point)];
+                let edges = [(&b, &c), (&c, &a), (&a, &b)];
+                let inside = weights.iter().zip(edges.iter())
+                    .all(|(&weight, (from, to))| weight > 0.0 || (weight == 0.0 && is_top_left(from, to)));
+
+                if !inside {
+                    continue;
+                }
+
+                // perspective correct, the same as gl for the orthographic cameras
+                let [wa, wb, wc] = [weights[0] / area * a.inv_w, weights[1] / area * b.inv_w, weights[2] / area * c.inv_w];
+                let total = wa + wb + wc;
+                let lerp = |fa: f32, fb: f32, fc: f32| (wa * fa + wb * fb + wc * fc) / total;
+                let uv = (lerp(a.uv.0, b.uv.0, c.uv.0), lerp(a.uv.1, b.uv.1, c.uv.1));
+                // every vertex of a quad has the same texture index
+                let texel = self.sample(draw, a.tex_index, uv);
+                let color = [0, 1, 2, 3].map(|i| (texel[i] * lerp(a.color[i], b.color[i], c.color[i])).clamp(0.0, 1.0));
+
+                if color[3] < alpha_cutoff {
+                    continue;
+                }
+
+                let (index, image_x, image_y) = self.pixel_index(px, py);
+
+                match draw.mask {
+                    MaskState::Write(reference) => {
+                        self.stencil[index] = self.stencil[index] & COVERAGE_BIT | reference;
+                        continue;
+                    },
+                    MaskState::Cover => {
+                        self.stencil[index] |= COVERAGE_BIT;
+                        continue;
+                    },
+                    MaskState::Inside(reference) if self.stencil[index] & MASK_BITS != reference => continue,
+                    MaskState::Outside(reference) if self.stencil[index] & MASK_BITS == reference => continue,
+                    _ => {},
+                }
+
+                let pixel = self.target.get_pixel_mut(image_x, image_y);
+
+                *pixel = from_color(blend(draw.blend_mode, color, to_color(pixel)));
+            }
+        }
+    }
+}
+
+impl RenderBackend for SoftwareBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Software
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn max_textures(&self) -> usize {
+        MAX_TEXTURES
+    }
+
+    fn supports_instancing(&self) -> bool {
+        false
+    }
+
+    fn create_texture(&mut self, width: u32, height: u32, pixels: &[u8]) -> u32 {
+        let texture = RgbaImage::from_raw(width, height, pixels.to_vec())
+            .expect("Texture pixels do not match its size");
+        let handle = self.next_texture;
+
+        self.next_texture += 1;
+        self.textures.insert(handle, texture);
+
+        handle
+    }
+
+    fn update_texture(&mut self, texture: u32, (x, y): (u32, u32), (width, height): (u32, u32), pixels: &[u8]) {
+        let texture = match self.textures.get_mut(&texture) {
+            Some(texture) => texture,
+            None if self.render_target == Some(texture) => &mut self.target,
+            None => return,
+        };
+
+        for (i, pixel) in pixels.chunks_exact(4).take((width * height) as usize).enumerate() {
+            let (px, py) = (x + i as u32 % width, y + i as u32 / width);
+
+            if px < texture.width() && py < texture.height() {
+                texture.put_pixel(px, py, Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+    }
+
+    fn delete_texture(&mut self, texture: u32) {
+        if self.render_target == Some(texture) {
+            self.set_render_target(None);
+        }
+
+        self.textures.remove(&texture);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        self.viewport
+    }
+
+    fn set_viewport(&mut self, viewport: [i32; 4]) {
+        self.viewport = viewport;
+    }
+
+    fn set_clear_color(&mut self, color: (f32, f32, f32, f32)) {
+        self.clear_color = color;
+    }
+
+    fn clear(&mut self, scissor: Option<[i32; 4]>) {
+        let (width, height) = self.target.dimensions();
+        let (xs, ys) = self.pixels_in(scissor.unwrap_or([0, 0, width as i32, height as i32]));
+        let (r, g, b, a) = self.clear_color;
+        let color = from_color([r, g, b, a].map(|channel| channel.clamp(0.0, 1.0)));
+
+        for y in ys {
+            for x in xs.clone() {
+                let (_, image_x, image_y) = self.pixel_index(x, y);
+
+                self.target.put_pixel(image_x, image_y, color);
+            }
+        }
+    }
+
+    fn clear_stencil(&mut self) {
+        self.stencil.iter_mut().for_each(|value| *value = 0);
+    }
+
+    fn set_render_target(&mut self, texture: Option<u32>) {
+        // the current target goes back to its texture first
+        if let (Some(current), Some(screen)) = (self.render_target.take(), self.screen.take()) {
+            let image = std::mem::replace(&mut self.target, screen);
+
+            self.textures.insert(current, image);
+        }
+
+        if let Some(image) = texture.and_then(|texture| self.textures.remove(&texture)) {
+            self.screen = Some(std::mem::replace(&mut self.target, image));
+            self.render_target = texture;
+        }
+
+        let (width, height) = self.target.dimensions();
+
+        self.stencil = vec![0; (width * height) as usize];
+        self.viewport = [0, 0, width as i32, height as i32];
+    }
+
+    fn read_pixels(&mut self) -> RgbaImage {
+        let [x, y, width, height] = self.viewport;
+        let (xs, ys) = self.pixels_in(self.viewport);
+        let mut image = RgbaImage::new(width.max(0) as u32, height.max(0) as u32);
+
+        for py in ys {
+            for px in xs.clone() {
+                let (_, image_x, image_y) = self.pixel_index(px, py);
+
+                image.put_pixel((px - x) as u32, (height - 1 - (py - y)) as u32, *self.target.get_pixel(image_x, image_y));
+            }
+        }
+
+        image
+    }
+
+    fn draw_batch(&mut self, draw: &BatchDraw) -> Result<(), failure::Error> {
+        let viewport = self.viewport;
+
+        for triangle in draw.indices.chunks_exact(3) {
+            let corner = |index: u32| ScreenVertex::new(&draw.vertices[index as usize], &draw.mvp, viewport);
+
+            self.draw_triangle(draw, [corner(triangle[0]), corner(triangle[1]), corner(triangle[2])]);
+        }
+
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {}
+}
+
+// a batch vertex after the mvp, in window coordinates with y going up
+#[derive(Clone, Copy, Debug)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    inv_w: f32,
+    uv: (f32, f32),
+    color: [f32; 4],
+    tex_index: f32,
+}
+
+impl ScreenVertex {
+    fn new(vertex: &BatchVertex, mvp: &glm::Mat4, [x, y, width, height]: [i32; 4]) -> ScreenVertex {
+        let (pos, tex, color) = (vertex.pos, vertex.tex, vertex.color);
+        let (translate, scale) = (vertex.tex_translate, vertex.tex_scale);
+        let clip = mvp * glm::vec4(pos.d0, pos.d1, pos.d2, 1.0);
+        let inv_w = 1.0 / clip.w;
+
+        ScreenVertex {
+            x: x as f32 + (clip.x * inv_w + 1.0) / 2.0 * width as f32,
+            y: y as f32 + (clip.y * inv_w + 1.0) / 2.0 * height as f32,
+            inv_w,
+            // the batch vertex shader's texture transform
+            uv: (tex.d0 * scale.d0 + translate.d0, tex.d1 * scale.d1 + translate.d1),
+            color: [color.d0, color.d1, color.d2, color.d3],
+            tex_index: vertex.tex_id.d0,
+        }
+    }
+}
+
+// twice the area of `from`, `to`, `point`, positive when `point` is left of the edge going from `from` to `to`
+fn edge(from: &ScreenVertex, to: &ScreenVertex, (x, y): (f32, f32)) -> f32 {
+    (to.x - from.x) * (y - from.y) - (to.y - from.y) * (x - from.x)
+}
+
+// pixels exactly on an edge only belong to the triangle on its top or left side, so triangles
+// sharing an edge don't both draw it. Triangles are counter clockwise with y going up
+fn is_top_left(from: &ScreenVertex, to: &ScreenVertex) -> bool {
+    to.y < from.y || (to.y == from.y && to.x < from.x)
+}
+
+fn intersect([x, y, width, height]: [i32; 4], [ox, oy, other_width, other_height]: [i32; 4]) -> [i32; 4] {
+    let (left, bottom) = (x.max(ox), y.max(oy));
+    let (right, top) = ((x + width).min(ox + other_width), (y + height).min(oy + other_height));
+
+    [left, bottom, (right - left).max(0), (top - bottom).max(0)]
+}
+
+fn to_color(pixel: &Rgba<u8>) -> [f32; 4] {
+    pixel.0.map(|channel| channel as f32 / 255.0)
+}
+
+fn from_color(color: [f32; 4]) -> Rgba<u8> {
+    Rgba(color.map(|channel| (channel * 255.0).round() as u8))
+}
+
+// `glBlendFunc` with the factors of `BlendMode::factors`, for colour and alpha alike
+fn blend(blend_mode: BlendMode, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let src_alpha = src[3];
+    let channel = |i: usize| match blend_mode {
+        BlendMode::Alpha => src[i] * src_alpha + dst[i] * (1.0 - src_alpha),
+        BlendMode::PremultipliedAlpha => src[i] + dst[i] * (1.0 - src_alpha),
+        BlendMode::Additive => src[i] * src_alpha + dst[i],
+        BlendMode::Multiply => src[i] * dst[i] + dst[i] * (1.0 - src_alpha),
+        BlendMode::Screen => src[i] + dst[i] * (1.0 - src[i]),
+        BlendMode::Opaque => src[i],
+    };
+
+    [0, 1, 2, 3].map(|i| channel(i).clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::renderer::Renderer2D;
+    use crate::renderer::clip::{ClipRect, ClipSpace};
+    use crate::sprite::{Sprite, SpriteProps};
+    use crate::textures::texture::{Texture};
+
+    const SQUARE: [(f32, f32); 4] = [(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)];
+
+    fn render(draw: impl FnOnce(&mut Renderer2D)) -> RgbaImage {
+        let camera = Camera::new(8.0, 8.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::with_backend(SoftwareBackend::new(8, 8));
+
+        renderer.set_clear_color(0, 0, 255, 1.0);
+        renderer.clear();
+        renderer.begin_batch();
+        draw(&mut renderer);
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+        renderer.get_backend_mut().read_pixels()
+    }
+
+    #[test]
+    fn renders_textured_sprites_top_row_first() {
+        let image = render(|renderer| {
+            let pixels = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 0, 255], [255, 255, 255, 255]].concat();
+            let handle = renderer.get_backend_mut().create_texture(2, 2, &pixels);
+            let texture = Texture::from_handle(handle, 2, 2);
+            let props = SpriteProps { dim: (2, 2), ..SpriteProps::default() };
+
+            renderer.submit(&Sprite::from_texture(&texture, props).unwrap());
+        });
+
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 1).0, [255, 255, 255, 255]);
+        assert_eq!(image.get_pixel(2, 2).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn quads_sharing_an_edge_blend_once() {
+        let image = render(|renderer| renderer.draw_polygon(&SQUARE, (1.0, 0.0, 0.0, 0.5)));
+
+        assert!(image.pixels().all(|pixel| pixel.0 == [128, 0, 128, 191]));
+    }
+
+    #[test]
+    fn clip_rects_and_masks_limit_drawing() {
+        let image = render(|renderer| {
+            renderer.push_clip_rect(ClipRect::new(0.0, 0.0, 4.0, 8.0), ClipSpace::Screen);
+            renderer.draw_polygon(&SQUARE, (1.0, 0.0, 0.0, 1.0));
+            renderer.pop_clip_rect();
+            renderer.begin_mask();
+            renderer.draw_polygon(&[(0.0, 6.0), (8.0, 6.0), (8.0, 8.0), (0.0, 8.0)], (1.0, 1.0, 1.0, 1.0));
+            renderer.end_mask();
+            renderer.draw_polygon(&SQUARE, (0.0, 1.0, 0.0, 1.0));
+        });
+
+        assert_eq!(image.get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(6, 1).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(1, 7).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(6, 7).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn blend_modes_match_their_blend_factors() {
+        let src = [1.0, 0.0, 0.0, 0.5];
+        let dst = [0.0, 0.0, 1.0, 1.0];
+
+        assert_eq!(blend(BlendMode::Alpha, src, dst), [0.5, 0.0, 0.5, 0.75]);
+        assert_eq!(blend(BlendMode::PremultipliedAlpha, src, dst), [1.0, 0.0, 0.5, 1.0]);
+        assert_eq!(blend(BlendMode::Additive, src, dst), [0.5, 0.0, 1.0, 1.0]);
+        assert_eq!(blend(BlendMode::Multiply, src, dst), [0.0, 0.0, 0.5, 1.0]);
+        assert_eq!(blend(BlendMode::Screen, src, dst), [1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(blend(BlendMode::Opaque, src, dst), src);
+    }
+
+    #[test]
+    fn textures_are_updated_in_place() {
+        let mut backend = SoftwareBackend::new(1, 1);
+        let texture = backend.create_texture(2, 2, &[0; 16]);
+
+        backend.update_texture(texture, (1, 0), (1, 2), &[255, 0, 0, 255, 0, 255, 0, 255]);
+
+        let image = &backend.textures[&texture];
+
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 1).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn render_targets_keep_what_was_drawn_into_them() {
+        let mut backend = SoftwareBackend::new(4, 4);
+        let texture = backend.create_texture(2, 2, &[0; 16]);
+
+        backend.set_render_target(Some(texture));
+        backend.set_clear_color((1.0, 0.0, 0.0, 1.0));
+        backend.clear(Some([1, 0, 1, 2]));
+
+        assert_eq!(backend.viewport(), [0, 0, 2, 2]);
+        assert_eq!(backend.read_pixels().get_pixel(1, 1).0, [255, 0, 0, 255]);
+
+        backend.set_render_target(None);
+
+        assert_eq!(backend.viewport(), [0, 0, 4, 4]);
+        assert!(backend.get_image().pixels().all(|pixel| pixel.0 == [0, 0, 0, 0]));
+        assert_eq!(backend.textures[&texture].get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(backend.textures[&texture].get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,1014 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+
+use image::{RgbaImage};
+
+use super::{RenderBackend, BackendKind, BatchDraw};
+use super::wgpu_shaders::{create_batch_source};
+use super::super::BatchVertex;
+use super::super::blend_mode::{BlendMode};
+use super::super::mask::{MaskState, COVERAGE_BIT, MASK_BITS};
+
+// texture slots a batch gets, within what downlevel (gl/webgl2) adapters allow in a shader stage
+const MAX_TEXTURES: usize = 8;
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+// the mvp and alpha cutoff, padded to the 16 byte alignment of uniform structs
+const UNIFORMS_SIZE: u64 = (16 + 4) * 4;
+const FALLBACK_ADAPTER: wgpu::RequestAdapterOptions = wgpu::RequestAdapterOptions {
+    power_preference: wgpu::PowerPreference::None,
+    force_fallback_adapter: true,
+    compatible_surface: None,
+};
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="No wgpu adapter found, not even a fallback (software) one")]
+    NoAdapter,
+    #[fail(display="Could not create a wgpu device: {}", message)]
+    RequestDevice { message: String },
+}
+
+// pipelines only differ in blending and what they do with the stencil buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum StencilUse {
+    Off,
+    Write,
+    Inside,
+    Outside,
+    Cover,
+}
+
+impl StencilUse {
+    fn of(mask: MaskState) -> (StencilUse, u8) {
+        match mask {
+            MaskState::Off => (StencilUse::Off, 0),
+            MaskState::Write(reference) => (StencilUse::Write, reference),
+            MaskState::Inside(reference) => (StencilUse::Inside, reference),
+            MaskState::Outside(reference) => (StencilUse::Outside, reference),
+            MaskState::Cover => (StencilUse::Cover, COVERAGE_BIT),
+        }
+    }
+}
+
+// a batch recorded for the next `submit`, its vertices, indices and uniforms are uploaded then
+#[derive(Debug)]
+struct RecordedDraw {
+    pipeline: (BlendMode, StencilUse),
+    // the textures of the bind group, by slot
+    textures: Vec<u32>,
+    uniform_offset: u32,
+    indices: Range<u32>,
+    base_vertex: i32,
+    // in wgpu's coordinates, y going down
+    viewport: [f32; 4],
+    scissor: [u32; 4],
+    reference: u8,
+}
+
+#[derive(Debug)]
+enum Command {
+    // a pass without draws, clearing the colour and/or the stencil of the target
+    Clear { target: u32, color: Option<wgpu::Color>, stencil: bool },
+    Draw { target: u32, draw: RecordedDraw },
+}
+
+impl Command {
+    fn target(&self) -> u32 {
+        match self {
+            Command::Clear { target, .. } | Command::Draw { target, .. } => *target,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BackendTexture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    // created the first time the texture is rendered into, for masks
+    stencil: Option<wgpu::TextureView>,
+}
+
+/// Draws batches with wgpu into an offscreen target, on whatever adapter wgpu picks (Vulkan, Metal,
+/// DX12 or GL) or its fallback adapter, ie lavapipe or llvmpipe for headless runs. Every texture can
+/// be rendered into with `RenderBackend::set_render_target` and sampled by later batches, the target
+/// can be read back with `read_pixels` or copied out of `get_texture`, ie onto a surface. Batches are
+/// recorded and only handed to the gpu, in one encoder, on `RenderBackend::submit`. Like
+/// `SoftwareBackend`, materials aren't run and there is no instancing
+#[derive(Debug)]
+pub struct WgpuBackend {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    shader: wgpu::ShaderModule,
+    uniform_layout: wgpu::BindGroupLayout,
+    texture_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<(BlendMode, StencilUse), wgpu::RenderPipeline>,
+    sampler: wgpu::Sampler,
+    uniforms: wgpu::Buffer,
+    uniform_group: wgpu::BindGroup,
+    uniform_alignment: u32,
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    // texture bind groups by the textures in their slots, dropped with any of their textures
+    bind_groups: HashMap<Vec<u32>, wgpu::BindGroup>,
+    // everything since the last `submit`
+    commands: Vec<Command>,
+    vertex_data: Vec<u8>,
+    index_data: Vec<u32>,
+    uniform_data: Vec<u8>,
+    textures: HashMap<u32, BackendTexture>,
+    next_texture: u32,
+    white_texture: u32,
+    screen: u32,
+    target: u32,
+    viewport: [i32; 4],
+    clear_color: (f32, f32, f32, f32),
+}
+
+impl WgpuBackend {
+    /// A `width` x `height` target on the default adapter, or the fallback adapter when there is no other
+    pub fn new(width: u32, height: u32) -> Result<WgpuBackend, Error> {
+        let instance = create_instance();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .or_else(|| pollster::block_on(instance.request_adapter(&FALLBACK_ADAPTER)))
+            .ok_or(Error::NoAdapter)?;
+
+        WgpuBackend::with_adapter(&adapter, width, height)
+    }
+
+    /// Like `new` but always on the fallback adapter, ie to render the same on every machine in tests
+    pub fn with_fallback_adapter(width: u32, height: u32) -> Result<WgpuBackend, Error> {
+        let adapter = pollster::block_on(create_instance().request_adapter(&FALLBACK_ADAPTER))
+            .ok_or(Error::NoAdapter)?;
+
+        WgpuBackend::with_adapter(&adapter, width, height)
+    }
+
+    fn with_adapter(adapter: &wgpu::Adapter, width: u32, height: u32) -> Result<WgpuBackend, Error> {
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("yarge"),
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+            memory_hints: wgpu::MemoryHints::default(),
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None))
+            .map_err(|error| Error::RequestDevice { message: error.to_string() })?;
+
+        Ok(WgpuBackend::with_device(device, queue, width, height))
+    }
+
+    /// Shares a device the application already has, ie the one its window surface is presented with
+    pub fn with_device(device: wgpu::Device, queue: wgpu::Queue, width: u32, height: u32) -> WgpuBackend {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("internal/shaders/batch"),
+            source: wgpu::ShaderSource::Wgsl(create_batch_source(MAX_TEXTURES).into()),
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("batch uniforms"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(UNIFORMS_SIZE),
+                },
+                count: None,
+            }],
+        });
+        let texture_entries: Vec<wgpu::BindGroupLayoutEntry> = (0..MAX_TEXTURES)
+            .map(|i| wgpu::BindGroupLayoutEntry {
+                binding: i as u32,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            })
+            .chain(std::iter::once(wgpu::BindGroupLayoutEntry {
+                binding: MAX_TEXTURES as u32,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            }))
+            .collect();
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("batch textures"),
+            entries: &texture_entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("batch"),
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        // nearest and clamped to the edge, the same as `texture::create_texture`
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("batch"),
+            ..Default::default()
+        });
+        // the uniforms of every batch in a frame are in one buffer, bound at their offset
+        let uniforms = create_buffer(&device, wgpu::BufferUsages::UNIFORM, 0);
+        let uniform_group = create_uniform_group(&device, &uniform_layout, &uniforms);
+        let uniform_alignment = device.limits().min_uniform_buffer_offset_alignment;
+        let vertices = create_buffer(&device, wgpu::BufferUsages::VERTEX, 0);
+        let indices = create_buffer(&device, wgpu::BufferUsages::INDEX, 0);
+
+        let mut backend = WgpuBackend {
+            device,
+            queue,
+            shader,
+            uniform_layout,
+            texture_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            sampler,
+            uniforms,
+            uniform_group,
+            uniform_alignment,
+            vertices,
+            indices,
+            bind_groups: HashMap::new(),
+            commands: Vec::new(),
+            vertex_data: Vec::new(),
+            index_data: Vec::new(),
+            uniform_data: Vec::new(),
+            textures: HashMap::new(),
+            next_texture: 1,
+            white_texture: 0,
+            screen: 0,
+            target: 0,
+            viewport: [0, 0, width as i32, height as i32],
+            clear_color: (0.0, 0.0, 0.0, 0.0),
+        };
+
+        backend.white_texture = backend.create_texture(1, 1, &[255, 255, 255, 255]);
+        backend.screen = backend.create_texture(width, height, &vec![0; (width * height * 4) as usize]);
+        backend.target = backend.screen;
+
+        backend
+    }
+
+    pub fn get_device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn get_queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// The wgpu texture behind a handle from `create_texture`, or the target batches are drawn into
+    /// with `get_render_target`, ie to copy it onto a surface. What is drawn into it is only there
+    /// after `submit`
+    pub fn get_texture(&self, texture: u32) -> Option<&wgpu::Texture> {
+        self.textures.get(&texture).map(|texture| &texture.texture)
+    }
+
+    /// The texture batches are drawn into, the backend's own target unless one was set
+    pub fn get_render_target(&self) -> u32 {
+        self.target
+    }
+
+    fn target_size(&self) -> (u32, u32) {
+        let size = self.textures[&self.target].texture.size();
+
+        (size.width, size.height)
+    }
+
+    // the part of the gl style (y going up) `rect` inside the target, as a wgpu rect with y going down
+    fn target_rect(&self, [x, y, width, height]: [i32; 4]) -> Option<[u32; 4]> {
+        let (target_width, target_height) = self.target_size();
+        let (left, right) = (x.max(0), (x + width).min(target_width as i32));
+        let (bottom, top) = (y.max(0), (y + height).min(target_height as i32));
+
+        if right <= left || top <= bottom {
+            return None;
+        }
+
+        Some([left as u32, target_height - top as u32, (right - left) as u32, (top - bottom) as u32])
+    }
+
+    fn pipeline(&mut self, blend_mode: BlendMode, stencil: StencilUse) -> &wgpu::RenderPipeline {
+        let device = &self.device;
+        let shader = &self.shader;
+        let layout = &self.pipeline_layout;
+
+        self.pipelines.entry((blend_mode, stencil))
+            .or_insert_with(|| create_pipeline(device, shader, layout, blend_mode, stencil))
+    }
+
+    // masks are drawn into a stencil buffer of the target, made the first time it is drawn into
+    fn record(&mut self, command: Command) {
+        let device = &self.device;
+
+        if let Some(target) = self.textures.get_mut(&command.target()) {
+            let size = target.texture.size();
+
+            target.stencil.get_or_insert_with(|| create_stencil(device, size.width, size.height));
+        }
+
+        self.commands.push(command);
+    }
+
+    // the bind group of the textures in a batch's slots, made once for every set of textures
+    fn texture_group(&mut self, textures: &[i32]) -> Vec<u32> {
+        let handles: Vec<u32> = (0..MAX_TEXTURES)
+            .map(|slot| textures.get(slot)
+                .map(|&handle| handle as u32)
+                .filter(|handle| self.textures.contains_key(handle))
+                .unwrap_or(self.white_texture))
+            .collect();
+
+        if self.bind_groups.contains_key(&handles) {
+            return handles;
+        }
+
+        let entries: Vec<wgpu::BindGroupEntry> = handles.iter()
+            .enumerate()
+            .map(|(slot, handle)| wgpu::BindGroupEntry {
+                binding: slot as u32,
+                resource: wgpu::BindingResource::TextureView(&self.textures[handle].view),
+            })
+            .chain(std::iter::once(wgpu::BindGroupEntry {
+                binding: MAX_TEXTURES as u32,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            }))
+            .collect();
+        let group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("batch textures"),
+            layout: &self.texture_layout,
+            entries: &entries,
+        });
+
+        self.bind_groups.insert(handles.clone(), group);
+
+        handles
+    }
+
+    fn draw(&mut self, draw: &BatchDraw) {
+        let (width, height) = self.target_size();
+        let [x, y, viewport_width, viewport_height] = self.viewport;
+        let [scissor_x, scissor_y, scissor_width, scissor_height] = match self.target_rect(draw.scissor) {
+            Some(scissor) => scissor,
+            None => return,
+        };
+
+        if draw.indices.is_empty() || viewport_width <= 0 || viewport_height <= 0 {
+            return;
+        }
+
+        let mut uniforms = [0.0f32; (UNIFORMS_SIZE / 4) as usize];
+        let (stencil, reference) = StencilUse::of(draw.mask);
+        let uniform_offset = align_to(self.uniform_data.len() as u32, self.uniform_alignment);
+        let base_vertex = (self.vertex_data.len() / mem::size_of::<BatchVertex>()) as i32;
+        let first_index = self.index_data.len() as u32;
+
+        uniforms[..16].copy_from_slice(draw.mvp.as_slice());
+        uniforms[16] = draw.mask.alpha_cutoff();
+
+        self.uniform_data.resize(uniform_offset as usize, 0);
+        self.uniform_data.extend_from_slice(as_bytes(&uniforms));
+        self.vertex_data.extend_from_slice(as_bytes(draw.vertices));
+        self.index_data.extend_from_slice(draw.indices);
+        self.pipeline(draw.blend_mode, stencil);
+
+        let draw = RecordedDraw {
+            pipeline: (draw.blend_mode, stencil),
+            textures: self.texture_group(draw.textures),
+            uniform_offset,
+            indices: first_index..self.index_data.len() as u32,
+            base_vertex,
+            // gl viewports go up from the bottom of the target, wgpu's down from the top
+            viewport: [
+                x as f32,
+                height as f32 - (y + viewport_height) as f32,
+                viewport_width as f32,
+                viewport_height as f32,
+            ],
+            scissor: [scissor_x, scissor_y, scissor_width.min(width), scissor_height.min(height)],
+            reference,
+        };
+
+        self.record(Command::Draw { target: self.target, draw });
+    }
+
+    // a render pass into `target` that keeps what's there unless told otherwise.
+    // `None` when the target is gone or was never recorded into, so it has no stencil buffer
+    fn begin_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        target: u32,
+        color_load: wgpu::LoadOp<wgpu::Color>,
+        stencil_load: wgpu::LoadOp<u32>,
+    ) -> Option<wgpu::RenderPass<'e>> {
+        let target = self.textures.get(&target)?;
+        let stencil = target.stencil.as_ref()?;
+
+        Some(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("batch"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: color_load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: stencil,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store }),
+                stencil_ops: Some(wgpu::Operations { load: stencil_load, store: wgpu::StoreOp::Store }),
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        }))
+    }
+
+    fn draw_in_pass(&self, pass: &mut wgpu::RenderPass, draw: &RecordedDraw) {
+        let [x, y, width, height] = draw.viewport;
+        let [scissor_x, scissor_y, scissor_width, scissor_height] = draw.scissor;
+
+        pass.set_pipeline(&self.pipelines[&draw.pipeline]);
+        pass.set_bind_group(0, &self.uniform_group, &[draw.uniform_offset]);
+        pass.set_bind_group(1, &self.bind_groups[&draw.textures], &[]);
+        pass.set_vertex_buffer(0, self.vertices.slice(..));
+        pass.set_index_buffer(self.indices.slice(..), wgpu::IndexFormat::Uint32);
+        pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        pass.set_scissor_rect(scissor_x, scissor_y, scissor_width, scissor_height);
+        pass.set_stencil_reference(draw.reference as u32);
+        pass.draw_indexed(draw.indices.clone(), draw.base_vertex, 0..1);
+    }
+
+    // the vertices, indices and uniforms of every recorded batch, the buffers grow to fit them
+    fn upload_frame(&mut self) {
+        write_buffer(&self.device, &self.queue, &mut self.vertices, wgpu::BufferUsages::VERTEX, &self.vertex_data);
+        write_buffer(&self.device, &self.queue, &mut self.indices, wgpu::BufferUsages::INDEX, as_bytes(&self.index_data));
+
+        if write_buffer(&self.device, &self.queue, &mut self.uniforms, wgpu::BufferUsages::UNIFORM, &self.uniform_data) {
+            self.uniform_group = create_uniform_group(&self.device, &self.uniform_layout, &self.uniforms);
+        }
+
+        self.vertex_data.clear();
+        self.index_data.clear();
+        self.uniform_data.clear();
+    }
+}
+
+impl RenderBackend for WgpuBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Wgpu
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn max_textures(&self) -> usize {
+        MAX_TEXTURES
+    }
+
+    fn supports_instancing(&self) -> bool {
+        false
+    }
+
+    fn create_texture(&mut self, width: u32, height: u32, pixels: &[u8]) -> u32 {
+        let size = wgpu::Extent3d { width: width.max(1), height: height.max(1), depth_or_array_layers: 1 };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let handle = self.next_texture;
+
+        self.next_texture += 1;
+        self.textures.insert(handle, BackendTexture { texture, view, stencil: None });
+        self.update_texture(handle, (0, 0), (width, height), pixels);
+
+        handle
+    }
+
+    fn update_texture(&mut self, texture: u32, (x, y): (u32, u32), (width, height): (u32, u32), pixels: &[u8]) {
+        // texture writes go ahead of the next submit, the batches before it sample the old pixels
+        self.submit();
+
+        let texture = match self.textures.get(&texture) {
+            Some(texture) => &texture.texture,
+            None => return,
+        };
+
+        if width == 0 || height == 0 || pixels.len() < (width * height * 4) as usize {
+            return;
+        }
+
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(width * 4), rows_per_image: Some(height) },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+    }
+
+    fn delete_texture(&mut self, texture: u32) {
+        // its own target and white texture stay until the backend is dropped
+        if texture == self.screen || texture == self.white_texture {
+            return;
+        }
+
+        self.submit();
+
+        if texture == self.target {
+            self.set_render_target(None);
+        }
+
+        self.bind_groups.retain(|textures, _| !textures.contains(&texture));
+        self.textures.remove(&texture);
+    }
+
+    fn viewport(&self) -> [i32; 4] {
+        self.viewport
+    }
+
+    fn set_viewport(&mut self, viewport: [i32; 4]) {
+        self.viewport = viewport;
+    }
+
+    fn set_clear_color(&mut self, color: (f32, f32, f32, f32)) {
+        self.clear_color = color;
+    }
+
+    fn clear(&mut self, scissor: Option<[i32; 4]>) {
+        let (r, g, b, a) = self.clear_color;
+
+        let scissor = match scissor {
+            Some(scissor) => scissor,
+            None => {
+                let color = wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 };
+
+                self.record(Command::Clear { target: self.target, color: Some(color), stencil: false });
+
+                return;
+            },
+        };
+
+        // render passes only clear all of the target, a part of it is cleared by drawing over it
+        let (width, height) = self.target_size();
+        let viewport = self.viewport;
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+        let vertices: Vec<BatchVertex> = corners.iter()
+            .map(|&(x, y)| BatchVertex {
+                pos: (x, y, 0.0).into(),
+                tex: (0.0, 0.0).into(),
+                color: (r, g, b, a).into(),
+                tex_id: (-1.0).into(),
+                tex_translate: (0.0, 0.0, 0.0).into(),
+                tex_scale: (1.0, 1.0, 1.0).into(),
+            })
+            .collect();
+
+        self.viewport = [0, 0, width as i32, height as i32];
+        self.draw(&BatchDraw {
+            mvp: glm::identity(),
+            vertices: &vertices,
+            indices: &[0, 1, 2, 2, 1, 3],
+            instances: &[],
+            textures: &[],
+            blend_mode: BlendMode::Opaque,
+            mask: MaskState::Off,
+            scissor,
+            material: None,
+        });
+        self.viewport = viewport;
+    }
+
+    fn clear_stencil(&mut self) {
+        self.record(Command::Clear { target: self.target, color: None, stencil: true });
+    }
+
+    fn set_render_target(&mut self, texture: Option<u32>) {
+        let target = texture.filter(|texture| self.textures.contains_key(texture)).unwrap_or(self.screen);
+
+        self.target = target;
+
+        let (width, height) = self.target_size();
+
+        self.viewport = [0, 0, width as i32, height as i32];
+    }
+
+    fn read_pixels(&mut self) -> RgbaImage {
+        self.submit();
+
+        let [_, _, width, height] = self.viewport;
+        let mut image = RgbaImage::new(width.max(0) as u32, height.max(0) as u32);
+        let [x, y, copy_width, copy_height] = match self.target_rect(self.viewport) {
+            Some(rect) => rect,
+            None => return image,
+        };
+        // rows of a texture copy are padded to 256 bytes
+        let row_bytes = copy_width * 4;
+        let padded_row_bytes = align_to(row_bytes, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("read pixels"),
+            size: (padded_row_bytes * copy_height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("read pixels") });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.textures[&self.target].texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(padded_row_bytes), rows_per_image: Some(copy_height) },
+            },
+            wgpu::Extent3d { width: copy_width, height: copy_height, depth_or_array_layers: 1 },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+
+        // image rows go down from the top of the viewport, which is `origin` rows below the top of the target
+        let [viewport_x, viewport_y, _, _] = self.viewport;
+        let origin = self.target_size().1 as i32 - viewport_y - height;
+        let data = slice.get_mapped_range();
+
+        for (row, pixels) in data.chunks(padded_row_bytes as usize).enumerate() {
+            for (column, pixel) in pixels[..row_bytes as usize].chunks_exact(4).enumerate() {
+                let image_x = (x as i32 + column as i32 - viewport_x) as u32;
+                let image_y = (y as i32 + row as i32 - origin) as u32;
+
+                image.put_pixel(image_x, image_y, image::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+
+        image
+    }
+
+    // the buffers grow to fit, so batches are always drawn
+    fn draw_batch(&mut self, draw: &BatchDraw) -> Result<(), failure::Error> {
+        self.draw(draw);
+
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {}
+
+    fn submit(&mut self) {
+        if self.commands.is_empty() {
+            return;
+        }
+
+        let commands = mem::take(&mut self.commands);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("frame") });
+
+        self.upload_frame();
+
+        let mut commands = commands.iter().peekable();
+
+        while let Some(command) = commands.next() {
+            match command {
+                Command::Clear { target, color, stencil } => {
+                    let color_load = color.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear);
+                    let stencil_load = if *stencil { wgpu::LoadOp::Clear(0) } else { wgpu::LoadOp::Load };
+
+                    drop(self.begin_pass(&mut encoder, *target, color_load, stencil_load));
+                },
+                Command::Draw { target, draw } => {
+                    let mut pass = match self.begin_pass(&mut encoder, *target, wgpu::LoadOp::Load, wgpu::LoadOp::Load) {
+                        Some(pass) => pass,
+                        None => continue,
+                    };
+
+                    self.draw_in_pass(&mut pass, draw);
+
+                    // the draws after it into the same target go in the same pass
+                    while let Some(Command::Draw { draw, .. }) = commands.next_if(|next| {
+                        matches!(next, Command::Draw { target: next_target, .. } if next_target == target)
+                    }) {
+                        self.draw_in_pass(&mut pass, draw);
+                    }
+                },
+            }
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+}
+
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+        ..Default::default()
+    })
+}
+
+fn create_buffer(device: &wgpu::Device, usage: wgpu::BufferUsages, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("batch"),
+        size: size.max(4096).next_power_of_two(),
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_uniform_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, uniforms: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("batch uniforms"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: uniforms,
+                offset: 0,
+                size: wgpu::BufferSize::new(UNIFORMS_SIZE),
+            }),
+        }],
+    })
+}
+
+// copies `bytes` into `buffer`, replacing it with a bigger one first when it's too small
+fn write_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &mut wgpu::Buffer, usage: wgpu::BufferUsages, bytes: &[u8]) -> bool {
+    let grown = buffer.size() < bytes.len() as u64;
+
+    if grown {
+        *buffer = create_buffer(device, usage, bytes.len() as u64);
+    }
+
+    queue.write_buffer(buffer, 0, bytes);
+
+    grown
+}
+
+fn align_to(value: u32, alignment: u32) -> u32 {
+    match value % alignment {
+        0 => value,
+        rest => value + alignment - rest,
+    }
+}
+
+fn create_stencil(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("mask"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: STENCIL_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    }).create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+    blend_mode: BlendMode,
+    stencil: StencilUse,
+) -> wgpu::RenderPipeline {
+    // the same stencil tests and writes as `MaskState::apply`
+    let (compare, pass_op) = match stencil {
+        StencilUse::Off => (wgpu::CompareFunction::Always, wgpu::StencilOperation::Keep),
+        StencilUse::Write => (wgpu::CompareFunction::Always, wgpu::StencilOperation::Replace),
+        StencilUse::Inside => (wgpu::CompareFunction::Equal, wgpu::StencilOperation::Keep),
+        StencilUse::Outside => (wgpu::CompareFunction::NotEqual, wgpu::StencilOperation::Keep),
+        StencilUse::Cover => (wgpu::CompareFunction::Always, wgpu::StencilOperation::Replace),
+    };
+    let stencil_write_mask = match stencil {
+        StencilUse::Cover => COVERAGE_BIT,
+        _ => MASK_BITS,
+    };
+    let face = wgpu::StencilFaceState {
+        compare,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op,
+    };
+    let blend = blend_mode.factors().map(|(src, dst)| {
+        let component = wgpu::BlendComponent {
+            src_factor: blend_factor(src),
+            dst_factor: blend_factor(dst),
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        wgpu::BlendState { color: component, alpha: component }
+    });
+    let write_mask = match stencil {
+        StencilUse::Write | StencilUse::Cover => wgpu::ColorWrites::empty(),
+        _ => wgpu::ColorWrites::ALL,
+    };
+    let attributes = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4,
+        3 => Float32,
+        4 => Float32x3,
+        5 => Float32x3,
+    ];
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("internal/shaders/batch"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            compilation_options: Default::default(),
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<BatchVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &attributes,
+            }],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState { format: TARGET_FORMAT, blend, write_mask })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState { front: face, back: face, read_mask: MASK_BITS as u32, write_mask: stencil_write_mask as u32 },
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+// the wgpu version of a `BlendMode::factors` factor
+fn blend_factor(factor: gl::types::GLenum) -> wgpu::BlendFactor {
+    match factor {
+        gl::ZERO => wgpu::BlendFactor::Zero,
+        gl::SRC_COLOR => wgpu::BlendFactor::Src,
+        gl::ONE_MINUS_SRC_COLOR => wgpu::BlendFactor::OneMinusSrc,
+        gl::SRC_ALPHA => wgpu::BlendFactor::SrcAlpha,
+        gl::ONE_MINUS_SRC_ALPHA => wgpu::BlendFactor::OneMinusSrcAlpha,
+        gl::DST_COLOR => wgpu::BlendFactor::Dst,
+        gl::ONE_MINUS_DST_COLOR => wgpu::BlendFactor::OneMinusDst,
+        gl::DST_ALPHA => wgpu::BlendFactor::DstAlpha,
+        gl::ONE_MINUS_DST_ALPHA => wgpu::BlendFactor::OneMinusDstAlpha,
+        _ => wgpu::BlendFactor::One,
+    }
+}
+
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::renderer::{Renderer2D, shapes};
+    use crate::renderer::render_texture::{RenderTexture};
+
+    // covering the left or the right half of the target, tinted `color`
+    fn half_quad(left: f32, color: (f32, f32, f32, f32), tex_id: f32) -> Vec<BatchVertex> {
+        [(left, -1.0), (left + 1.0, -1.0), (left, 1.0), (left + 1.0, 1.0)].iter()
+            .map(|&(x, y)| BatchVertex {
+                pos: (x, y, 0.0).into(),
+                tex: (0.0, 0.0).into(),
+                color: color.into(),
+                tex_id: tex_id.into(),
+                tex_translate: (0.0, 0.0, 0.0).into(),
+                tex_scale: (1.0, 1.0, 1.0).into(),
+            })
+            .collect()
+    }
+
+    fn draw_quad(backend: &mut WgpuBackend, vertices: &[BatchVertex], textures: &[i32]) {
+        backend.draw(&BatchDraw {
+            mvp: glm::identity(),
+            vertices,
+            indices: &[0, 1, 2, 2, 1, 3],
+            instances: &[],
+            textures,
+            blend_mode: BlendMode::Alpha,
+            mask: MaskState::Off,
+            scissor: backend.viewport(),
+            material: None,
+        });
+    }
+
+    // the tests need a wgpu adapter, a fallback (software) one is enough. Run them with `--include-ignored`
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn clears_are_read_back() {
+        let mut backend = WgpuBackend::with_fallback_adapter(4, 4).expect("No wgpu fallback adapter");
+
+        backend.set_clear_color((1.0, 0.0, 0.0, 1.0));
+        backend.clear(None);
+        backend.set_clear_color((0.0, 1.0, 0.0, 1.0));
+        backend.clear(Some([0, 0, 2, 4]));
+
+        let image = backend.read_pixels();
+
+        assert_eq!(image.get_pixel(0, 0).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [255, 0, 0, 255]);
+        assert!(backend.commands.is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn batches_of_a_frame_are_drawn_in_one_submit() {
+        let mut backend = WgpuBackend::with_fallback_adapter(4, 4).expect("No wgpu fallback adapter");
+        let blue = backend.create_texture(1, 1, &[0, 0, 255, 255]);
+
+        backend.clear(None);
+        draw_quad(&mut backend, &half_quad(-1.0, (1.0, 0.0, 0.0, 1.0), -1.0), &[]);
+        draw_quad(&mut backend, &half_quad(0.0, (1.0, 1.0, 1.0, 1.0), 0.0), &[blue as i32]);
+        draw_quad(&mut backend, &half_quad(0.0, (1.0, 1.0, 1.0, 0.0), 0.0), &[blue as i32]);
+
+        assert_eq!(backend.commands.len(), 4);
+        // the batches with the same texture share a bind group
+        assert_eq!(backend.bind_groups.len(), 2);
+
+        let image = backend.read_pixels();
+
+        assert_eq!(image.get_pixel(1, 2).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 1).0, [0, 0, 255, 255]);
+
+        backend.delete_texture(blue);
+
+        assert_eq!(backend.bind_groups.len(), 1);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn render_textures_are_drawn_top_row_first() {
+        let backend = WgpuBackend::with_fallback_adapter(4, 4).expect("No wgpu fallback adapter");
+        let camera = Camera::new(4.0, 4.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::with_backend(backend);
+        let target = RenderTexture::new(renderer.get_backend_mut(), 4, 4).unwrap();
+
+        renderer.begin_batch();
+        renderer.submit(&shapes::convex_polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)], (1.0, 0.0, 0.0, 1.0)));
+        renderer.end_batch();
+        renderer.render_to_texture(&target, &camera).unwrap();
+
+        let backend = renderer.get_backend_mut();
+
+        backend.set_render_target(Some(target.get_texture_handle()));
+
+        let image = backend.read_pixels();
+
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 0, 0]);
+
+        target.delete(backend);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn renderer_submits_every_frame() {
+        let backend = WgpuBackend::with_fallback_adapter(4, 4).expect("No wgpu fallback adapter");
+        let camera = Camera::new(4.0, 4.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::with_backend(backend);
+
+        renderer.clear();
+        renderer.begin_batch();
+        renderer.submit(&shapes::convex_polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)], (1.0, 0.0, 0.0, 1.0)));
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+
+        assert!(renderer.get_backend_as::<super::super::SoftwareBackend>().is_none());
+
+        let backend = renderer.get_backend_as_mut::<WgpuBackend>().unwrap();
+        let target = backend.get_render_target();
+
+        assert!(backend.commands.is_empty());
+        assert!(backend.get_texture(target).is_some());
+    }
+}
@@ -0,0 +1,119 @@
+// the batch shader in WGSL, the same stages as `batch_shaders::create_vertex_source` and
+// `create_fragment_source`. Glyph atlases are white with coverage in alpha, so text is drawn with it too.
+// WGSL can't index an array of textures with a per vertex index, so every slot is its own binding
+// and `batch_texture` switches over them like the GLSL version
+pub(super) fn create_batch_source(max_textures: usize) -> String {
+    let bindings: Vec<String> = (0..max_textures)
+        .map(|i| format!("@group(1) @binding({idx}) var texture{idx}: texture_2d<f32>;\n", idx = i))
+        .collect();
+    let switches: Vec<String> = (0..max_textures)
+        .map(|i| format!("
+        case {idx}: {{
+            return textureSampleLevel(texture{idx}, batch_sampler, uv, 0.0);
+        }}", idx = i))
+        .collect();
+
+    let source_parts = [
+        r#"
+struct Uniforms {
+    mvp: mat4x4<f32>,
+    alpha_cutoff: f32,
+}
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+"#.to_string(),
+        bindings.join(""),
+        format!("@group(1) @binding({}) var batch_sampler: sampler;\n", max_textures),
+        r#"
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) tex_index: f32,
+    @location(4) tex_translate: vec3<f32>,
+    @location(5) tex_scale: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) tex_index: f32,
+}
+
+@vertex
+fn vs_main(input: VertexInput) -> VertexOutput {
+    var output: VertexOutput;
+    let position = uniforms.mvp * vec4<f32>(input.position, 1.0);
+
+    // cameras project depth from -w to w like gl, wgpu clips it from 0 to w
+    output.position = vec4<f32>(position.xy, (position.z + position.w) * 0.5, position.w);
+    output.tex_coord = input.tex_coord * input.tex_scale.xy + input.tex_translate.xy;
+    output.color = input.color;
+    output.tex_index = input.tex_index;
+
+    return output;
+}
+
+// sampled without mipmaps so it can be called outside uniform control flow
+fn batch_texture(index: i32, uv: vec2<f32>) -> vec4<f32> {
+    switch index {"#.to_string(),
+        switches.join(""),
+        r#"
+        default: {
+            return vec4<f32>(1.0);
+        }
+    }
+}
+
+@fragment
+fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
+    let color = batch_texture(i32(input.tex_index + 0.5), input.tex_coord) * input.color;
+
+    if color.a < uniforms.alpha_cutoff {
+        discard;
+    }
+
+    return color;
+}
+"#.to_string(),
+    ];
+
+    source_parts.join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{WgpuBackend};
+
+    #[test]
+    fn every_slot_has_a_binding_and_a_case() {
+        let source = create_batch_source(3);
+
+        assert!(source.contains("@group(1) @binding(2) var texture2: texture_2d<f32>;"));
+        assert!(source.contains("@group(1) @binding(3) var batch_sampler: sampler;"));
+        assert!(source.contains("case 2: {"));
+        assert!(!source.contains("case 3: {"));
+    }
+
+    // validated by the device of wgpu's fallback adapter, run with `--include-ignored`
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn batch_source_is_valid_wgsl() {
+        let backend = WgpuBackend::with_fallback_adapter(1, 1).expect("No wgpu fallback adapter");
+        let device = backend.get_device();
+
+        for &max_textures in &[1, 8, 16] {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(create_batch_source(max_textures).into()),
+            });
+
+            let error = pollster::block_on(device.pop_error_scope());
+
+            assert!(error.is_none(), "{} textures: {:?}", max_textures, error);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+/// How `Renderer2D` gives each quad in a batch its texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BatchMode {
+    /// Every texture in a batch gets its own texture unit, a batch is flushed once it
+    /// has `MAX_TEXTURE_IMAGE_UNITS` textures
+    #[default]
+    TextureUnits,
+    /// Textures are copied into the layers of one `GL_TEXTURE_2D_ARRAY` and quads pick their layer,
+    /// so any number of textures share one batch. Smaller textures are padded into a layer, bigger
+    /// ones are scaled down to fit. `max_layers` is capped to `GL_MAX_ARRAY_TEXTURE_LAYERS`, a batch is
+    /// flushed when more textures than that are used so the layers can be reused after it.
+    /// Submissions with uvs outside of their texture (texture scale, tiling) are drawn from the texture's
+    /// own unit instead, so they wrap or clamp the way the texture does. Lighting doesn't use normal maps in this mode
+    TextureArray {
+        layer_width: u32,
+        layer_height: u32,
+        max_layers: u32,
+    },
+}
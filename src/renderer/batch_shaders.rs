@@ -1,15 +1,16 @@
-pub fn create_fragment_source(max_textures: i32) -> String {
+// every batch fragment shader starts with this, it declares the batch textures, the outputs of
+// the vertex stage and `batchTexture(uv)` which samples whatever texture the vertex uses
+pub fn create_fragment_header(max_textures: i32) -> String {
     let mut switches = Vec::new();
 
     for i in 0..max_textures {
         switches.push(format!("
         case  {idx}:
-            texColor = texture2D(Textures[{idx}u], IN.TexCoord) * vec4(IN.TexColor.x, IN.TexColor.y, IN.TexColor.z, IN.TexColor.w);
-            break;
+            return texture(Textures[{idx}u], uv);
         ", idx = i));
     }
 
-    let shader_parts = vec![
+    let header_parts = vec![
         format!("#version 330 core
 
         precision mediump float;
@@ -17,6 +18,8 @@ pub fn create_fragment_source(max_textures: i32) -> String {
         uniform sampler2D Textures[{max_texs}];
         ", max_texs = max_textures),
         r#"
+        uniform float AlphaCutoff;
+
         in VS_OUTPUT {
             vec2 TexCoord;
             vec4 TexColor;
@@ -25,10 +28,8 @@ pub fn create_fragment_source(max_textures: i32) -> String {
 
         out vec4 Color;
 
-        void main() {
+        vec4 batchTexture(vec2 uv) {
             int ttid = int(IN.TexIndex + 0.5);
-            vec4 baseColor = vec4(IN.TexColor.x, IN.TexColor.y, IN.TexColor.z, IN.TexColor.w);
-            vec4 texColor = baseColor;
 
             switch (ttid)
             {
@@ -36,16 +37,96 @@ pub fn create_fragment_source(max_textures: i32) -> String {
         switches.join(""),
         r#"
                 default:
-                    texColor = baseColor;
-                    break;
+                    return vec4(1.0);
             }
-
-            Color = texColor;
         }
         "#.to_string(),
     ];
 
-    shader_parts.join("")
+    header_parts.join("")
+}
+
+/// Header for `BatchMode::TextureArray`, `TexIndex` is the array layer or -1 for no texture.
+/// Textures the array has no room for are bound to the units after the array's, their quads
+/// count down from -2 for unit 1
+pub fn create_array_fragment_header(max_textures: i32) -> String {
+    let unit_textures = (max_textures - 1).max(1);
+    let mut switches = Vec::new();
+
+    for i in 0..unit_textures {
+        switches.push(format!("
+        case  {idx}:
+            return texture(UnitTextures[{idx}u], uv);
+        ", idx = i));
+    }
+
+    let header_parts = [
+        format!("#version 330 core
+
+precision mediump float;
+
+uniform sampler2DArray Textures;
+uniform sampler2D UnitTextures[{unit_texs}];
+", unit_texs = unit_textures),
+        r#"
+uniform float AlphaCutoff;
+
+in VS_OUTPUT {
+    vec2 TexCoord;
+    vec4 TexColor;
+    float TexIndex;
+} IN;
+
+out vec4 Color;
+
+vec4 unitTexture(int unit, vec2 uv) {
+    switch (unit)
+    {
+"#.to_string(),
+        switches.join(""),
+        r#"
+        default:
+            return vec4(1.0);
+    }
+}
+
+vec4 batchTexture(vec2 uv) {
+    float layer = floor(IN.TexIndex + 0.5);
+
+    if (layer < -1.5) {
+        return unitTexture(int(-layer) - 2, uv);
+    }
+
+    if (layer < 0.0) {
+        return vec4(1.0);
+    }
+
+    return texture(Textures, vec3(uv, layer));
+}
+"#.to_string(),
+    ];
+
+    header_parts.join("")
+}
+
+const DEFAULT_FRAGMENT_MAIN: &str = r#"
+void main() {
+    vec4 texColor = batchTexture(IN.TexCoord) * IN.TexColor;
+
+    if (texColor.a < AlphaCutoff) {
+        discard;
+    }
+
+    Color = texColor;
+}
+"#;
+
+pub fn create_fragment_source(max_textures: i32) -> String {
+    create_fragment_header(max_textures) + DEFAULT_FRAGMENT_MAIN
+}
+
+pub fn create_array_fragment_source(max_textures: i32) -> String {
+    create_array_fragment_header(max_textures) + DEFAULT_FRAGMENT_MAIN
 }
 
 pub fn create_vertex_source() -> String {
@@ -84,6 +165,39 @@ void main() {
     src.to_string()
 }
 
+/// Vertex stage for instanced batches, a unit quad corner per vertex and a `QuadInstance` per quad.
+/// Its outputs are the same as `create_vertex_source`, so it works with every batch fragment shader
+pub fn create_instanced_vertex_source() -> String {
+    let src = r#"
+#version 330 core
+
+layout (location = 0) in vec2 Corner;
+layout (location = 1) in vec3 TransformX;
+layout (location = 2) in vec3 TransformY;
+layout (location = 3) in vec4 UvRect;
+layout (location = 4) in vec4 TexColor;
+layout (location = 5) in float TexIndex;
+
+uniform mat4 MVP;
+
+out VS_OUTPUT {
+    vec2 TexCoord;
+    vec4 TexColor;
+    float TexIndex;
+} OUT;
+
+void main() {
+    vec3 corner = vec3(Corner, 1.0);
+
+    gl_Position = MVP * vec4(dot(TransformX, corner), dot(TransformY, corner), 0.0, 1.0);
+    OUT.TexColor = TexColor;
+    OUT.TexIndex = TexIndex;
+    OUT.TexCoord = mix(UvRect.xy, UvRect.zw, Corner);
+}"#;
+
+    src.to_string()
+}
+
 //#[cfg(test)]
 //mod test {
 //    use super::*;
@@ -0,0 +1,60 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    #[default]
+    Alpha,
+    PremultipliedAlpha,
+    Additive,
+    Multiply,
+    Screen,
+    Opaque,
+}
+
+impl BlendMode {
+    /// Source and destination factors for `glBlendFunc`, `None` when blending is disabled
+    pub fn factors(self) -> Option<(gl::types::GLenum, gl::types::GLenum)> {
+        match self {
+            BlendMode::Alpha => Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)),
+            BlendMode::PremultipliedAlpha => Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)),
+            BlendMode::Additive => Some((gl::SRC_ALPHA, gl::ONE)),
+            // exact for opaque or premultiplied colors, transparent pixels leave the destination alone
+            BlendMode::Multiply => Some((gl::DST_COLOR, gl::ONE_MINUS_SRC_ALPHA)),
+            BlendMode::Screen => Some((gl::ONE, gl::ONE_MINUS_SRC_COLOR)),
+            BlendMode::Opaque => None,
+        }
+    }
+
+    pub fn apply(self) {
+        match self.factors() {
+            Some((src, dst)) => unsafe {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(src, dst);
+            },
+            None => unsafe {
+                gl::Disable(gl::BLEND);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_blend_mode_is_alpha() {
+        assert_eq!(BlendMode::Alpha, BlendMode::default());
+    }
+
+    #[test]
+    fn blend_modes_map_to_blend_factors() {
+        assert_eq!(Some((gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA)), BlendMode::Alpha.factors());
+        assert_eq!(Some((gl::ONE, gl::ONE_MINUS_SRC_ALPHA)), BlendMode::PremultipliedAlpha.factors());
+        assert_eq!(Some((gl::SRC_ALPHA, gl::ONE)), BlendMode::Additive.factors());
+        assert_eq!(Some((gl::DST_COLOR, gl::ONE_MINUS_SRC_ALPHA)), BlendMode::Multiply.factors());
+        assert_eq!(Some((gl::ONE, gl::ONE_MINUS_SRC_COLOR)), BlendMode::Screen.factors());
+        assert_eq!(None, BlendMode::Opaque.factors());
+    }
+}
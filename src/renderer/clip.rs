@@ -0,0 +1,164 @@
+/// Rectangle to clip drawing to, `x` and `y` are the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ClipRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> ClipRect {
+        ClipRect { x, y, width, height }
+    }
+}
+
+/// What a `ClipRect` is measured in. `World` uses the camera a layer is drawn with, `Screen`
+/// is in pixels of that camera's dimensions from the top left, the same as the ui camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipSpace {
+    World,
+    Screen,
+}
+
+// the clip rects pushed so far, kept as they were pushed so every camera that draws them
+// measures them itself
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct Clip {
+    rects: Vec<(ClipRect, ClipSpace)>,
+}
+
+impl Clip {
+    pub(super) fn push(&self, rect: ClipRect, space: ClipSpace) -> Clip {
+        let mut rects = self.rects.clone();
+
+        rects.push((rect, space));
+
+        Clip { rects }
+    }
+
+    /// Where all the rects overlap, for a camera's view projection and dimensions
+    pub(super) fn to_ndc(&self, camera_mvp: &glm::Mat4, camera_size: (f32, f32)) -> NdcRect {
+        let full = NdcRect { min: (-1.0, -1.0), max: (1.0, 1.0) };
+
+        self.rects.iter()
+            .map(|(rect, space)| NdcRect::from_clip_rect(rect, *space, camera_mvp, camera_size))
+            .fold(full, |clip, rect| clip.intersect(&rect))
+    }
+}
+
+// a clip rect in normalized device coordinates of the camera drawing it, so nested clips in
+// different spaces can be intersected and it only has to be matched to a viewport when drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct NdcRect {
+    min: (f32, f32),
+    max: (f32, f32),
+}
+
+impl NdcRect {
+    pub(super) fn from_clip_rect(rect: &ClipRect, space: ClipSpace, camera_mvp: &glm::Mat4, camera_size: (f32, f32)) -> NdcRect {
+        let corners = [(rect.x, rect.y), (rect.x + rect.width, rect.y + rect.height)];
+        let (width, height) = camera_size;
+
+        match space {
+            ClipSpace::World => NdcRect::bounding(&corners, camera_mvp),
+            ClipSpace::Screen => {
+                let to_ndc = glm::mat4(
+                    2.0 / width, 0.0, 0.0, -1.0,
+                    0.0, -2.0 / height, 0.0, 1.0,
+                    0.0, 0.0, 1.0, 0.0,
+                    0.0, 0.0, 0.0, 1.0,
+                );
+
+                NdcRect::bounding(&corners, &to_ndc)
+            },
+        }
+    }
+
+    // smallest rect around the corners after they are transformed
+    fn bounding(corners: &[(f32, f32)], transform: &glm::Mat4) -> NdcRect {
+        let points: Vec<glm::Vec4> = corners.iter()
+            .map(|&(x, y)| transform * glm::vec4(x, y, 0.0, 1.0))
+            .collect();
+        let min_x = points.iter().map(|point| point.x).fold(f32::INFINITY, f32::min);
+        let min_y = points.iter().map(|point| point.y).fold(f32::INFINITY, f32::min);
+        let max_x = points.iter().map(|point| point.x).fold(f32::NEG_INFINITY, f32::max);
+        let max_y = points.iter().map(|point| point.y).fold(f32::NEG_INFINITY, f32::max);
+
+        NdcRect { min: (min_x, min_y), max: (max_x, max_y) }
+    }
+
+    /// Empty (max == min) when they don't overlap
+    pub(super) fn intersect(&self, other: &NdcRect) -> NdcRect {
+        let min = (self.min.0.max(other.min.0), self.min.1.max(other.min.1));
+        let max = (self.max.0.min(other.max.0).max(min.0), self.max.1.min(other.max.1).max(min.1));
+
+        NdcRect { min, max }
+    }
+
+    /// `glScissor` box (x, y, width, height) for the current viewport, `target_transform` is applied
+    /// first so clips follow the flip used when rendering to a texture
+    pub(super) fn scissor_box(&self, target_transform: &glm::Mat4, viewport: [i32; 4]) -> [i32; 4] {
+        let rect = NdcRect::bounding(&[self.min, self.max], target_transform);
+        let [x, y, width, height] = viewport;
+        let to_x = |ndc: f32| x as f32 + (ndc.clamp(-1.0, 1.0) + 1.0) / 2.0 * width as f32;
+        let to_y = |ndc: f32| y as f32 + (ndc.clamp(-1.0, 1.0) + 1.0) / 2.0 * height as f32;
+        let (left, bottom) = (to_x(rect.min.0).floor() as i32, to_y(rect.min.1).floor() as i32);
+        let (right, top) = (to_x(rect.max.0).ceil() as i32, to_y(rect.max.1).ceil() as i32);
+
+        [left, bottom, right - left, top - bottom]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_rect_maps_to_ndc_from_the_top_left() {
+        let rect = ClipRect::new(0.0, 0.0, 50.0, 25.0);
+        let ndc = NdcRect::from_clip_rect(&rect, ClipSpace::Screen, &glm::identity(), (100.0, 100.0));
+
+        assert_eq!(ndc, NdcRect { min: (-1.0, 0.5), max: (0.0, 1.0) });
+    }
+
+    #[test]
+    fn world_rect_uses_the_camera_transform() {
+        let mvp = glm::scale(&glm::identity(), &glm::vec3(0.5, -0.5, 1.0));
+        let ndc = NdcRect::from_clip_rect(&ClipRect::new(-1.0, -1.0, 2.0, 2.0), ClipSpace::World, &mvp, (1.0, 1.0));
+
+        assert_eq!(ndc, NdcRect { min: (-0.5, -0.5), max: (0.5, 0.5) });
+    }
+
+    #[test]
+    fn nested_clips_intersect() {
+        let outer = NdcRect { min: (-1.0, -1.0), max: (0.5, 0.5) };
+        let inner = NdcRect { min: (0.0, 0.0), max: (1.0, 1.0) };
+        let apart = NdcRect { min: (0.75, 0.75), max: (1.0, 1.0) };
+
+        assert_eq!(outer.intersect(&inner), NdcRect { min: (0.0, 0.0), max: (0.5, 0.5) });
+        assert_eq!(outer.intersect(&apart), NdcRect { min: (0.75, 0.75), max: (0.75, 0.75) });
+    }
+
+    #[test]
+    fn clips_are_measured_with_the_camera_drawing_them() {
+        let clip = Clip::default()
+            .push(ClipRect::new(0.0, 0.0, 100.0, 50.0), ClipSpace::Screen)
+            .push(ClipRect::new(0.0, 0.0, 10.0, 10.0), ClipSpace::World);
+        let zoomed = glm::scale(&glm::identity(), &glm::vec3(0.05, 0.05, 1.0));
+
+        assert_eq!(clip.to_ndc(&glm::identity(), (100.0, 100.0)), NdcRect { min: (0.0, 0.0), max: (1.0, 1.0) });
+        assert_eq!(clip.to_ndc(&zoomed, (100.0, 100.0)), NdcRect { min: (0.0, 0.0), max: (0.5, 0.5) });
+        assert_eq!(clip.to_ndc(&zoomed, (200.0, 200.0)), NdcRect { min: (0.0, 0.5), max: (0.0, 0.5) });
+    }
+
+    #[test]
+    fn scissor_box_is_in_viewport_pixels() {
+        let rect = NdcRect { min: (-1.0, 0.0), max: (0.0, 1.0) };
+        let flip_y = glm::scale(&glm::identity(), &glm::vec3(1.0, -1.0, 1.0));
+
+        assert_eq!(rect.scissor_box(&glm::identity(), [0, 0, 200, 100]), [0, 50, 100, 50]);
+        assert_eq!(rect.scissor_box(&flip_y, [0, 0, 200, 100]), [0, 0, 100, 50]);
+        assert_eq!(rect.scissor_box(&glm::identity(), [10, 20, 200, 100]), [10, 70, 100, 50]);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::helpers::{data, buffer};
+use super::renderable::{QuadInstance};
+
+// the four corners of the unit quad every instance is drawn from, in the same
+// order as the corners of a sprite
+const UNIT_QUAD: [(f32, f32); 4] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+
+#[derive(VertexAttribPointers)]
+#[derive(Debug)]
+#[repr(C, packed)]
+struct QuadCorner {
+    #[location=0]
+    pos: data::f32_f32,
+}
+
+/// Per instance attributes of an instanced batch, 60 bytes a quad instead of 4 `BatchVertex`
+#[derive(VertexAttribPointers)]
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub(super) struct InstanceData {
+    #[location=1]
+    transform_x: data::f32_f32_f32,
+    #[location=2]
+    transform_y: data::f32_f32_f32,
+    #[location=3]
+    uv_rect: data::f32_f32_f32_f32,
+    #[location=4]
+    color: data::f32_f32_f32_f32,
+    #[location=5]
+    tex_id: data::f32_,
+}
+
+const INSTANCE_LOCATIONS: [u32; 5] = [1, 2, 3, 4, 5];
+
+impl InstanceData {
+    /// `uv_scale` moves the uvs onto the part of a texture array layer the texture was copied into
+    pub(super) fn new(instance: &QuadInstance, tex_id: f32, uv_scale: Option<(f32, f32)>) -> InstanceData {
+        let [transform_x, transform_y] = instance.transform;
+        let (u, v, u2, v2) = instance.uv_rect;
+        let (scale_u, scale_v) = uv_scale.unwrap_or((1.0, 1.0));
+
+        InstanceData {
+            transform_x: transform_x.into(),
+            transform_y: transform_y.into(),
+            uv_rect: (u * scale_u, v * scale_v, u2 * scale_u, v2 * scale_v).into(),
+            color: instance.color.into(),
+            tex_id: tex_id.into(),
+        }
+    }
+}
+
+/// The unit quad and the buffer of instances drawn over it, with their own vertex array
+#[derive(Debug)]
+pub(super) struct InstancedQuad {
+    vao: buffer::VertexArray,
+    instance_vbo: buffer::StreamArrayBuffer,
+    // only drawn through the vao, kept so they aren't deleted
+    _quad_buffers: (buffer::ArrayBuffer, buffer::ElementArrayBuffer),
+}
+
+impl InstancedQuad {
+    pub(super) fn new(max_instances: usize, stream_mode: buffer::StreamMode) -> InstancedQuad {
+        let max_buffer_size = ::std::mem::size_of::<InstanceData>() * max_instances * super::STREAM_BATCHES;
+        let corners: Vec<QuadCorner> = UNIT_QUAD.iter().map(|&pos| QuadCorner { pos: pos.into() }).collect();
+        let vao = buffer::VertexArray::new();
+        let quad_vbo = buffer::ArrayBuffer::new();
+        let ibo = buffer::ElementArrayBuffer::new();
+        let instance_vbo = buffer::StreamArrayBuffer::new(max_buffer_size, stream_mode);
+
+        vao.bind();
+
+        quad_vbo.bind();
+        quad_vbo.static_draw_data(&corners);
+        QuadCorner::vertex_attrib_pointers();
+
+        instance_vbo.bind();
+        InstanceData::vertex_attrib_pointers();
+
+        // instance attributes move on once per quad instead of once per corner
+        for location in INSTANCE_LOCATIONS.iter() {
+            unsafe {
+                gl::VertexAttribDivisor(*location, 1);
+            }
+        }
+
+        ibo.bind();
+        ibo.static_draw_data(&super::QUAD_INDICES);
+
+        vao.unbind();
+        ibo.unbind();
+        instance_vbo.unbind();
+
+        InstancedQuad { vao, instance_vbo, _quad_buffers: (quad_vbo, ibo) }
+    }
+
+    /// Draws every instance in one draw call, the program and textures have to be set up already
+    pub(super) fn draw(&mut self, instances: &[InstanceData]) -> Result<(), buffer::Error> {
+        self.vao.bind();
+        self.instance_vbo.bind();
+        // instanced batches hold at most max_sprites instances, which the buffer is sized for
+        let uploaded = self.instance_vbo.upload(instances);
+
+        if let Ok(offset) = uploaded {
+            // there's no base instance before gl 4.2, so the instance attributes point at the upload instead
+            InstanceData::vertex_attrib_pointers_at(offset);
+
+            unsafe {
+                gl::DrawElementsInstanced(
+                    gl::TRIANGLES,
+                    super::QUAD_INDICES.len() as i32,
+                    gl::UNSIGNED_INT,
+                    ::std::ptr::null(),
+                    instances.len() as i32,
+                );
+            }
+        }
+
+        self.instance_vbo.fence();
+        self.instance_vbo.unbind();
+        self.vao.unbind();
+
+        uploaded.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_data_scales_uvs_into_array_layers() {
+        let instance = QuadInstance {
+            transform: [(2.0, 0.0, 5.0), (0.0, 3.0, 6.0)],
+            uv_rect: (0.5, 0.0, 1.0, 1.0),
+            color: (1.0, 1.0, 1.0, 1.0),
+        };
+        let data = InstanceData::new(&instance, 2.0, Some((0.5, 0.25)));
+        let uv_rect = data.uv_rect;
+
+        assert_eq!((uv_rect.d0, uv_rect.d1, uv_rect.d2, uv_rect.d3), (0.25, 0.0, 0.5, 0.25));
+        assert_eq!(instance.corner((1.0, 1.0)), (7.0, 9.0));
+    }
+}
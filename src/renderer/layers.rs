@@ -39,6 +39,18 @@ impl Layer {
         self.id.clone()
     }
 
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_kind(&self) -> &LayerKind {
+        &self.kind
+    }
+
+    pub fn get_ordinal(&self) -> i32 {
+        self.ordinal
+    }
+
     pub fn on_attach() {
 
     }
@@ -86,6 +98,15 @@ impl Layers {
     pub fn sort(&mut self) {
         self.layers.sort();
     }
+
+    pub fn get(&self, id: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    /// Iterates layers in draw order, Background first and Overlay last
+    pub fn iter(&self) -> std::slice::Iter<'_, Layer> {
+        self.layers.iter()
+    }
 }
 
 #[cfg(test)]
@@ -184,4 +205,32 @@ mod tests {
 
         assert_eq!(expected_layers, actual_layers.layers);
     }
+
+    #[test]
+    fn can_get_layer_by_id() {
+        let test_layer = Layer::new("test_layer", LayerKind::UI, 1);
+        let test_layer_id = test_layer.get_id();
+        let mut actual_layers = Layers::new();
+
+        actual_layers.add(test_layer.clone());
+        actual_layers.add(Layer::new("test_layer_two", LayerKind::Generic, 1));
+
+        assert_eq!(Some(&test_layer), actual_layers.get(&test_layer_id));
+        assert_eq!(None, actual_layers.get("not_a_layer"));
+    }
+
+    #[test]
+    fn iterates_layers_in_draw_order() {
+        let mut actual_layers = Layers::new();
+
+        actual_layers.add(Layer::new("overlay", LayerKind::Overlay, 0));
+        actual_layers.add(Layer::new("ui", LayerKind::UI, 0));
+        actual_layers.add(Layer::new("background", LayerKind::Background, 0));
+        actual_layers.add(Layer::new("foreground", LayerKind::Foreground, 0));
+        actual_layers.add(Layer::new("generic", LayerKind::Generic, 0));
+
+        let names: Vec<&str> = actual_layers.iter().map(|layer| layer.get_name()).collect();
+
+        assert_eq!(vec!["background", "generic", "foreground", "ui", "overlay"], names);
+    }
 }
@@ -0,0 +1,102 @@
+// alpha below this is discarded when writing a mask, so only the visible part of a sprite masks
+const MASK_ALPHA_CUTOFF: f32 = 0.5;
+
+// the top bit of the stencil marks the pixels lit layers cover for the lighting,
+// masks use the other bits so they are still there for the layers drawn after the lighting
+pub(super) const COVERAGE_BIT: u8 = 0x80;
+pub(super) const MASK_BITS: u8 = !COVERAGE_BIT;
+
+// what a batch does with the stencil buffer, the u8 is the stencil value of the mask
+// it writes or is drawn through. `Cover` sets the coverage bit where the batch is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub(super) enum MaskState {
+    #[default]
+    Off,
+    Write(u8),
+    Inside(u8),
+    Outside(u8),
+    Cover,
+}
+
+impl MaskState {
+    pub(super) fn is_masked(self) -> bool {
+        self != MaskState::Off
+    }
+
+    /// Fragments with less alpha than this are discarded by the batch shader
+    pub(super) fn alpha_cutoff(self) -> f32 {
+        match self {
+            MaskState::Write(_) | MaskState::Cover => MASK_ALPHA_CUTOFF,
+            _ => 0.0,
+        }
+    }
+
+    pub(super) fn apply(self) {
+        unsafe {
+            match self {
+                MaskState::Off => {
+                    gl::Disable(gl::STENCIL_TEST);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                },
+                MaskState::Write(reference) => {
+                    gl::Enable(gl::STENCIL_TEST);
+                    gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+                    gl::StencilMask(MASK_BITS as u32);
+                    gl::StencilFunc(gl::ALWAYS, reference as i32, MASK_BITS as u32);
+                    gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+                },
+                MaskState::Inside(reference) | MaskState::Outside(reference) => {
+                    let func = if let MaskState::Inside(_) = self { gl::EQUAL } else { gl::NOTEQUAL };
+
+                    gl::Enable(gl::STENCIL_TEST);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                    gl::StencilFunc(func, reference as i32, MASK_BITS as u32);
+                    gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+                },
+                MaskState::Cover => {
+                    gl::Enable(gl::STENCIL_TEST);
+                    gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+                    gl::StencilMask(COVERAGE_BIT as u32);
+                    gl::StencilFunc(gl::ALWAYS, COVERAGE_BIT as i32, COVERAGE_BIT as u32);
+                    gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+                },
+            }
+        }
+    }
+}
+
+// stencil values go from 1 to 127, 0 is the cleared buffer
+pub(super) fn next_reference(reference: u8) -> u8 {
+    reference % MASK_BITS + 1
+}
+
+// clears the coverage bit of the bound framebuffer's stencil, leaving the masks alone
+pub(super) fn clear_coverage() {
+    unsafe {
+        gl::Disable(gl::SCISSOR_TEST);
+        gl::StencilMask(COVERAGE_BIT as u32);
+        gl::ClearStencil(0);
+        gl::Clear(gl::STENCIL_BUFFER_BIT);
+        gl::StencilMask(0xFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_skip_the_cleared_value() {
+        assert_eq!(next_reference(0), 1);
+        assert_eq!(next_reference(1), 2);
+        assert_eq!(next_reference(127), 1);
+    }
+
+    #[test]
+    fn only_mask_writes_are_alpha_tested() {
+        assert_eq!(MaskState::Write(1).alpha_cutoff(), MASK_ALPHA_CUTOFF);
+        assert_eq!(MaskState::Cover.alpha_cutoff(), MASK_ALPHA_CUTOFF);
+        assert_eq!(MaskState::Inside(1).alpha_cutoff(), 0.0);
+        assert_eq!(MaskState::Off.alpha_cutoff(), 0.0);
+    }
+}
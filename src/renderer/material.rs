@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use crate::helpers::{self, system, UniformValue};
+use super::batch_mode::{BatchMode};
+use super::batch_shaders::{create_fragment_header, create_array_fragment_header, create_vertex_source, create_instanced_vertex_source};
+
+/// Returned by `Renderer2D::add_material`, used to submit with that material
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub(super) usize);
+
+// locations of the uniforms the renderer sets on every batch program, -1 when a program
+// doesn't use one, which gl ignores
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct BatchUniforms {
+    pub(super) textures: i32,
+    pub(super) unit_textures: i32,
+    pub(super) mvp: i32,
+    pub(super) alpha_cutoff: i32,
+}
+
+impl BatchUniforms {
+    pub(super) fn locate(program: &helpers::Program) -> BatchUniforms {
+        let location = |name: &str| program.get_uniform_location(name).unwrap_or(-1);
+
+        BatchUniforms {
+            textures: location("Textures"),
+            unit_textures: location("UnitTextures"),
+            mvp: location("MVP"),
+            alpha_cutoff: location("AlphaCutoff"),
+        }
+    }
+}
+
+// where a uniform of the material is in its program and in its instanced program, located once
+// when the uniform is first set instead of on every batch
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct UniformLocations {
+    program: i32,
+    instanced: i32,
+}
+
+/// A program for the batch renderer with the uniform values and textures it is drawn with.
+/// The vertex stage gets `BatchVertex` attributes (position 0, uv 1, colour 2, texture index 3,
+/// texture translate 4 and scale 5) and the renderer sets `MVP`, `Textures` and `AlphaCutoff`
+#[derive(Debug)]
+pub struct Material {
+    name: String,
+    program: helpers::Program,
+    batch_uniforms: BatchUniforms,
+    // the same fragment shader with the instanced vertex stage, for instanced batches
+    instanced: Option<(helpers::Program, BatchUniforms)>,
+    uniforms: HashMap<String, (UniformLocations, UniformValue)>,
+    textures: Vec<(String, UniformLocations, gl::types::GLuint)>,
+}
+
+impl Material {
+    pub fn new(name: &str, program: helpers::Program) -> Material {
+        Material {
+            name: name.to_string(),
+            batch_uniforms: BatchUniforms::locate(&program),
+            program,
+            instanced: None,
+            uniforms: HashMap::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    /// Compiles `fragment_source` with the batch vertex shader. The source is appended to a header
+    /// for `batch_mode` that declares `IN.TexCoord`, `IN.TexColor`, the output `Color`, a `sampler2D`
+    /// for each of `texture_names` and `batchTexture(uv)`, which samples the texture the quad was
+    /// submitted with, so it only needs a `main`:
+    /// `void main() { Color = batchTexture(IN.TexCoord) * IN.TexColor; }`.
+    /// It is also compiled for instanced batches, a material from `Material::new` is never drawn instanced
+    pub fn from_fragment_source(
+        name: &str,
+        fragment_source: &str,
+        texture_names: &[&str],
+        batch_mode: BatchMode,
+    ) -> Result<Material, failure::Error> {
+        let max_textures = system::SystemInfo::get_max_textures() as usize;
+        let batch_textures = max_textures.saturating_sub(texture_names.len()).max(1) as i32;
+        let mut header = match batch_mode {
+            BatchMode::TextureUnits => create_fragment_header(batch_textures),
+            BatchMode::TextureArray { .. } => create_array_fragment_header(batch_textures),
+        };
+
+        for texture_name in texture_names {
+            header += &format!("uniform sampler2D {};\n", texture_name);
+        }
+
+        let fragment_source = header + fragment_source;
+        let program = link_program(name, &create_vertex_source(), &fragment_source)?;
+        let instanced_program = link_program(&format!("{}/instanced", name), &create_instanced_vertex_source(), &fragment_source)?;
+        let mut material = Material::new(name, program);
+
+        let instanced_uniforms = BatchUniforms::locate(&instanced_program);
+
+        material.instanced = Some((instanced_program, instanced_uniforms));
+
+        for texture_name in texture_names {
+            material.set_texture(texture_name, 0);
+        }
+
+        Ok(material)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_program(&self) -> &helpers::Program {
+        &self.program
+    }
+
+    pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        match self.uniforms.get_mut(name) {
+            Some(uniform) => uniform.1 = value,
+            None => {
+                let locations = self.locate(name);
+
+                self.uniforms.insert(name.to_string(), (locations, value));
+            },
+        }
+    }
+
+    pub fn get_uniform(&self, name: &str) -> Option<&UniformValue> {
+        self.uniforms.get(name).map(|(_, value)| value)
+    }
+
+    /// Binds an extra texture to the `name` sampler. Extra textures take the last texture units,
+    /// so batches with this material have that many fewer slots for sprite textures and a program
+    /// from `Material::new` has to leave those units free. Add every texture before submitting
+    /// with the material. The texture is not owned by the material and has to outlive it
+    pub fn set_texture(&mut self, name: &str, texture_handle: gl::types::GLuint) {
+        match self.textures.iter_mut().find(|(texture_name, _, _)| texture_name == name) {
+            Some(texture) => texture.2 = texture_handle,
+            None => {
+                let locations = self.locate(name);
+
+                self.textures.push((name.to_string(), locations, texture_handle));
+            },
+        }
+    }
+
+    fn locate(&self, name: &str) -> UniformLocations {
+        let location = |program: &helpers::Program| program.get_uniform_location(name).unwrap_or(-1);
+
+        UniformLocations {
+            program: location(&self.program),
+            instanced: self.instanced.as_ref().map_or(-1, |(program, _)| location(program)),
+        }
+    }
+
+    pub(super) fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub(super) fn supports_instancing(&self) -> bool {
+        self.instanced.is_some()
+    }
+
+    // the program for a batch and where its batch uniforms are
+    pub(super) fn program(&self, instanced: bool) -> (&helpers::Program, BatchUniforms) {
+        match &self.instanced {
+            Some((program, uniforms)) if instanced => (program, *uniforms),
+            _ => (&self.program, self.batch_uniforms),
+        }
+    }
+
+    // sets the uniform values and binds the extra textures on the program `program(instanced)`
+    // returned, which has to be in use
+    pub(super) fn apply(&self, instanced: bool, max_textures: usize) {
+        let (program, _) = self.program(instanced);
+        let location = |locations: &UniformLocations| match &self.instanced {
+            Some(_) if instanced => locations.instanced,
+            _ => locations.program,
+        };
+
+        for (unit, (_, locations, texture_handle)) in extra_texture_units(max_textures, self.textures.len()).zip(&self.textures) {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+                gl::BindTexture(gl::TEXTURE_2D, *texture_handle);
+            }
+
+            program.set_uniform_1i(location(locations), unit as i32);
+        }
+
+        // draw_batch unbinds TEXTURE_2D on the active unit before binding the batch textures
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+
+        for (locations, value) in self.uniforms.values() {
+            program.set_uniform(location(locations), value);
+        }
+    }
+}
+
+fn link_program(name: &str, vertex_source: &str, fragment_source: &str) -> Result<helpers::Program, failure::Error> {
+    let shaders = [
+        helpers::Shader::from_raw(vertex_source, gl::VERTEX_SHADER)?,
+        helpers::Shader::from_raw(fragment_source, gl::FRAGMENT_SHADER)?,
+    ];
+    let program = helpers::Program::from_shaders(&shaders[..], &format!("internal/shaders/material/{}", name))
+        .map_err(|message| helpers::Error::LinkError { name: name.into(), message })?;
+
+    Ok(program)
+}
+
+// the last `count` of the `max_textures` units
+fn extra_texture_units(max_textures: usize, count: usize) -> std::ops::Range<usize> {
+    max_textures.saturating_sub(count)..max_textures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_textures_use_the_last_units() {
+        assert_eq!(extra_texture_units(16, 2), 14..16);
+        assert_eq!(extra_texture_units(16, 0), 16..16);
+    }
+}
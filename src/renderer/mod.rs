@@ -1,16 +1,41 @@
 mod batch_shaders;
+mod instancing;
+mod mask;
 mod render_target;
+pub mod backend;
+pub mod batch_mode;
+pub mod blend_mode;
+pub mod clip;
 pub mod layers;
+pub mod material;
+pub mod post_process;
 pub mod renderable;
+pub mod render_texture;
+pub mod shapes;
+pub mod stats;
+pub mod texture_array;
 
-use std::collections::HashMap;
+use std::mem;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{HashMap, HashSet};
 
 use layers::*;
-use crate::helpers::{self, data, buffer, system};
+use crate::helpers::{self, data, system};
 use crate::camera::*;
 use render_target::{RenderTarget};
-use batch_shaders::{create_fragment_source, create_vertex_source};
-use renderable::{Renderable2D};
+use renderable::{Renderable2D, RenderVertex, QuadInstance, Vertex2D, VertexSink};
+use stats::{RenderStats};
+use blend_mode::{BlendMode};
+use render_texture::{RenderTexture};
+use post_process::{PostProcessChain};
+use batch_mode::{BatchMode};
+use texture_array::{TextureArray, ArrayLayer};
+use clip::{Clip, ClipRect, ClipSpace, NdcRect};
+use mask::{MaskState, COVERAGE_BIT};
+use material::{Material, MaterialId};
+use instancing::{InstanceData};
+use backend::{RenderBackend, BackendKind, BatchDraw, GlBackend};
+use crate::lighting::{Lighting, Light, Occluder};
 
 #[derive(VertexAttribPointers)]
 #[derive(Debug)]
@@ -30,332 +55,1898 @@ pub struct BatchVertex {
     tex_scale: data::f32_f32_f32,
 }
 
+// gl state that has to be the same for every quad in a batch
+#[derive(Clone, Debug, Default, PartialEq)]
+struct BatchState {
+    blend_mode: BlendMode,
+    clip: Option<Clip>,
+    mask: MaskState,
+    material: Option<MaterialId>,
+    instanced: bool,
+}
+
+// what draw_layers draws the batches with, the normal pass draws the normal maps of the
+// batch textures into the lighting's normal buffer. The coverage pass marks the pixels the
+// batches cover with the stencil's coverage bit, so lighting is only multiplied over them
+#[derive(Clone, Copy, Debug)]
+enum LayerPass<'a> {
+    Color,
+    Normals(&'a Lighting),
+    Coverage,
+}
+
+// lit layers are drawn and lit a run of layers with the same camera at a time
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LayerSelection<'a> {
+    All,
+    Only(&'a [String]),
+    Unlit,
+}
+
+// the order the corners of every quad renderable are in
+const QUAD_INDICES: [u32; 6] = [0, 1, 2, 2, 1, 3];
+
+// how many full batches the stream buffers hold, so a batch is written while earlier ones are still drawn
+const STREAM_BATCHES: usize = 3;
+
+// quads a batch holds before a new one is started
+const MAX_SPRITES: usize = 1000;
+
+// a single draw call worth of triangles, a new one is started whenever the current one runs
+// out of room for vertices, indices or texture slots, or the gl state changes.
+// `max_sprites` quads worth of vertices and indices fit in a batch, instanced batches
+// hold `instances` instead, each taking up a quad's worth of room
+#[derive(Debug)]
+struct Batch {
+    state: BatchState,
+    vertices: Vec<BatchVertex>,
+    indices: Vec<u32>,
+    instances: Vec<InstanceData>,
+    texture_slots: Vec<i32>,
+    // textures and the array layer they have to be in when the batch is drawn, in texture array mode
+    array_textures: Vec<(u32, u32)>,
+    sprite_count: usize,
+}
+
+impl Batch {
+    fn new(max_sprites: usize, state: BatchState) -> Batch {
+        Batch {
+            state,
+            vertices: Vec::with_capacity(max_sprites * 4),
+            indices: Vec::with_capacity(max_sprites * 6),
+            instances: Vec::new(),
+            texture_slots: Vec::new(),
+            array_textures: Vec::new(),
+            sprite_count: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.instances.is_empty()
+    }
+
+    fn can_fit(
+        &self,
+        state: &BatchState,
+        texture_handle: i32,
+        (vertex_count, index_count): (usize, usize),
+        max_sprites: usize,
+        max_textures: usize,
+    ) -> bool {
+        let (used_vertices, used_indices) = (
+            self.vertices.len() + self.instances.len() * 4,
+            self.indices.len() + self.instances.len() * 6,
+        );
+
+        if self.state != *state
+            || used_vertices + vertex_count > max_sprites * 4
+            || used_indices + index_count > max_sprites * 6 {
+            return false;
+        }
+
+        self.texture_slots.contains(&texture_handle) || self.texture_slots.len() < max_textures
+    }
+
+    // indices are relative to the first of `vertices`
+    fn push<I: IntoIterator<Item = BatchVertex>>(&mut self, vertices: I, indices: &[u32]) {
+        let offset = self.vertices.len() as u32;
+
+        self.vertices.extend(vertices);
+        self.indices.extend(indices.iter().map(|index| index + offset));
+    }
+
+    // what the vertices of a submission put in their texture index: the array layer in texture array
+    // mode (-1 when untextured, below that for textures bound to a slot after the array's),
+    // otherwise the batch slot the texture is bound to
+    fn texture_index(&mut self, texture: &SubmitTexture) -> f32 {
+        let batch_slot = self.texture_slot(texture.batch_texture_handle);
+
+        match (&texture.array_layer, texture.array_mode) {
+            (Some(layer), _) => {
+                let array_texture = (texture.texture_handle, layer.index);
+
+                if !self.array_textures.contains(&array_texture) {
+                    self.array_textures.push(array_texture);
+                }
+
+                layer.index as f32
+            },
+            (None, true) => -1.0 - batch_slot as f32,
+            (None, false) => batch_slot as f32,
+        }
+    }
+
+    fn texture_slot(&mut self, texture_handle: i32) -> usize {
+        match self.texture_slots.iter().position(|&handle| handle == texture_handle) {
+            Some(slot) => slot,
+            None => {
+                self.texture_slots.push(texture_handle);
+
+                self.texture_slots.len() - 1
+            },
+        }
+    }
+}
+
+// starts a new batch on a layer, only counted as a flush when it closes a batch that was in use.
+// `reserved_texture` is given the first slot so untextured quads never need a slot of their own
+fn start_batch(
+    batches: &mut Vec<Batch>,
+    state: BatchState,
+    max_sprites: usize,
+    reserved_texture: Option<i32>,
+    stats: &mut RenderStats,
+) {
+    if matches!(batches.last(), Some(batch) if !batch.is_empty()) {
+        stats.flushes += 1;
+    }
+
+    let mut batch = Batch::new(max_sprites, state);
+
+    if let Some(texture_handle) = reserved_texture {
+        batch.texture_slot(texture_handle);
+    }
+
+    batches.push(batch);
+}
+
+// where the vertices of a submission sample their texture from
+#[derive(Clone, Copy, Debug)]
+struct SubmitTexture {
+    texture_handle: u32,
+    batch_texture_handle: i32,
+    array_layer: Option<ArrayLayer>,
+    array_mode: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BatchLimits {
+    max_sprites: usize,
+    max_textures: usize,
+    reserved_texture: Option<i32>,
+}
+
+impl BatchLimits {
+    fn fits_in_batch(&self, vertex_count: usize, index_count: usize) -> bool {
+        vertex_count <= self.max_sprites * 4 && index_count <= self.max_sprites * 6
+    }
+}
+
+// the batch of a layer a submission goes in: the last one, or a new one when it doesn't fit there.
+// Only the last batch is added to so batches are drawn in the order they were submitted
+fn batch_for(
+    batches: &mut Vec<Batch>,
+    state: &BatchState,
+    texture: &SubmitTexture,
+    counts: (usize, usize),
+    limits: BatchLimits,
+    stats: &mut RenderStats,
+) -> usize {
+    let has_room = match batches.last() {
+        Some(batch) => batch.can_fit(state, texture.batch_texture_handle, counts, limits.max_sprites, limits.max_textures),
+        None => false,
+    };
+
+    if !has_room {
+        start_batch(batches, state.clone(), limits.max_sprites, limits.reserved_texture, stats);
+    }
+
+    batches.len() - 1
+}
+
+// adds vertices and the triangles between them to a batch of a layer, returns which one
+fn push_to_batches(
+    batches: &mut Vec<Batch>,
+    state: &BatchState,
+    texture: SubmitTexture,
+    limits: BatchLimits,
+    vertices: &[Vertex2D],
+    indices: &[u32],
+    stats: &mut RenderStats,
+) -> usize {
+    let index = batch_for(batches, state, &texture, (vertices.len(), indices.len()), limits, stats);
+    let batch = &mut batches[index];
+    let tex_id = batch.texture_index(&texture);
+    let batch_vertices = vertices.iter()
+        .map(|vertex| {
+            let (tex_translate, tex_scale) = match &texture.array_layer {
+                Some(layer) => array_uv_transform(layer, vertex.texture_translate(), vertex.texture_scale()),
+                None => (vertex.texture_translate(), vertex.texture_scale()),
+            };
+
+            BatchVertex {
+                pos: vertex.position(),
+                tex: vertex.uv(),
+                color: vertex.color(),
+                tex_id: tex_id.into(),
+                tex_translate,
+                tex_scale,
+            }
+        });
+
+    batch.push(batch_vertices, indices);
+
+    index
+}
+
+// adds a quad to an instanced batch of a layer
+fn push_instance_to_batches(
+    batches: &mut Vec<Batch>,
+    state: &BatchState,
+    texture: SubmitTexture,
+    limits: BatchLimits,
+    instance: &QuadInstance,
+    stats: &mut RenderStats,
+) {
+    let index = batch_for(batches, state, &texture, (4, 6), limits, stats);
+    let batch = &mut batches[index];
+    let tex_id = batch.texture_index(&texture);
+    let uv_scale = texture.array_layer.map(|layer| layer.uv_scale);
+
+    batch.instances.push(InstanceData::new(instance, tex_id, uv_scale));
+    batch.sprite_count += 1;
+}
+
 pub struct Renderer2D {
-    program: helpers::Program,
-    vertices: Vec<Vec<BatchVertex>>,
-    indices: Vec<[i32; 6]>,
-    _layers: Layers,
-    vbo: buffer::DynamicArrayBuffer,
-    vao: buffer::VertexArray,
-    ibo: buffer::ElementArrayBuffer,
+    // `clear` only takes `&self`
+    backend: RefCell<Box<dyn RenderBackend>>,
+    instancing: bool,
+    batches: HashMap<String, Vec<Batch>>,
+    layers: Layers,
+    layer_cameras: HashMap<String, Camera>,
+    layer_blend_modes: HashMap<String, BlendMode>,
+    hidden_layers: HashSet<String>,
+    default_layer: String,
     clear_color: (f32, f32, f32, f32),
-    max_textures: gl::types::GLint,
     max_sprites: usize,
-    texture_slots: Vec<i32>,
-    uniforms: HashMap<String, i32>,
-    sprite_count: usize,
     render_target: Option<RenderTarget>,
+    post_process: RefCell<Option<PostProcessChain>>,
+    batch_mode: BatchMode,
+    texture_array: Option<TextureArray>,
+    white_texture: u32,
+    clip_stack: Vec<Clip>,
+    mask: MaskState,
+    mask_reference: u8,
+    materials: HashMap<MaterialId, Material>,
+    next_material_id: usize,
+    scene_viewport: [i32; 4],
+    scene_target_size: (u32, u32),
+    scene_active: Cell<bool>,
+    virtual_resolution: Option<(u32, u32)>,
+    viewport: Option<helpers::Viewport>,
+    lighting: Option<Lighting>,
+    lights: Vec<Light>,
+    occluders: Vec<Occluder>,
+    unlit_layers: HashSet<String>,
+    sink: VertexSink,
+    stats: RenderStats,
 }
 
 impl Renderer2D {
     pub fn new() -> Result<Renderer2D, failure::Error> {
+        Renderer2D::with_batch_mode(BatchMode::default())
+    }
+
+    pub fn with_batch_mode(batch_mode: BatchMode) -> Result<Renderer2D, failure::Error> {
+        let texture_array = match batch_mode {
+            BatchMode::TextureUnits => None,
+            BatchMode::TextureArray { layer_width, layer_height, max_layers } => {
+                let max_layers = max_layers.min(system::SystemInfo::get_max_array_texture_layers() as u32);
+
+                Some(TextureArray::new(layer_width, layer_height, max_layers))
+            },
+        };
+        let backend = GlBackend::new(batch_mode, MAX_SPRITES)?;
+
+        Ok(Renderer2D::from_backend(Box::new(backend), batch_mode, texture_array))
+    }
+
+    /// Draws with `backend` instead of OpenGL, ie a `SoftwareBackend` to render scenes in tests
+    /// without a gl context. Batches always bind their textures to `BatchMode::TextureUnits`
+    pub fn with_backend<B: RenderBackend + 'static>(backend: B) -> Renderer2D {
+        Renderer2D::from_backend(Box::new(backend), BatchMode::TextureUnits, None)
+    }
+
+    fn from_backend(
+        mut backend: Box<dyn RenderBackend>,
+        batch_mode: BatchMode,
+        texture_array: Option<TextureArray>,
+    ) -> Renderer2D {
         let default_clear_color = (1.0, 1.0, 1.0, 1.0);
-        let max_buffer_size = ((::std::mem::size_of::<BatchVertex>()) * 4000) as gl::types::GLsizeiptr;
-        let max_sprites = 1000;
-        let _max_index_size = ((::std::mem::size_of::<[u32; 6]>()) * 4000) as gl::types::GLsizeiptr;
-        let max_textures = system::SystemInfo::get_max_textures();
-        let vert_src = create_vertex_source();
-        let frag_src = create_fragment_source(max_textures);
-        let shaders = vec![
-            helpers::Shader::from_raw(&vert_src, gl::VERTEX_SHADER)?,
-            helpers::Shader::from_raw(&frag_src, gl::FRAGMENT_SHADER)?,
-        ];
-        let program = helpers::Program::from_shaders(&shaders[..], "internal/shaders/batch")
-            .expect("Failed to load Batch Renderer Shader Program");
-        let uniform_textures = program.get_uniform_location("Textures")?;
-        let uniform_mvp = program.get_uniform_location("MVP")?;
-        let texture_slots = Vec::with_capacity(max_textures as usize);
-
-        let vbo = buffer::DynamicArrayBuffer::new(max_buffer_size);
-        let vao = buffer::VertexArray::new();
-        let ibo = buffer::ElementArrayBuffer::new();
-
-        let indices = generate_batch_indices(max_sprites);
-
-        vbo.bind();
-        vbo.set_buffer_data();
-        //vbo.unbind();
-
-        vao.bind();
-        //vbo.bind();
-
-        BatchVertex::vertex_attrib_pointers();
-
-        ibo.bind();
-        ibo.static_draw_data(&indices);
-        ibo.unbind();
-
-        vbo.unbind();
-        vao.unbind();
-
-        Ok(Renderer2D {
-            program,
-            vertices: Vec::new(),
-            indices,
-            _layers: Layers::new(),
-            vbo,
-            vao,
-            ibo,
+        let default_layer = Layer::new("default", LayerKind::Generic, 0);
+        let default_layer_id = default_layer.get_id();
+        let mut layers = Layers::new();
+
+        layers.add(default_layer);
+
+        Renderer2D {
+            white_texture: backend.create_texture(1, 1, &[255, 255, 255, 255]),
+            backend: RefCell::new(backend),
+            instancing: false,
+            batches: vec![(default_layer_id.clone(), Vec::new())].into_iter().collect(),
+            layers,
+            layer_cameras: HashMap::new(),
+            layer_blend_modes: HashMap::new(),
+            hidden_layers: HashSet::new(),
+            default_layer: default_layer_id,
             clear_color: default_clear_color,
-            max_textures,
-            max_sprites,
-            sprite_count: 0,
-            texture_slots,
-            uniforms: vec![
-                ("textures".to_owned(), uniform_textures),
-                ("mvp".to_owned(), uniform_mvp),
-            ].into_iter().collect(),
+            max_sprites: MAX_SPRITES,
             render_target: None,
-        })
+            post_process: RefCell::new(None),
+            batch_mode,
+            texture_array,
+            clip_stack: Vec::new(),
+            mask: MaskState::Off,
+            mask_reference: 0,
+            materials: HashMap::new(),
+            next_material_id: 0,
+            scene_viewport: [0, 0, 1, 1],
+            scene_target_size: (1, 1),
+            scene_active: Cell::new(false),
+            virtual_resolution: None,
+            viewport: None,
+            lighting: None,
+            lights: Vec::new(),
+            occluders: Vec::new(),
+            unlit_layers: HashSet::new(),
+            sink: VertexSink::new(),
+            stats: RenderStats::default(),
+        }
+    }
+
+    // scene targets, post processing, lighting and materials draw with gl directly
+    fn require_opengl(&mut self, feature: &'static str) -> Result<(), backend::Error> {
+        match self.backend.get_mut().kind() {
+            BackendKind::OpenGl => Ok(()),
+            backend => Err(backend::Error::OpenGlOnly { feature, backend }),
+        }
+    }
+
+    pub fn get_backend(&self) -> Ref<'_, dyn RenderBackend> {
+        Ref::map(self.backend.borrow(), |backend| backend.as_ref())
+    }
+
+    /// Textures for `Renderable2D`s drawn by a backend other than OpenGL are created through this
+    pub fn get_backend_mut(&mut self) -> &mut dyn RenderBackend {
+        self.backend.get_mut().as_mut()
+    }
+
+    /// The backend as the type it was made with, `None` for any other type. For what only that
+    /// backend has, ie the device and target texture of a `WgpuBackend` to present a frame with
+    pub fn get_backend_as<B: RenderBackend + 'static>(&self) -> Option<Ref<'_, B>> {
+        Ref::filter_map(self.backend.borrow(), |backend| backend.as_any().downcast_ref::<B>()).ok()
+    }
+
+    pub fn get_backend_as_mut<B: RenderBackend + 'static>(&mut self) -> Option<&mut B> {
+        self.backend.get_mut().as_any_mut().downcast_mut::<B>()
+    }
+
+    /// Renders scenes into a `width` x `height` target that `end_scene` scales up to the current
+    /// `glViewport` without filtering. `use_viewport` sets this and the viewport together.
+    /// `None` renders at the size of the viewport again
+    pub fn set_virtual_resolution(&mut self, resolution: Option<(u32, u32)>) {
+        self.virtual_resolution = resolution;
+    }
+
+    /// Draws into the letterboxed rect of `viewport` at its virtual resolution, if it has one.
+    /// The renderer keeps its own copy, `resize` moves the letterbox with the window from then on
+    pub fn use_viewport(&mut self, viewport: &helpers::Viewport) {
+        self.virtual_resolution = viewport.get_virtual_resolution();
+        self.backend.get_mut().set_viewport(viewport.scaled_rect());
+        self.viewport = Some(viewport.clone());
+    }
+
+    /// Call on window resize events, recomputes the letterbox of the viewport given to `use_viewport`
+    /// for the new window size and draws into it. Without a viewport the whole window is drawn to
+    pub fn resize(&mut self, width: f32, height: f32) {
+        let viewport = self.viewport.get_or_insert_with(|| helpers::Viewport::for_window(width, height));
+
+        viewport.update_size(width, height);
+        self.backend.get_mut().set_viewport(viewport.scaled_rect());
+    }
+
+    /// The viewport as of the last `resize`, ie to convert mouse positions with `Viewport::to_logical`
+    pub fn get_viewport(&self) -> Option<&helpers::Viewport> {
+        self.viewport.as_ref()
+    }
+
+    pub fn get_virtual_resolution(&self) -> Option<(u32, u32)> {
+        self.virtual_resolution
     }
 
-    pub fn begin_scene(&mut self, camera: &Camera) {
-        let (width, height) = camera.get_dimensions();
-        //self.render_target = Some(
-        //    RenderTarget::new(width as u32, height as u32).expect("Could not create RenderTarget")
-        //);
+    /// Scenes are sized from the viewport and every `render` draws through its own camera,
+    /// so `camera` isn't used
+    #[deprecated(note = "the camera isn't used, call `begin_scene_target` instead")]
+    pub fn begin_scene(&mut self, _camera: &Camera) -> Result<(), failure::Error> {
+        self.begin_scene_target()
+    }
+
+    /// Starts drawing into an offscreen target the size of the current `glViewport` (or the virtual
+    /// resolution), every `render` until `end_scene` draws into it and `end_scene` puts it on the
+    /// screen. Each `render` draws through its own camera. Fails on backends other than `OpenGl`
+    pub fn begin_scene_target(&mut self) -> Result<(), failure::Error> {
+        self.require_opengl("begin_scene_target")?;
+        self.scene_active.set(true);
+        self.scene_viewport = self.backend.get_mut().viewport();
+
+        let [_, _, viewport_width, viewport_height] = self.scene_viewport;
+        let (target_width, target_height) = match self.virtual_resolution {
+            Some((width, height)) => (width.max(1), height.max(1)),
+            None => (viewport_width.max(1) as u32, viewport_height.max(1) as u32),
+        };
+
+        self.scene_target_size = (target_width, target_height);
 
         match &mut self.render_target {
-            Some(_) => {},
-            None => {
-                self.render_target = Some(
-                    RenderTarget::new(width as u32, height as u32).expect("Could not create RenderTarget")
-                );
-            },
+            Some(render_target) => render_target.update_fbo_size(target_width, target_height),
+            None => self.render_target = Some(RenderTarget::new(target_width, target_height)?),
+        }
+
+        if let Some(render_target) = &self.render_target {
+            let backend = self.backend.get_mut();
+            let filter = match self.virtual_resolution {
+                Some(_) => gl::NEAREST,
+                None => gl::LINEAR,
+            };
+
+            render_target.frame_buffer.texture.set_filter(filter);
+            render_target.bind();
+
+            // clear the fbo screen, set the default screen color back to what it was
+            backend.set_viewport([0, 0, target_width as i32, target_height as i32]);
+            backend.set_clear_color((0.0, 0.0, 0.0, 0.0));
+            backend.clear(None);
+            backend.clear_stencil();
+            backend.set_clear_color(self.clear_color);
+
+            render_target.unbind();
+            backend.set_viewport(self.scene_viewport);
         }
+
+        Ok(())
     }
 
-    pub fn set_ppe_program(&mut self, program: &helpers::Program) {
-        match &mut self.render_target {
-            Some(_) => {},
-            None => {
-                self.render_target = Some(
-                    RenderTarget::new(10, 10).expect("Could not create RenderTarget")
-                );
-            },
+    /// Fails on backends other than `OpenGl`, the program is a gl program
+    pub fn set_ppe_program(&mut self, program: &helpers::Program) -> Result<(), failure::Error> {
+        self.require_opengl("set_ppe_program")?;
+
+        if self.render_target.is_none() {
+            self.render_target = Some(RenderTarget::new(10, 10)?);
         }
 
         if let Some(render_target) = &mut self.render_target {
             render_target.set_program(program.clone()); // not sure about cloning programs
         }
+
+        Ok(())
     }
 
-    pub fn end_scene(&self) {
+    /// Runs the passes of `chain` over the scene instead of the single `set_ppe_program` program.
+    /// Like `set_ppe_program` this needs `begin_scene_target` to have been called and fails on
+    /// backends other than `OpenGl`
+    pub fn set_post_process_chain(&mut self, chain: PostProcessChain) -> Result<(), failure::Error> {
+        self.require_opengl("set_post_process_chain")?;
+        *self.post_process.get_mut() = Some(chain);
 
+        Ok(())
     }
 
-    pub fn begin_batch(&mut self) {
-        self.vbo.bind();
-        self.vbo.reset_buffer_offset();
+    /// Passes can be added, removed, enabled and disabled through this at any point
+    pub fn get_post_process_chain_mut(&mut self) -> Option<&mut PostProcessChain> {
+        self.post_process.get_mut().as_mut()
     }
 
-    pub fn end_batch(&mut self) {
-        self.sprite_count = 0;
-        self.vbo.reset_buffer_offset();
-        self.vbo.unbind();
+    pub fn take_post_process_chain(&mut self) -> Option<PostProcessChain> {
+        self.post_process.get_mut().take()
     }
 
-    pub fn submit(&mut self, sprite: &dyn Renderable2D) {
-        if self.sprite_count >= self.max_sprites {
-            // need to reset/end/flush/render/begin new batch and reset sprite_count
+    /// Draws the scene rendered since `begin_scene_target` to the screen, through the post processing
+    /// passes if there are any. It's drawn into the viewport `begin_scene_target` was called with, which
+    /// every `render` puts back when it's done. When the post processing passes can't be run
+    /// the scene is drawn without them and the error is returned
+    pub fn end_scene(&self) -> Result<(), failure::Error> {
+        if !self.scene_active.replace(false) {
+            return Ok(());
         }
 
-        let sprite_texture_handle = sprite.texture() as i32;
-        let _tex_id: i32 = match self.texture_slots.binary_search(&sprite_texture_handle) {
-            Ok(tid) => tid as i32,
-            Err(_next_id) if self.texture_slots.len() >= self.max_textures as usize => {
-                // we dont have this texture, but we have no space
-                // we need to flush/end/render, and start again
+        let (width, height) = self.scene_target_size;
 
-                -1
-            },
-            Err(next_id) => {
-                // we don't have this, but we have space to add it
-                self.texture_slots.push(sprite_texture_handle);
+        if let Some(render_target) = &self.render_target {
+            match &mut *self.post_process.borrow_mut() {
+                Some(chain) if chain.has_enabled_passes() => {
+                    if let Err(e) = chain.render(render_target, width, height) {
+                        render_target.render();
 
-                next_id as i32
-            },
-        };
+                        return Err(e);
+                    }
+                },
+                _ => render_target.render(),
+            }
+        }
 
+        Ok(())
+    }
 
-        let sprite_vertices = sprite.vertices();
-        let sprite_texture_handle = sprite.texture() as i32;
-        let sprite_tex_id = self.texture_slots.iter().position(|&id| id == sprite_texture_handle).unwrap_or(0); // should use a single reserved slot for blank white texture or a debug texture
-
-        let mut batch_vertices: Vec<BatchVertex> = Vec::new();
-        for vertex in sprite_vertices {
-            batch_vertices.push(
-                BatchVertex {
-                    pos: vertex.position(),
-                    tex: vertex.uv(),
-                    color: vertex.color(),
-                    tex_id: (sprite_tex_id as u32).into(),
-                    tex_translate: vertex.texture_translate(),
-                    tex_scale: vertex.texture_scale(),
-                }
-            );
-        };
+    /// Lights lit layers with the lights submitted since `begin_batch`, starting from the lighting's
+    /// ambient colour. Lighting is only applied to scenes, so `render` has to be between
+    /// `begin_scene_target` and `end_scene`. Lit layers with their own camera are lit through it.
+    /// With `BatchMode::TextureArray` the normal maps aren't used and everything is lit as if it was flat.
+    /// Fails on backends other than `OpenGl`
+    pub fn set_lighting(&mut self, lighting: Lighting) -> Result<(), failure::Error> {
+        self.require_opengl("set_lighting")?;
+        self.lighting = Some(lighting);
+
+        Ok(())
+    }
 
-        self.vbo.upload_draw_data(&batch_vertices);
-        self.vbo.set_buffer_offset(self.vbo.buffer_offset + ((::std::mem::size_of::<BatchVertex>()) * 4) as isize);
+    /// Ambient colour and normal maps can be changed through this at any point
+    pub fn get_lighting_mut(&mut self) -> Option<&mut Lighting> {
+        self.lighting.as_mut()
+    }
 
-        self.vertices.push(batch_vertices);
-        self.sprite_count += 1;
+    pub fn take_lighting(&mut self) -> Option<Lighting> {
+        self.lighting.take()
+    }
 
-        // TODO: experimental
-        //self.indices = generate_batch_indices(self.sprite_count);
-        //self.ibo.bind();
-        //self.ibo.static_draw_data(&self.indices);
-        //self.ibo.unbind();
+    /// Adds a light for this frame, lights are cleared on `begin_batch` like sprites
+    pub fn submit_light<L: Into<Light>>(&mut self, light: L) {
+        self.lights.push(light.into());
     }
 
-    pub fn set_clear_color(&mut self, r: u8, g: u8, b: u8, a: f32) {
-        let rf = r as f32 / 255.0;
-        let gf = g as f32 / 255.0;
-        let bf = b as f32 / 255.0;
+    /// Shadows are cast from occluders submitted since `begin_batch` by every light that casts shadows
+    pub fn submit_occluder(&mut self, occluder: Occluder) {
+        self.occluders.push(occluder);
+    }
 
-        self.clear_color = (rf, gf, bf, a);
+    pub fn submit_occluders<I: IntoIterator<Item = Occluder>>(&mut self, occluders: I) {
+        self.occluders.extend(occluders);
+    }
 
-        unsafe {
-            gl::ClearColor(rf, gf, bf, a);
+    /// Layers are lit by default, unlit layers (ie UI) are drawn over the lit ones after lighting,
+    /// whatever their order
+    pub fn set_layer_lit(&mut self, layer_id: &str, lit: bool) {
+        if lit {
+            self.unlit_layers.remove(layer_id);
+        } else {
+            self.unlit_layers.insert(layer_id.to_string());
         }
     }
 
-    pub fn clear(&self) {
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+    pub fn is_layer_lit(&self, layer_id: &str) -> bool {
+        !self.unlit_layers.contains(layer_id)
+    }
+
+    /// Submissions that have a `Renderable2D::quad_instance` (ie `Sprite`) are drawn instanced while
+    /// this is on: one unit quad and a small block of per quad data each instead of building and
+    /// uploading 4 vertices. Instanced and non instanced submissions go in separate batches
+    pub fn set_instancing(&mut self, instancing: bool) {
+        self.instancing = instancing;
+    }
+
+    pub fn is_instancing(&self) -> bool {
+        self.instancing
+    }
+
+    pub fn get_batch_mode(&self) -> BatchMode {
+        self.batch_mode
+    }
+
+    /// With `BatchMode::TextureArray` textures are copied into the array once, this copies `texture_handle`
+    /// again on its next use. Needed when a texture's pixels change or its handle is reused,
+    /// `render_to_texture` does it for its target
+    pub fn invalidate_texture(&mut self, texture_handle: u32) {
+        if let Some(texture_array) = &mut self.texture_array {
+            texture_array.invalidate(texture_handle);
         }
     }
 
-    pub fn render(&mut self, camera: &Camera) {
-        let (cam_width, cam_height) = camera.get_dimensions();
-        let mvp = camera.get_projection() * camera.get_view();
+    pub fn begin_batch(&mut self) {
+        self.stats.reset();
+        self.clip_stack.clear();
+        self.mask = MaskState::Off;
+        self.mask_reference = 0;
+        self.lights.clear();
+        self.occluders.clear();
 
-        if let Some(render_target) = &mut self.render_target {
-            render_target.update_fbo_size(cam_width as u32, cam_height as u32);
-            render_target.bind();
-            let (r, g, b, a) = self.clear_color;
+        if let Some(texture_array) = &mut self.texture_array {
+            texture_array.next_pass();
+        }
 
-            unsafe {
-                // clear the fbo screen, set the default screen color back to what it was
-                gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-                gl::Clear(gl::COLOR_BUFFER_BIT);
-                gl::ClearColor(r, g, b, a);
-            }
+        for batches in self.batches.values_mut() {
+            batches.clear();
         }
+    }
+
+    pub fn end_batch(&mut self) {
+        // batches are only drawn by `render`, nothing is left bound between the two
+    }
+
+    /// Closes the current batch of every layer so the next submission starts a new one.
+    /// Batches are drawn in the order they were submitted, one draw call each, when `render` is called
+    pub fn flush(&mut self) {
+        let max_sprites = self.max_sprites;
+        let reserved_texture = self.reserved_texture();
 
-        self.vbo.bind();
-        self.ibo.bind();
-        self.vao.bind();
+        for batches in self.batches.values_mut() {
+            let state = match batches.last() {
+                Some(batch) if !batch.is_empty() => batch.state.clone(),
+                _ => continue,
+            };
 
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, 0);
+            start_batch(batches, state, max_sprites, reserved_texture, &mut self.stats);
         }
+    }
 
-        for (i, handle) in self.texture_slots.iter().enumerate() {
-            unsafe {
-                gl::ActiveTexture(gl::TEXTURE0 + i as u32);
-                gl::BindTexture(gl::TEXTURE_2D, *handle as u32);
-            }
+    // the white texture untextured quads (texture 0) are drawn with in texture unit mode. In texture
+    // array mode the array takes the first slot, untextured quads are marked with layer -1 instead
+    fn reserved_texture(&self) -> Option<i32> {
+        match &self.texture_array {
+            Some(texture_array) => Some(texture_array.get_texture_handle() as i32),
+            None => Some(self.white_texture as i32),
         }
+    }
 
-        self.program.set_used();
-        self.program.set_uniform_mat4f(*self.uniforms.get("mvp").unwrap(), &mvp);
-        self.program.set_uniform_1iv(
-            *self.uniforms.get("textures").unwrap(),
-            &generate_texture_slots(self.max_textures)
-        );
+    // the texture array layer for a submission's texture. When every layer is taken by this pass the
+    // batches so far are closed, so the layers can change hands for the batches after them. `None`
+    // when there is still no layer, the texture is then bound to a slot of its own
+    fn array_layer_for(&mut self, texture_handle: u32) -> Option<ArrayLayer> {
+        if let Ok(layer) = self.texture_array.as_mut()?.layer_for(texture_handle) {
+            return Some(layer);
+        }
+
+        self.flush();
+
+        let texture_array = self.texture_array.as_mut()?;
+
+        texture_array.next_pass();
+        texture_array.layer_for(texture_handle).ok()
+    }
+
+    /// Clips everything submitted after this, on every layer, to `rect` until the matching
+    /// `pop_clip_rect`. Nested clips only draw where they overlap. Clips are measured with the camera
+    /// each layer is drawn with, so every camera passed to `render` clips its own view.
+    /// The stack is emptied on `begin_batch`
+    pub fn push_clip_rect(&mut self, rect: ClipRect, space: ClipSpace) {
+        let clip = self.clip_stack.last().cloned().unwrap_or_default().push(rect, space);
+
+        self.clip_stack.push(clip);
+    }
+
+    pub fn pop_clip_rect(&mut self) {
+        self.clip_stack.pop();
+    }
+
+    /// Everything submitted after this, until `end_mask`, is not drawn but marks the pixels it
+    /// covers in the stencil buffer instead. Only pixels with at least half alpha are marked,
+    /// so textured sprites mask by their shape. A mask and what it masks have to be on the same layer.
+    /// Scene targets have a stencil buffer, outside `begin_scene_target` the window needs one too
+    /// (`gl_attr.set_stencil_size(8)` with sdl2) or masks do nothing
+    pub fn begin_mask(&mut self) {
+        self.mask_reference = mask::next_reference(self.mask_reference);
+        self.mask = MaskState::Write(self.mask_reference);
+    }
+
+    /// Submissions after this are only drawn inside the last mask, until `clear_mask`
+    pub fn end_mask(&mut self) {
+        self.mask = MaskState::Inside(self.mask_reference);
+    }
 
-        self.vao.bind();
+    /// Like `end_mask` but only draws outside of the last mask
+    pub fn end_mask_inverted(&mut self) {
+        self.mask = MaskState::Outside(self.mask_reference);
+    }
+
+    /// Stops masking, masks are also cleared on `begin_batch`. Each mask gets its own stencil
+    /// value, after 127 masks in a frame the values are reused and older masks can show through
+    pub fn clear_mask(&mut self) {
+        self.mask = MaskState::Off;
+    }
+
+    /// Keeps `material` so it can be submitted with, the renderer drops it on `remove_material`.
+    /// Fails on backends other than `OpenGl`, materials are gl programs
+    pub fn add_material(&mut self, material: Material) -> Result<MaterialId, failure::Error> {
+        self.require_opengl("add_material")?;
+
+        let material_id = MaterialId(self.next_material_id);
+
+        self.next_material_id += 1;
+        self.materials.insert(material_id, material);
+
+        Ok(material_id)
+    }
+
+    /// Submissions with a removed material still in the batch are drawn with the default program
+    pub fn remove_material(&mut self, material_id: MaterialId) -> Option<Material> {
+        self.materials.remove(&material_id)
+    }
+
+    pub fn get_material(&self, material_id: MaterialId) -> Option<&Material> {
+        self.materials.get(&material_id)
+    }
+
+    /// Uniforms changed through this are used for every batch with the material drawn after it
+    pub fn get_material_mut(&mut self, material_id: MaterialId) -> Option<&mut Material> {
+        self.materials.get_mut(&material_id)
+    }
+
+    /// Statistics for the current frame, reset on `begin_batch`
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
 
-        unsafe {
-            gl::DrawElements(
-                gl::TRIANGLES,
-                self.indices.len() as i32 * 6,
-                gl::UNSIGNED_INT,
-                self.indices.as_ptr() as *const gl::types::GLvoid
-            );
+    /// Adds a layer to draw submissions into, returns the id used to submit to it
+    pub fn add_layer(&mut self, layer: Layer) -> String {
+        let layer_id = layer.get_id();
+
+        self.layers.add(layer);
+        self.batches.insert(layer_id.clone(), Vec::new());
+
+        layer_id
+    }
+
+    /// Drops a layer and what was submitted to it, returns false when there is no such layer.
+    /// The default layer is never removed
+    pub fn remove_layer(&mut self, layer_id: &str) -> bool {
+        if layer_id == self.default_layer || self.layers.get(layer_id).is_none() {
+            return false;
         }
 
-        self.vbo.unbind();
-        self.vao.unbind();
-        self.ibo.unbind();
+        self.layers.remove(layer_id);
+        self.batches.remove(layer_id);
+        self.layer_cameras.remove(layer_id);
+        self.layer_blend_modes.remove(layer_id);
+        self.unlit_layers.remove(layer_id);
+        self.hidden_layers.remove(layer_id);
 
-        if let Some(render_target) = &mut self.render_target {
-            render_target.unbind();
-            //unsafe {
-            //    gl::Clear(gl::COLOR_BUFFER_BIT);
-            //}
-            render_target.render();
+        true
+    }
+
+    /// Id of the Generic layer that `submit` draws into
+    pub fn default_layer(&self) -> String {
+        self.default_layer.clone()
+    }
+
+    /// Draws a layer with its own camera instead of the one passed to `render`,
+    /// ie a UI layer that shouldn't move with the world camera
+    pub fn set_layer_camera(&mut self, layer_id: &str, camera: &Camera) {
+        self.layer_cameras.insert(layer_id.to_string(), camera.clone());
+    }
+
+    pub fn clear_layer_camera(&mut self, layer_id: &str) {
+        self.layer_cameras.remove(layer_id);
+    }
+
+    /// Hidden layers keep their submissions but `render` and `render_to_texture` skip them,
+    /// ie a minimap camera that shouldn't draw the UI a second time
+    pub fn set_layer_visible(&mut self, layer_id: &str, visible: bool) {
+        if visible {
+            self.hidden_layers.remove(layer_id);
+        } else {
+            self.hidden_layers.insert(layer_id.to_string());
         }
+    }
 
-        self.texture_slots = Vec::new();
+    pub fn is_layer_visible(&self, layer_id: &str) -> bool {
+        !self.hidden_layers.contains(layer_id)
     }
-}
 
-fn generate_batch_indices(vertices_len: usize) -> Vec<[i32; 6]> {
-    let mut offset: i32 = 0;
-    let mut indices: Vec<[i32; 6]> = Vec::new();
+    /// Blend mode used by submissions to a layer that don't set their own, defaults to `BlendMode::Alpha`
+    pub fn set_layer_blend_mode(&mut self, layer_id: &str, blend_mode: BlendMode) {
+        self.layer_blend_modes.insert(layer_id.to_string(), blend_mode);
+    }
 
-    // TODO: maybe take in a format or base it off given vertices?
-    // as this needs to match the order of a sprites vertices
-    // this order is more of a top left to bottom right
-    for _i in 0..vertices_len {
-        let group: [i32; 6] = [
-            offset,
-            offset + 1,
-            offset + 2,
-            offset + 2,
-            offset + 1,
-            offset + 3,
-        ];
+    pub fn get_layer_blend_mode(&self, layer_id: &str) -> BlendMode {
+        self.layer_blend_modes.get(layer_id).copied().unwrap_or_default()
+    }
 
-        indices.push(group);
+    pub fn submit(&mut self, sprite: &dyn Renderable2D) {
+        let layer_id = self.default_layer.clone();
 
-        offset += 4;
+        self.submit_to_layer(&layer_id, sprite);
     }
 
-    indices
-}
+    pub fn submit_with_blend(&mut self, sprite: &dyn Renderable2D, blend_mode: BlendMode) {
+        let layer_id = self.default_layer.clone();
 
-fn generate_texture_slots(max: i32) -> Vec<i32> {
-    let mut texture_slots = Vec::new();
+        self.submit_to_layer_with_blend(&layer_id, sprite, blend_mode);
+    }
 
-    for i in 0..max {
-        texture_slots.push(i as i32);
+    /// Submissions to a layer that doesn't exist (ie one that was removed) aren't drawn, and neither are
+    /// renderables without indices whose vertices aren't whole quads. Returns false for them
+    pub fn submit_to_layer(&mut self, layer_id: &str, sprite: &dyn Renderable2D) -> bool {
+        let blend_mode = self.get_layer_blend_mode(layer_id);
+
+        self.submit_to_layer_with_blend(layer_id, sprite, blend_mode)
     }
 
-    texture_slots
-}
+    pub fn submit_to_layer_with_blend(&mut self, layer_id: &str, sprite: &dyn Renderable2D, blend_mode: BlendMode) -> bool {
+        self.submit_with_state(layer_id, sprite, blend_mode, None)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Draws `sprite` with a material added through `add_material` instead of the default batch program,
+    /// `None` is the same as `submit`. Every change of material starts a new batch, so submissions that
+    /// alternate between materials are drawn in order but cost a draw call each. Submitting everything
+    /// with one material before the next keeps them in as few batches as possible
+    pub fn submit_with_material(&mut self, sprite: &dyn Renderable2D, material: Option<MaterialId>) {
+        let layer_id = self.default_layer.clone();
 
-    #[test]
-    fn can_generate_indices() {
-        let expected_len = 4;
-        let test_vec = vec![0; expected_len];
-        let expected_indices = vec![
-            [0, 1, 2, 2, 1, 3],
-            [4, 5, 6, 6, 5, 7],
-            [8, 9, 10, 10, 9, 11],
-            [12, 13, 14, 14, 13, 15]
-        ];
+        self.submit_to_layer_with_material(&layer_id, sprite, material);
+    }
 
-        let indices = generate_batch_indices(test_vec.len());
+    pub fn submit_to_layer_with_material(&mut self, layer_id: &str, sprite: &dyn Renderable2D, material: Option<MaterialId>) -> bool {
+        let blend_mode = self.get_layer_blend_mode(layer_id);
 
-        assert_eq!(expected_len, indices.len());
-        assert_eq!(expected_indices, indices);
+        self.submit_with_state(layer_id, sprite, blend_mode, material)
     }
 
-    #[test]
-    fn can_generate_texture_slots() {
-        let max = 4;
-        let actual_texture_slots = generate_texture_slots(max);
-        let expected_texture_slots = vec![0, 1, 2, 3];
+    fn submit_with_state(
+        &mut self,
+        layer_id: &str,
+        sprite: &dyn Renderable2D,
+        blend_mode: BlendMode,
+        material: Option<MaterialId>,
+    ) -> bool {
+        // checked first so a bad layer can't take texture array layers or flush the batches
+        if !self.batches.contains_key(layer_id) {
+            return false;
+        }
 
-        assert_eq!(max as usize, actual_texture_slots.len());
-        assert_eq!(expected_texture_slots, actual_texture_slots);
+        let sprite_texture_handle = sprite.texture();
+        let material_instancing = material.and_then(|material_id| self.materials.get(&material_id))
+            .is_none_or(|material| material.supports_instancing());
+        let instance = if self.instancing && self.backend.get_mut().supports_instancing() && material_instancing {
+            sprite.quad_instance()
+        } else {
+            None
+        };
+        // the sink is kept between submissions so writing into it stops allocating once it has grown
+        let mut sink = mem::take(&mut self.sink);
+
+        sink.clear();
+
+        if instance.is_none() {
+            sprite.write_vertices(&mut sink);
+
+            // without indices the vertices have to be whole quads,
+            // with them whole triangles that only point at written vertices
+            let malformed = match sink.indices() {
+                None => !sink.vertices().chunks_exact(4).remainder().is_empty(),
+                Some(indices) => !indices.chunks_exact(3).remainder().is_empty()
+                    || indices.iter().any(|&index| index as usize >= sink.vertices().len()),
+            };
+
+            if malformed {
+                self.sink = sink;
+
+                return false;
+            }
+        }
+
+        // a layer only holds the texture, so uvs outside of it (texture scale, tiling) have to be
+        // sampled from the texture itself to wrap or clamp like they do in texture unit mode
+        let samples_outside = match &instance {
+            Some(instance) => {
+                let (u, v, u2, v2) = instance.uv_rect;
+
+                [(u, v), (u2, v2)].iter().any(|&uv| outside_texture(uv))
+            },
+            None => sink.vertices().iter().any(|vertex| {
+                let (translate, scale) = (vertex.texture_translate, vertex.texture_scale);
+
+                outside_texture((vertex.uv.0 * scale.0 + translate.0, vertex.uv.1 * scale.1 + translate.1))
+            }),
+        };
+        let array_layer = match (&self.texture_array, sprite_texture_handle) {
+            (Some(_), handle) if handle != 0 && !samples_outside => self.array_layer_for(handle),
+            _ => None,
+        };
+        let submit_material = material.and_then(|material_id| self.materials.get(&material_id));
+        let state = BatchState {
+            blend_mode,
+            clip: self.clip_stack.last().cloned(),
+            mask: self.mask,
+            material,
+            instanced: instance.is_some(),
+        };
+        // the material's own textures take units away from the batch
+        let material_textures = submit_material.map_or(0, |material| material.texture_count());
+        let limits = BatchLimits {
+            max_sprites: self.max_sprites,
+            max_textures: self.backend.get_mut().max_textures().saturating_sub(material_textures).max(1),
+            reserved_texture: self.reserved_texture(),
+        };
+        let stats = &mut self.stats;
+        let batches = self.batches.get_mut(layer_id).expect("Layer was checked above");
+        // in texture array mode every batch uses the array, the quad picks a layer instead of a slot
+        let texture = SubmitTexture {
+            texture_handle: sprite_texture_handle,
+            batch_texture_handle: match (sprite_texture_handle, array_layer, limits.reserved_texture) {
+                (0, _, Some(reserved_texture_handle)) | (_, Some(_), Some(reserved_texture_handle)) => reserved_texture_handle,
+                (handle, _, _) => handle as i32,
+            },
+            array_layer,
+            array_mode: self.texture_array.is_some(),
+        };
+
+        if let Some(instance) = instance {
+            push_instance_to_batches(batches, &state, texture, limits, &instance, stats);
+            stats.quads += 1;
+            self.sink = sink;
+
+            return true;
+        }
+
+        let sprite_vertices = sink.vertices();
+
+        // counted here rather than when drawing, which can happen more than once per batch
+        stats.quads += match sink.indices() {
+            None => sprite_vertices.len() / 4,
+            Some(indices) => {
+                let triangles = indices.len() / 3;
+
+                triangles / 2 + triangles % 2
+            },
+        };
+
+        match sink.indices() {
+            // a renderable can be made up of many quads (Tilemap), so each quad is
+            // checked on its own and the batch is flushed as soon as it is full
+            None => for quad in sprite_vertices.chunks(4) {
+                let index = push_to_batches(batches, &state, texture, limits, quad, &QUAD_INDICES, stats);
+
+                batches[index].sprite_count += 1;
+            },
+            Some(indices) if limits.fits_in_batch(sprite_vertices.len(), indices.len()) => {
+                push_to_batches(batches, &state, texture, limits, sprite_vertices, indices, stats);
+            },
+            // meshes too big for a single batch are split up into their triangles
+            Some(indices) => for triangle in indices.chunks_exact(3) {
+                let vertices = [
+                    sprite_vertices[triangle[0] as usize],
+                    sprite_vertices[triangle[1] as usize],
+                    sprite_vertices[triangle[2] as usize],
+                ];
+
+                push_to_batches(batches, &state, texture, limits, &vertices, &[0, 1, 2], stats);
+            },
+        }
+
+        self.sink = sink;
+
+        true
+    }
+
+    pub fn set_clear_color(&mut self, r: u8, g: u8, b: u8, a: f32) {
+        let rf = r as f32 / 255.0;
+        let gf = g as f32 / 255.0;
+        let bf = b as f32 / 255.0;
+
+        self.clear_color = (rf, gf, bf, a);
+        self.backend.get_mut().set_clear_color(self.clear_color);
+    }
+
+    /// With a virtual resolution only the viewport is cleared to the clear color, the bars around it are black.
+    /// Puts the letterbox of the `use_viewport` viewport back, in case anything else drew to the window
+    pub fn clear(&self) {
+        let mut backend = self.backend.borrow_mut();
+
+        if let Some(viewport) = &self.viewport {
+            backend.set_viewport(viewport.scaled_rect());
+        }
+
+        if self.virtual_resolution.is_none() {
+            backend.clear(None);
+
+            return;
+        }
+
+        let viewport = backend.viewport();
+
+        backend.set_clear_color((0.0, 0.0, 0.0, 1.0));
+        backend.clear(None);
+        backend.set_clear_color(self.clear_color);
+        backend.clear(Some(viewport));
+    }
+
+    /// Draws everything submitted since `begin_batch` through `camera` into its viewport rect.
+    /// Can be called for more than one camera between `begin_scene_target` and `end_scene`, each camera's
+    /// rect is cleared first so later cameras are drawn on top, ie a minimap over the main view.
+    /// Fails when the backend can't draw a batch, the batches after it aren't drawn
+    pub fn render(&mut self, camera: &Camera) -> Result<(), failure::Error> {
+        let to_scene_target = self.scene_active.get() && self.render_target.is_some();
+        let previous_viewport = self.backend.get_mut().viewport();
+
+        let target_viewport = if to_scene_target {
+            let (width, height) = self.scene_target_size;
+
+            [0, 0, width as i32, height as i32]
+        } else {
+            previous_viewport
+        };
+        let camera_viewport = camera.get_viewport_rect().to_pixels(target_viewport);
+
+        self.backend.get_mut().set_viewport(camera_viewport);
+
+        if let Some(render_target) = self.render_target.as_ref().filter(|_| to_scene_target) {
+            let backend = self.backend.get_mut();
+
+            render_target.bind();
+
+            backend.set_clear_color((0.0, 0.0, 0.0, 0.0));
+            backend.clear(Some(camera_viewport));
+            backend.set_clear_color(self.clear_color);
+        }
+
+        self.clear_masks();
+
+        let drawn = if to_scene_target && self.lighting.is_some() {
+            self.draw_lit_layers(camera, camera_viewport)
+        } else {
+            self.draw_layers(camera, &glm::identity(), LayerPass::Color, LayerSelection::All)
+        };
+
+        if let Some(render_target) = self.render_target.as_ref().filter(|_| to_scene_target) {
+            render_target.unbind();
+        }
+
+        let backend = self.backend.get_mut();
+
+        backend.set_viewport(previous_viewport);
+        backend.submit();
+
+        drawn
+    }
+
+    /// Draws everything submitted since `begin_batch` into `target` instead of the screen,
+    /// `target` is cleared first and can't be used as a texture in the same pass. Fails like `render`
+    pub fn render_to_texture(&mut self, target: &RenderTexture, camera: &Camera) -> Result<(), failure::Error> {
+        let backend = self.backend.get_mut();
+        let previous_viewport = backend.viewport();
+        let (width, height) = target.get_dimensions();
+
+        backend.set_render_target(Some(target.get_texture_handle()));
+        backend.set_clear_color((0.0, 0.0, 0.0, 0.0));
+        backend.clear(None);
+        backend.set_clear_color(self.clear_color);
+        backend.set_viewport(camera.get_viewport_rect().to_pixels([0, 0, width as i32, height as i32]));
+
+        // gl framebuffers are stored bottom row first, render upside down so the texture is
+        // top to bottom like textures loaded from images
+        let target_transform = match backend.kind() {
+            BackendKind::OpenGl => glm::scale(&glm::identity(), &glm::vec3(1.0, -1.0, 1.0)),
+            _ => glm::identity(),
+        };
+
+        self.clear_masks();
+
+        let drawn = self.draw_layers(camera, &target_transform, LayerPass::Color, LayerSelection::All);
+
+        self.backend.get_mut().set_render_target(None);
+        self.invalidate_texture(target.get_texture_handle());
+
+        let backend = self.backend.get_mut();
+
+        backend.set_viewport(previous_viewport);
+        backend.submit();
+
+        drawn
+    }
+
+    /// Fills all of `target` with `color`, the clear color stays what `set_clear_color` set
+    pub fn clear_render_texture(&mut self, target: &RenderTexture, color: (f32, f32, f32, f32)) {
+        let backend = self.backend.get_mut();
+        let previous_viewport = backend.viewport();
+
+        backend.set_render_target(Some(target.get_texture_handle()));
+        backend.set_clear_color(color);
+        backend.clear(None);
+        backend.set_clear_color(self.clear_color);
+        backend.set_render_target(None);
+        backend.set_viewport(previous_viewport);
+        backend.submit();
+        self.invalidate_texture(target.get_texture_handle());
+    }
+
+    // masks from the last frame would still be in the stencil buffer. Cleared once for every
+    // `render` so masks on lit layers still work for the layers drawn after the lighting
+    fn clear_masks(&mut self) {
+        if self.batches.values().flatten().any(|batch| batch.state.mask.is_masked()) {
+            self.backend.get_mut().clear_stencil();
+        }
+    }
+
+    // lit layers are drawn and lit a run at a time, the unlit layers are drawn over them after
+    fn draw_lit_layers(&mut self, camera: &Camera, viewport: [i32; 4]) -> Result<(), failure::Error> {
+        for (i, (lighting_camera, layers)) in self.lit_layer_runs(camera).iter().enumerate() {
+            self.draw_layers(camera, &glm::identity(), LayerPass::Color, LayerSelection::Only(layers))?;
+            // the runs before were lit already
+            self.apply_lighting(lighting_camera, viewport, layers, i > 0)?;
+        }
+
+        self.draw_layers(camera, &glm::identity(), LayerPass::Color, LayerSelection::Unlit)
+    }
+
+    // consecutive lit layers drawn through the same view, with the camera they are drawn with:
+    // `camera` or the layer's own camera
+    fn lit_layer_runs(&self, camera: &Camera) -> Vec<(Camera, Vec<String>)> {
+        let mut runs: Vec<(Camera, Vec<String>)> = Vec::new();
+
+        for layer in self.layers.iter() {
+            let layer_id = layer.get_id();
+
+            if !self.is_layer_lit(&layer_id) || !self.is_layer_visible(&layer_id) {
+                continue;
+            }
+
+            let layer_camera = self.layer_cameras.get(&layer_id).unwrap_or(camera);
+            let view = layer_camera.get_projection() * layer_camera.get_view();
+
+            match runs.last_mut() {
+                Some((run_camera, layers)) if run_camera.get_projection() * run_camera.get_view() == view => {
+                    layers.push(layer_id);
+                },
+                _ => runs.push((layer_camera.clone(), vec![layer_id])),
+            }
+        }
+
+        runs
+    }
+
+    // multiplies the light of this frame's lights, seen through `camera`, over what has been drawn into
+    // the scene target so far or only over `layers` when `only_layers`. Leaves the scene target bound
+    fn apply_lighting(&mut self, camera: &Camera, viewport: [i32; 4], layers: &[String], only_layers: bool) -> Result<(), failure::Error> {
+        let mut lighting = match self.lighting.take() {
+            Some(lighting) => lighting,
+            None => return Ok(()),
+        };
+        let lit = self.light_layers(&mut lighting, camera, viewport, layers, only_layers);
+
+        self.lighting = Some(lighting);
+
+        lit
+    }
+
+    fn light_layers(
+        &mut self,
+        lighting: &mut Lighting,
+        camera: &Camera,
+        viewport: [i32; 4],
+        layers: &[String],
+        only_layers: bool,
+    ) -> Result<(), failure::Error> {
+        let mvp = camera.get_projection() * camera.get_view();
+        // the normal buffer is drawn with the normal maps in place of the batch textures,
+        // which the texture array doesn't have slots for
+        let use_normals = self.texture_array.is_none() && lighting.has_normal_maps();
+
+        lighting.resize(self.scene_target_size);
+
+        if use_normals {
+            lighting.begin_normals(viewport);
+            self.draw_layers(camera, &glm::identity(), LayerPass::Normals(lighting), LayerSelection::Only(layers))?;
+        }
+
+        lighting.render_lights(&mvp, viewport, &self.lights, &self.occluders, use_normals);
+
+        if let Some(render_target) = &self.render_target {
+            render_target.bind();
+        }
+
+        if only_layers {
+            mask::clear_coverage();
+            self.draw_layers(camera, &glm::identity(), LayerPass::Coverage, LayerSelection::Only(layers))?;
+        }
+
+        lighting.composite(&mvp, only_layers.then_some(COVERAGE_BIT));
+
+        Ok(())
+    }
+
+    // stops at the first batch the backend can't draw
+    fn draw_layers(
+        &mut self,
+        camera: &Camera,
+        target_transform: &glm::Mat4,
+        pass: LayerPass,
+        selection: LayerSelection,
+    ) -> Result<(), failure::Error> {
+        let viewport = self.backend.get_mut().viewport();
+        let materials = &self.materials;
+        let texture_array = &mut self.texture_array;
+
+        for layer in self.layers.iter() {
+            let layer_id = layer.get_id();
+            let selected = match selection {
+                LayerSelection::All => true,
+                LayerSelection::Only(layers) => layers.contains(&layer_id),
+                LayerSelection::Unlit => self.unlit_layers.contains(&layer_id),
+            };
+
+            if !selected {
+                continue;
+            }
+
+            if self.hidden_layers.contains(&layer_id) {
+                continue;
+            }
+
+            let layer_camera = self.layer_cameras.get(&layer_id).unwrap_or(camera);
+            let camera_mvp = layer_camera.get_projection() * layer_camera.get_view();
+            let layer_mvp = target_transform * camera_mvp;
+            let batches = match self.batches.get(&layer_id) {
+                Some(batches) => batches,
+                None => continue,
+            };
+
+            for batch in batches.iter().filter(|batch| !batch.is_empty()) {
+                let normal_maps: Vec<i32>;
+                let (material, blend_mode, mask, textures) = match pass {
+                    LayerPass::Color => (
+                        batch.state.material.and_then(|material_id| materials.get(&material_id)),
+                        batch.state.blend_mode,
+                        batch.state.mask,
+                        &batch.texture_slots,
+                    ),
+                    // masks only shape what is drawn in the colour pass
+                    LayerPass::Normals(_) if matches!(batch.state.mask, MaskState::Write(_)) => continue,
+                    LayerPass::Normals(lighting) => {
+                        normal_maps = batch.texture_slots.iter()
+                            .map(|&texture_handle| lighting.normal_texture(texture_handle))
+                            .collect();
+
+                        (Some(lighting.normal_material()), BlendMode::Opaque, MaskState::Off, &normal_maps)
+                    },
+                    LayerPass::Coverage if matches!(batch.state.mask, MaskState::Write(_)) => continue,
+                    LayerPass::Coverage => (None, BlendMode::Opaque, MaskState::Cover, &batch.texture_slots),
+                };
+
+                if let Some(texture_array) = texture_array.as_mut() {
+                    texture_array.copy_layers(&batch.array_textures);
+                }
+
+                let drawn = self.backend.get_mut().draw_batch(&BatchDraw {
+                    mvp: layer_mvp,
+                    vertices: &batch.vertices,
+                    indices: &batch.indices,
+                    instances: &batch.instances,
+                    textures,
+                    blend_mode,
+                    mask,
+                    scissor: scissor_box(
+                        batch.state.clip.as_ref().map(|clip| clip.to_ndc(&camera_mvp, layer_camera.get_dimensions())),
+                        target_transform,
+                        viewport,
+                    ),
+                    material,
+                });
+
+                if let Err(error) = drawn {
+                    self.backend.get_mut().reset_state();
+
+                    return Err(error);
+                }
+
+                // the lighting passes draw the same batches again, they aren't counted
+                if let LayerPass::Color = pass {
+                    count_batch(batch, &mut self.stats);
+                }
+            }
+        }
+
+        self.backend.get_mut().reset_state();
+
+        Ok(())
+    }
+}
+
+impl Drop for Renderer2D {
+    fn drop(&mut self) {
+        self.backend.get_mut().delete_texture(self.white_texture);
+    }
+}
+
+// scissor is measured against whatever viewport is in use, the window or a RenderTarget/RenderTexture.
+// Without a clip the scissor still keeps drawing inside the viewport, as it may only be part of the target
+fn scissor_box(clip: Option<NdcRect>, target_transform: &glm::Mat4, viewport: [i32; 4]) -> [i32; 4] {
+    match clip {
+        Some(clip) => clip.scissor_box(target_transform, viewport),
+        None => viewport,
+    }
+}
+
+// whether a texture coordinate is past the edges of the texture, with some room for rounding
+fn outside_texture((u, v): (f32, f32)) -> bool {
+    const EPSILON: f32 = 0.0001;
+
+    u < -EPSILON || v < -EPSILON || u > 1.0 + EPSILON || v > 1.0 + EPSILON
+}
+
+// moves a quad's texture transform onto the part of the array layer its texture was copied into
+fn array_uv_transform(
+    layer: &ArrayLayer,
+    translate: data::f32_f32_f32,
+    scale: data::f32_f32_f32,
+) -> (data::f32_f32_f32, data::f32_f32_f32) {
+    let (u, v) = layer.uv_scale;
+
+    (
+        (translate.d0 * u, translate.d1 * v, translate.d2).into(),
+        (scale.d0 * u, scale.d1 * v, scale.d2).into(),
+    )
+}
+
+fn count_batch(batch: &Batch, stats: &mut RenderStats) {
+    stats.draw_calls += 1;
+    stats.texture_binds += batch.texture_slots.len();
+
+    if batch.state.instanced {
+        stats.triangles += batch.instances.len() * 2;
+        stats.vertex_bytes += batch.instances.len() * ::std::mem::size_of::<InstanceData>();
+    } else {
+        stats.triangles += batch.indices.len() / 3;
+        stats.vertex_bytes += batch.vertices.len() * ::std::mem::size_of::<BatchVertex>();
+    }
+}
+
+fn generate_texture_slots(max: i32) -> Vec<i32> {
+    let mut texture_slots = Vec::new();
+
+    for i in 0..max {
+        texture_slots.push(i as i32);
+    }
+
+    texture_slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: (usize, usize) = (4, 6);
+
+    fn test_vertex() -> BatchVertex {
+        BatchVertex {
+            pos: (0.0, 0.0, 0.0).into(),
+            tex: (0.0, 0.0).into(),
+            color: (1.0, 1.0, 1.0, 1.0).into(),
+            tex_id: 0.0.into(),
+            tex_translate: (0.0, 0.0, 0.0).into(),
+            tex_scale: (1.0, 1.0, 1.0).into(),
+        }
+    }
+
+    #[test]
+    fn batch_offsets_indices_by_its_vertices() {
+        let mut batch = Batch::new(10, BatchState::default());
+        let quad = || (0..4).map(|_| test_vertex()).collect::<Vec<_>>();
+
+        batch.push(quad(), &QUAD_INDICES);
+        batch.push(quad(), &QUAD_INDICES);
+        batch.push(vec![test_vertex(), test_vertex(), test_vertex()], &[0, 1, 2]);
+
+        assert_eq!(batch.indices, vec![0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn can_generate_texture_slots() {
+        let max = 4;
+        let actual_texture_slots = generate_texture_slots(max);
+        let expected_texture_slots = vec![0, 1, 2, 3];
+
+        assert_eq!(max as usize, actual_texture_slots.len());
+        assert_eq!(expected_texture_slots, actual_texture_slots);
+    }
+
+    #[test]
+    fn batch_is_full_at_max_sprites() {
+        let max_sprites = 2;
+        let state = BatchState::default();
+        let mut batch = Batch::new(max_sprites, state.clone());
+
+        assert!(batch.can_fit(&state, 1, QUAD, max_sprites, 4));
+
+        batch.push((0..8).map(|_| test_vertex()), &[0; 12]);
+
+        assert!(!batch.can_fit(&state, 1, QUAD, max_sprites, 4));
+    }
+
+    #[test]
+    fn batch_is_full_when_out_of_indices() {
+        let state = BatchState::default();
+        let mut batch = Batch::new(1, state.clone());
+
+        // a fan of 4 triangles only needs 6 vertices but 12 indices
+        assert!(!batch.can_fit(&state, 1, (6, 12), 1, 4));
+
+        batch.push(vec![test_vertex(), test_vertex(), test_vertex()], &[0, 1, 2]);
+
+        assert!(batch.can_fit(&state, 1, (1, 3), 1, 4));
+        assert!(!batch.can_fit(&state, 1, (1, 6), 1, 4));
+    }
+
+    #[test]
+    fn batch_is_full_when_out_of_texture_slots() {
+        let max_textures = 2;
+        let state = BatchState::default();
+        let mut batch = Batch::new(10, state.clone());
+
+        batch.texture_slot(11);
+        batch.texture_slot(12);
+
+        assert!(batch.can_fit(&state, 11, QUAD, 10, max_textures));
+        assert!(batch.can_fit(&state, 12, QUAD, 10, max_textures));
+        assert!(!batch.can_fit(&state, 13, QUAD, 10, max_textures));
+    }
+
+    #[test]
+    fn batch_reuses_texture_slots() {
+        let mut batch = Batch::new(10, BatchState::default());
+
+        assert_eq!(0, batch.texture_slot(11));
+        assert_eq!(1, batch.texture_slot(12));
+        assert_eq!(0, batch.texture_slot(11));
+        assert_eq!(vec![11, 12], batch.texture_slots);
+    }
+
+    #[test]
+    fn batch_is_full_when_blend_mode_changes() {
+        let alpha = BatchState { blend_mode: BlendMode::Alpha, ..Default::default() };
+        let additive = BatchState { blend_mode: BlendMode::Additive, ..Default::default() };
+        let batch = Batch::new(10, alpha.clone());
+
+        assert!(batch.can_fit(&alpha, 1, QUAD, 10, 4));
+        assert!(!batch.can_fit(&additive, 1, QUAD, 10, 4));
+    }
+
+    #[test]
+    fn batch_is_full_when_clip_changes() {
+        let clip = Clip::default().push(ClipRect::new(0.0, 0.0, 10.0, 10.0), ClipSpace::Screen);
+        let unclipped = BatchState::default();
+        let clipped = BatchState { clip: Some(clip), ..Default::default() };
+        let batch = Batch::new(10, unclipped.clone());
+
+        assert!(batch.can_fit(&unclipped, 1, QUAD, 10, 4));
+        assert!(!batch.can_fit(&clipped, 1, QUAD, 10, 4));
+    }
+
+    #[test]
+    fn batch_is_full_when_mask_changes() {
+        let writing = BatchState { mask: MaskState::Write(1), ..Default::default() };
+        let inside = BatchState { mask: MaskState::Inside(1), ..Default::default() };
+        let next_inside = BatchState { mask: MaskState::Inside(2), ..Default::default() };
+        let batch = Batch::new(10, writing.clone());
+
+        assert!(batch.can_fit(&writing, 1, QUAD, 10, 4));
+        assert!(!batch.can_fit(&inside, 1, QUAD, 10, 4));
+        assert!(!Batch::new(10, inside).can_fit(&next_inside, 1, QUAD, 10, 4));
+    }
+
+    #[test]
+    fn batch_is_full_when_material_changes() {
+        let default = BatchState::default();
+        let material = BatchState { material: Some(MaterialId(0)), ..Default::default() };
+        let other_material = BatchState { material: Some(MaterialId(1)), ..Default::default() };
+        let batch = Batch::new(10, material.clone());
+
+        assert!(batch.can_fit(&material, 1, QUAD, 10, 4));
+        assert!(!batch.can_fit(&default, 1, QUAD, 10, 4));
+        assert!(!batch.can_fit(&other_material, 1, QUAD, 10, 4));
+    }
+
+    #[test]
+    fn default_layer_is_kept_and_unknown_layers_are_ignored() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let default_layer = renderer.default_layer();
+        let ui_layer = renderer.add_layer(Layer::new("ui", LayerKind::UI, 0));
+
+        assert!(!renderer.remove_layer(&default_layer));
+        assert!(renderer.remove_layer(&ui_layer));
+        assert!(!renderer.remove_layer(&ui_layer));
+
+        renderer.begin_batch();
+
+        assert!(!renderer.submit_to_layer(&ui_layer, &shapes::convex_polygon(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], (1.0, 1.0, 1.0, 1.0))));
+
+        assert!(renderer.batches[&default_layer].is_empty());
+        assert!(!renderer.batches.contains_key(&ui_layer));
+    }
+
+    struct LooseVertices;
+
+    impl Renderable2D for LooseVertices {
+        fn write_vertices(&self, out: &mut VertexSink) {
+            let vertex = renderable::BasicVertex { pos: (0.0, 0.0, 0.0), uv: (0.0, 0.0), color: (1.0, 1.0, 1.0, 1.0) };
+
+            out.extend(&[vertex; 6]);
+        }
+    }
+
+    #[test]
+    fn vertices_that_are_not_quads_are_rejected() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let layer = renderer.default_layer();
+
+        renderer.begin_batch();
+
+        assert!(!renderer.submit_to_layer(&layer, &LooseVertices));
+        assert!(renderer.batches[&layer].is_empty());
+    }
+
+    struct BadIndices(Vec<u32>);
+
+    impl Renderable2D for BadIndices {
+        fn write_vertices(&self, out: &mut VertexSink) {
+            let vertex = renderable::BasicVertex { pos: (0.0, 0.0, 0.0), uv: (0.0, 0.0), color: (1.0, 1.0, 1.0, 1.0) };
+
+            out.extend(&[vertex; 3]);
+            out.extend_indices(&self.0);
+        }
+    }
+
+    #[test]
+    fn indices_that_are_not_triangles_of_the_vertices_are_rejected() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let layer = renderer.default_layer();
+
+        renderer.begin_batch();
+
+        assert!(!renderer.submit_to_layer(&layer, &BadIndices(vec![0, 1, 3])));
+        assert!(!renderer.submit_to_layer(&layer, &BadIndices(vec![0, 1, 2, 0])));
+        assert!(renderer.batches[&layer].is_empty());
+
+        assert!(renderer.submit_to_layer(&layer, &BadIndices(vec![0, 1, 2])));
+    }
+
+    #[test]
+    fn resizing_recomputes_the_letterbox() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let viewport = helpers::Viewport::with_virtual_resolution(640.0, 360.0, 320, 180, helpers::ScalingMode::Integer);
+
+        renderer.use_viewport(&viewport);
+
+        assert_eq!(renderer.get_virtual_resolution(), Some((320, 180)));
+        assert_eq!(renderer.get_backend().viewport(), [0, 0, 640, 360]);
+
+        renderer.resize(700.0, 400.0);
+
+        assert_eq!(renderer.get_backend().viewport(), [30, 20, 640, 360]);
+        assert_eq!(renderer.get_viewport().map(helpers::Viewport::scaled_rect), Some([30, 20, 640, 360]));
+
+        // something else drawing to the whole window doesn't move the letterbox for the next frame
+        renderer.get_backend_mut().set_viewport([0, 0, 700, 400]);
+        renderer.clear();
+
+        assert_eq!(renderer.get_backend().viewport(), [30, 20, 640, 360]);
+    }
+
+    #[test]
+    fn hidden_layers_are_not_drawn() {
+        let camera = Camera::new(4.0, 4.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let ui_layer = renderer.add_layer(Layer::new("ui", LayerKind::UI, 0));
+
+        renderer.begin_batch();
+        renderer.submit_to_layer(&ui_layer, &shapes::convex_polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)], (1.0, 1.0, 1.0, 1.0)));
+        renderer.end_batch();
+        renderer.set_layer_visible(&ui_layer, false);
+        renderer.render(&camera).unwrap();
+
+        assert!(!renderer.is_layer_visible(&ui_layer));
+        assert_eq!(renderer.stats().draw_calls, 0);
+
+        renderer.set_layer_visible(&ui_layer, true);
+        renderer.render(&camera).unwrap();
+
+        assert_eq!(renderer.stats().draw_calls, 1);
+    }
+
+    #[test]
+    fn render_textures_are_drawn_top_row_first() {
+        let camera = Camera::new(4.0, 4.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let target = RenderTexture::new(renderer.get_backend_mut(), 4, 4).unwrap();
+
+        renderer.begin_batch();
+        renderer.submit(&shapes::convex_polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)], (1.0, 0.0, 0.0, 1.0)));
+        renderer.end_batch();
+        renderer.render_to_texture(&target, &camera).unwrap();
+
+        assert_eq!(renderer.get_backend().viewport(), [0, 0, 4, 4]);
+
+        let backend = renderer.get_backend_mut();
+
+        backend.set_render_target(Some(target.get_texture_handle()));
+
+        let image = backend.read_pixels();
+
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 0, 0]);
+
+        target.delete(backend);
+    }
+
+    #[test]
+    fn gl_only_features_fail_on_other_backends() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+
+        match renderer.begin_scene_target().map_err(|error| error.downcast::<backend::Error>()) {
+            Err(Ok(backend::Error::OpenGlOnly { feature: "begin_scene_target", backend: BackendKind::Software })) => (),
+            other => panic!("expected OpenGlOnly, got {:?}", other),
+        }
+
+        assert!(renderer.set_post_process_chain(PostProcessChain::new()).is_err());
+        assert!(renderer.get_post_process_chain_mut().is_none());
+    }
+
+    // needs a gl context, skipped where EGL can't make one. Without any layers every texture is
+    // bound to a slot of its own
+    #[cfg(feature = "headless")]
+    #[test]
+    fn texture_array_draws_more_textures_than_it_has_layers() {
+        use crate::sprite::{Sprite, SpriteProps};
+        use crate::textures::texture::{Texture};
+
+        let context = match crate::headless::HeadlessContext::new(6, 2) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(6.0, 2.0, Projection::Ortho).unwrap();
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let textures: Vec<Texture> = colors.iter().map(|color| Texture::from_pixels(2, 2, &color.repeat(4))).collect();
+
+        for &max_layers in &[2, 0] {
+            let batch_mode = BatchMode::TextureArray { layer_width: 2, layer_height: 2, max_layers };
+            let mut renderer = Renderer2D::with_batch_mode(batch_mode).unwrap();
+
+            renderer.begin_batch();
+
+            for (i, texture) in textures.iter().enumerate() {
+                // sprites own a copy of their texture, this one doesn't delete it when the sprite is dropped
+                let texture = Texture::from_handle(texture.get_texture_handle(), 2, 2);
+                let props = SpriteProps { pos: (2.0 * i as f32, 0.0, 0.0), dim: (2, 2), ..SpriteProps::default() };
+
+                renderer.submit(&Sprite::from_texture(&texture, props).unwrap());
+            }
+
+            renderer.render(&camera).unwrap();
+
+            let image = context.read_pixels();
+
+            for (i, color) in colors.iter().enumerate() {
+                assert_eq!(image.get_pixel(2 * i as u32, 0).0, *color);
+            }
+        }
+    }
+
+    // needs a gl context like the test above. The target is drawn upside down into its framebuffer,
+    // a sprite of it has to come out the right way up on the screen
+    #[cfg(feature = "headless")]
+    #[test]
+    fn render_textures_are_drawn_through_gl_render_targets() {
+        use crate::sprite::{Sprite, SpriteProps};
+
+        let context = match crate::headless::HeadlessContext::new(4, 4) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(4.0, 4.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::new().unwrap();
+        let target = RenderTexture::new(renderer.get_backend_mut(), 4, 4).unwrap();
+
+        renderer.begin_batch();
+        renderer.submit(&shapes::convex_polygon(&[(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)], (1.0, 0.0, 0.0, 1.0)));
+        renderer.end_batch();
+        renderer.render_to_texture(&target, &camera).unwrap();
+
+        assert_eq!(renderer.get_backend().viewport(), [0, 0, 4, 4]);
+
+        let props = SpriteProps { dim: (4, 4), ..SpriteProps::default() };
+
+        renderer.set_clear_color(0, 0, 255, 1.0);
+        renderer.clear();
+        renderer.begin_batch();
+        renderer.submit(&Sprite::from_texture(&target.as_texture(), props).unwrap());
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+
+        let image = context.read_pixels();
+
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 255, 255]);
+
+        target.delete(renderer.get_backend_mut());
+    }
+
+    // needs a gl context like the test above. A sprite twice as wide as its texture samples past the
+    // texture's edge, which has to look the same as in texture unit mode and not show the padding of the layer
+    #[cfg(feature = "headless")]
+    #[test]
+    fn texture_array_samples_past_the_texture_like_texture_units() {
+        use crate::sprite::{Sprite, SpriteProps};
+        use crate::textures::texture::{Texture};
+
+        let context = match crate::headless::HeadlessContext::new(4, 2) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(4.0, 2.0, Projection::Ortho).unwrap();
+        let texture = Texture::from_pixels(2, 2, &[[255, 0, 0, 255], [0, 255, 0, 255]].concat().repeat(2));
+        let props = SpriteProps { dim: (4, 2), ..SpriteProps::default() };
+        let sprite = Sprite::from_texture(&Texture::from_handle(texture.get_texture_handle(), 2, 2), props).unwrap();
+        let images: Vec<_> = [BatchMode::TextureUnits, BatchMode::TextureArray { layer_width: 4, layer_height: 4, max_layers: 2 }]
+            .iter()
+            .map(|&batch_mode| {
+                let mut renderer = Renderer2D::with_batch_mode(batch_mode).unwrap();
+
+                renderer.set_clear_color(0, 0, 0, 1.0);
+                renderer.clear();
+                renderer.begin_batch();
+                renderer.submit(&sprite);
+                renderer.render(&camera).unwrap();
+
+                context.read_pixels()
+            })
+            .collect();
+
+        assert_eq!(images[0].get_pixel(3, 0).0, [0, 255, 0, 255]);
+        assert_eq!(images[0].as_ref() as &[u8], images[1].as_ref() as &[u8]);
+    }
+
+    // needs a gl context like the test above. The same square at the light is drawn once through the
+    // scene camera and once through a layer camera that moves it 16 pixels right, both have to be lit
+    #[cfg(feature = "headless")]
+    #[test]
+    fn lit_layers_are_lit_through_their_own_camera() {
+        use crate::lighting::{PointLight};
+
+        let context = match crate::headless::HeadlessContext::new(32, 8) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(32.0, 8.0, Projection::Ortho).unwrap();
+        let mut moved_camera = camera.clone();
+        let mut renderer = Renderer2D::new().unwrap();
+        let moved_layer = renderer.add_layer(Layer::new("moved", LayerKind::Generic, 1));
+        let square = shapes::convex_polygon(&[(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)], (1.0, 1.0, 1.0, 1.0));
+
+        moved_camera.set_position(16.0, 0.0, 0.0);
+        renderer.set_layer_camera(&moved_layer, &moved_camera);
+        renderer.set_lighting(Lighting::new((0.0, 0.0, 0.0)).unwrap()).unwrap();
+        renderer.set_clear_color(0, 0, 0, 1.0);
+        renderer.clear();
+        renderer.begin_scene_target().unwrap();
+        renderer.begin_batch();
+        renderer.submit(&square);
+        renderer.submit_to_layer(&moved_layer, &square);
+        renderer.submit_light(PointLight { pos: (4.0, 4.0), radius: 8.0, casts_shadows: false, ..Default::default() });
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+        renderer.end_scene().unwrap();
+
+        let image = context.read_pixels();
+
+        assert!(image.get_pixel(4, 4).0[0] > 128);
+        assert!(image.get_pixel(20, 4).0[0] > 128);
+        assert_eq!(image.get_pixel(12, 4).0[0], 0);
+    }
+
+    // needs a gl context like the test above. The normal and coverage passes of the lighting
+    // draw the batch again, but the stats only count the scene as it was submitted
+    #[cfg(feature = "headless")]
+    #[test]
+    fn lit_frames_are_counted_once() {
+        use crate::lighting::{PointLight};
+
+        let _context = match crate::headless::HeadlessContext::new(8, 8) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(8.0, 8.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::new().unwrap();
+        let square = shapes::convex_polygon(&[(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)], (1.0, 1.0, 1.0, 1.0));
+
+        renderer.set_lighting(Lighting::new((0.0, 0.0, 0.0)).unwrap()).unwrap();
+        renderer.begin_scene_target().unwrap();
+        renderer.begin_batch();
+        renderer.submit(&square);
+        renderer.submit(&square);
+        renderer.submit_light(PointLight { pos: (4.0, 4.0), radius: 8.0, casts_shadows: false, ..Default::default() });
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+        renderer.end_scene().unwrap();
+
+        let stats = renderer.stats();
+
+        assert_eq!(stats.draw_calls, 1);
+        assert_eq!(stats.quads, 2);
+        assert_eq!(stats.triangles, 4);
+    }
+
+    // needs a gl context like the test above. The mask is written on the lit layer and the unlit
+    // layer drawn after the lighting is only drawn inside it
+    #[cfg(feature = "headless")]
+    #[test]
+    fn masks_on_lit_layers_are_kept_for_unlit_layers() {
+        let context = match crate::headless::HeadlessContext::new(16, 8) {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        let camera = Camera::new(16.0, 8.0, Projection::Ortho).unwrap();
+        let mut renderer = Renderer2D::new().unwrap();
+        let ui_layer = renderer.add_layer(Layer::new("ui", LayerKind::UI, 1));
+        let left = shapes::convex_polygon(&[(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)], (1.0, 1.0, 1.0, 1.0));
+        let whole = shapes::convex_polygon(&[(0.0, 0.0), (16.0, 0.0), (16.0, 8.0), (0.0, 8.0)], (1.0, 0.0, 0.0, 1.0));
+
+        renderer.set_layer_lit(&ui_layer, false);
+        renderer.set_lighting(Lighting::new((1.0, 1.0, 1.0)).unwrap()).unwrap();
+        renderer.set_clear_color(0, 0, 0, 1.0);
+        renderer.clear();
+        renderer.begin_scene_target().unwrap();
+        renderer.begin_batch();
+        renderer.begin_mask();
+        renderer.submit(&left);
+        renderer.end_mask();
+        renderer.submit_to_layer(&ui_layer, &whole);
+        renderer.end_batch();
+        renderer.render(&camera).unwrap();
+        renderer.end_scene().unwrap();
+
+        let image = context.read_pixels();
+
+        assert_eq!(image.get_pixel(4, 4).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(12, 4).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn interleaved_materials_are_drawn_in_submission_order() {
+        let mut renderer = Renderer2D::with_backend(backend::SoftwareBackend::new(4, 4));
+        let triangle = shapes::convex_polygon(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], (1.0, 1.0, 1.0, 1.0));
+        let layer = renderer.default_layer();
+
+        renderer.begin_batch();
+
+        for i in 0..4 {
+            renderer.submit_with_material(&triangle, Some(MaterialId(i % 2)));
+        }
+
+        renderer.submit_with_material(&triangle, Some(MaterialId(1)));
+
+        let materials: Vec<_> = renderer.batches[&layer].iter().map(|batch| (batch.state.material, batch.indices.len())).collect();
+
+        assert_eq!(materials, vec![
+            (Some(MaterialId(0)), 3),
+            (Some(MaterialId(1)), 3),
+            (Some(MaterialId(0)), 3),
+            (Some(MaterialId(1)), 6),
+        ]);
+    }
+
+    #[test]
+    fn array_uv_transform_scales_into_layer() {
+        let layer = ArrayLayer { index: 2, uv_scale: (0.5, 0.25) };
+        let (translate, scale) = array_uv_transform(&layer, (0.5, 1.0, 0.0).into(), (1.0, 2.0, 1.0).into());
+
+        assert_eq!((translate.d0, translate.d1, translate.d2), (0.25, 0.25, 0.0));
+        assert_eq!((scale.d0, scale.d1, scale.d2), (0.5, 0.5, 1.0));
     }
 }
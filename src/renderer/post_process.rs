@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::helpers::{self, UniformValue};
+use crate::helpers::buffer::{FrameBuffer};
+use super::blend_mode::{BlendMode};
+use super::render_target::{RenderTarget, QUAD_VERTEX_SOURCE};
+
+pub type UniformCallback = Box<dyn FnMut(&helpers::Program)>;
+
+/// Texture units below this are used for `RenderTexture` and `SceneTexture`
+const FIRST_EXTRA_TEXTURE_UNIT: u32 = 2;
+
+/// A parameterised effect made up of one or more passes, see `crate::postfx` for the built in ones
+pub trait PostEffect {
+    /// Passes are named after the effect, effects with more than one pass use `name/step`
+    fn name(&self) -> &str;
+    fn build_passes(&self) -> Result<Vec<PostProcessPass>, failure::Error>;
+    /// Set on every pass of the effect when it is added and by `PostProcessChain::update_effect`
+    fn uniforms(&self) -> Vec<(&'static str, UniformValue)>;
+    fn textures(&self) -> Vec<(&'static str, gl::types::GLuint)> {
+        Vec::new()
+    }
+}
+
+/// A single full screen effect in a `PostProcessChain`. The fragment shader gets the output
+/// of the previous pass as `RenderTexture` (unit 0), the untouched scene as `SceneTexture` (unit 1)
+/// if enabled, and the size of the scene in pixels as `Resolution`
+pub struct PostProcessPass {
+    name: String,
+    program: helpers::Program,
+    uniforms: HashMap<String, UniformValue>,
+    textures: Vec<(String, gl::types::GLuint)>,
+    on_apply: Option<UniformCallback>,
+    enabled: bool,
+    scene_texture: bool,
+}
+
+impl fmt::Debug for PostProcessPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostProcessPass")
+            .field("name", &self.name)
+            .field("program", &self.program)
+            .field("uniforms", &self.uniforms)
+            .field("textures", &self.textures)
+            .field("on_apply", &self.on_apply.as_ref().map(|_| "UniformCallback".to_string()))
+            .field("enabled", &self.enabled)
+            .field("scene_texture", &self.scene_texture)
+            .finish()
+    }
+}
+
+impl PostProcessPass {
+    pub fn new(name: &str, program: helpers::Program) -> PostProcessPass {
+        PostProcessPass {
+            name: name.to_string(),
+            program,
+            uniforms: HashMap::new(),
+            textures: Vec::new(),
+            on_apply: None,
+            enabled: true,
+            scene_texture: false,
+        }
+    }
+
+    /// Compiles `fragment_source` with the full screen quad vertex shader,
+    /// texture coordinates are passed in as `IN.UV`
+    pub fn from_fragment_source(name: &str, fragment_source: &str) -> Result<PostProcessPass, failure::Error> {
+        let shaders = [
+            helpers::Shader::from_raw(QUAD_VERTEX_SOURCE, gl::VERTEX_SHADER)?,
+            helpers::Shader::from_raw(fragment_source, gl::FRAGMENT_SHADER)?,
+        ];
+        let program = helpers::Program::from_shaders(&shaders[..], &format!("internal/shaders/post_process/{}", name))
+            .map_err(|message| helpers::Error::LinkError { name: name.into(), message })?;
+
+        Ok(PostProcessPass::new(name, program))
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_program(&self) -> &helpers::Program {
+        &self.program
+    }
+
+    pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        self.uniforms.insert(name.to_string(), value);
+    }
+
+    pub fn get_uniform(&self, name: &str) -> Option<&UniformValue> {
+        self.uniforms.get(name)
+    }
+
+    /// Binds an extra texture (e.g. a lookup table) to the `name` sampler, starting at texture unit 2.
+    /// The texture is not owned by the pass and has to outlive it
+    pub fn set_texture(&mut self, name: &str, texture_handle: gl::types::GLuint) {
+        match self.textures.iter_mut().find(|(texture_name, _)| texture_name == name) {
+            Some(texture) => texture.1 = texture_handle,
+            None => self.textures.push((name.to_string(), texture_handle)),
+        }
+    }
+
+    /// Called every frame after the uniform map is set, for uniforms that change each frame
+    pub fn set_uniform_callback<F>(&mut self, callback: F) where F: FnMut(&helpers::Program) + 'static {
+        self.on_apply = Some(Box::new(callback));
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Binds the scene as it was before any post processing as `SceneTexture`
+    pub fn use_scene_texture(&mut self, enabled: bool) {
+        self.scene_texture = enabled;
+    }
+
+    fn apply(&mut self, input: gl::types::GLuint, scene: gl::types::GLuint, resolution: (u32, u32)) {
+        let (width, height) = resolution;
+
+        self.program.set_used();
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, input);
+        }
+
+        if let Ok(location) = self.program.get_uniform_location("RenderTexture") {
+            self.program.set_uniform_1i(location, 0);
+        }
+
+        if self.scene_texture {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, scene);
+            }
+
+            if let Ok(location) = self.program.get_uniform_location("SceneTexture") {
+                self.program.set_uniform_1i(location, 1);
+            }
+        }
+
+        for (i, (name, texture_handle)) in self.textures.iter().enumerate() {
+            let unit = FIRST_EXTRA_TEXTURE_UNIT + i as u32;
+
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+                gl::BindTexture(gl::TEXTURE_2D, *texture_handle);
+            }
+
+            if let Ok(location) = self.program.get_uniform_location(name) {
+                self.program.set_uniform_1i(location, unit as i32);
+            }
+        }
+
+        if let Ok(location) = self.program.get_uniform_location("Resolution") {
+            self.program.set_uniform_2f(location, &glm::vec2(width as f32, height as f32));
+        }
+
+        for (name, value) in &self.uniforms {
+            if let Ok(location) = self.program.get_uniform_location(name) {
+                self.program.set_uniform(location, value);
+            }
+        }
+
+        if let Some(on_apply) = &mut self.on_apply {
+            on_apply(&self.program);
+        }
+    }
+}
+
+/// Ordered full screen passes run over the scene, each pass renders into one of two
+/// framebuffers and reads from the other, the last enabled pass draws to the screen
+#[derive(Debug, Default)]
+pub struct PostProcessChain {
+    passes: Vec<PostProcessPass>,
+    targets: Vec<FrameBuffer>,
+    size: (u32, u32),
+}
+
+impl PostProcessChain {
+    pub fn new() -> PostProcessChain {
+        PostProcessChain {
+            passes: Vec::new(),
+            targets: Vec::new(),
+            size: (0, 0),
+        }
+    }
+
+    pub fn add_pass(&mut self, pass: PostProcessPass) {
+        self.passes.push(pass);
+    }
+
+    pub fn insert_pass(&mut self, index: usize, pass: PostProcessPass) {
+        self.passes.insert(index, pass);
+    }
+
+    pub fn remove_pass(&mut self, name: &str) -> Option<PostProcessPass> {
+        let index = self.passes.iter().position(|pass| pass.name == name)?;
+
+        Some(self.passes.remove(index))
+    }
+
+    pub fn get_pass(&self, name: &str) -> Option<&PostProcessPass> {
+        self.passes.iter().find(|pass| pass.name == name)
+    }
+
+    pub fn get_pass_mut(&mut self, name: &str) -> Option<&mut PostProcessPass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if let Some(pass) = self.get_pass_mut(name) {
+            pass.set_enabled(enabled);
+        }
+    }
+
+    /// Builds the passes of `effect` and appends them to the end of the chain
+    pub fn add_effect<E: PostEffect>(&mut self, effect: &E) -> Result<(), failure::Error> {
+        for mut pass in effect.build_passes()? {
+            apply_effect_settings(effect, &mut pass);
+            self.passes.push(pass);
+        }
+
+        Ok(())
+    }
+
+    /// Pushes changed parameters of `effect` to the passes already in the chain
+    pub fn update_effect<E: PostEffect>(&mut self, effect: &E) {
+        for pass in self.passes.iter_mut().filter(|pass| is_effect_pass(&pass.name, effect.name())) {
+            apply_effect_settings(effect, pass);
+        }
+    }
+
+    pub fn set_effect_enabled(&mut self, name: &str, enabled: bool) {
+        for pass in self.passes.iter_mut().filter(|pass| is_effect_pass(&pass.name, name)) {
+            pass.set_enabled(enabled);
+        }
+    }
+
+    pub fn remove_effect(&mut self, name: &str) {
+        self.passes.retain(|pass| !is_effect_pass(&pass.name, name));
+    }
+
+    pub fn passes(&self) -> &[PostProcessPass] {
+        &self.passes
+    }
+
+    pub fn has_enabled_passes(&self) -> bool {
+        self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    pub(crate) fn render(&mut self, render_target: &RenderTarget, width: u32, height: u32) -> Result<(), failure::Error> {
+        self.resize_targets(width, height)?;
+
+        let scene = render_target.frame_buffer.texture.get_texture_handle();
+        let enabled: Vec<usize> = (0..self.passes.len())
+            .filter(|&i| self.passes[i].enabled)
+            .collect();
+        let mut clear_color: [gl::types::GLfloat; 4] = [0.0; 4];
+        let mut input = scene;
+
+        unsafe {
+            gl::GetFloatv(gl::COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+        }
+
+        for (i, &pass_index) in enabled.iter().enumerate() {
+            let is_last = i == enabled.len() - 1;
+            let target = &self.targets[i % 2];
+
+            if is_last {
+                // the last pass is blended over whatever was drawn to the screen before the scene
+                BlendMode::Alpha.apply();
+            } else {
+                target.bind();
+                BlendMode::Opaque.apply();
+
+                unsafe {
+                    gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+                    gl::Clear(gl::COLOR_BUFFER_BIT);
+                }
+            }
+
+            self.passes[pass_index].apply(input, scene, (width, height));
+            render_target.draw_quad();
+
+            if !is_last {
+                target.unbind();
+                input = target.texture.get_texture_handle();
+            }
+        }
+
+        let [r, g, b, a] = clear_color;
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::ClearColor(r, g, b, a);
+        }
+
+        BlendMode::default().apply();
+
+        Ok(())
+    }
+
+    fn resize_targets(&mut self, width: u32, height: u32) -> Result<(), failure::Error> {
+        if self.targets.is_empty() {
+            self.targets = vec![
+                FrameBuffer::new(width, height)?,
+                FrameBuffer::new(width, height)?,
+            ];
+        } else if self.size != (width, height) {
+            for target in &self.targets {
+                target.set_size(width, height);
+            }
+        }
+
+        self.size = (width, height);
+
+        Ok(())
+    }
+}
+
+fn apply_effect_settings<E: PostEffect>(effect: &E, pass: &mut PostProcessPass) {
+    for (name, value) in effect.uniforms() {
+        pass.set_uniform(name, value);
+    }
+
+    for (name, texture_handle) in effect.textures() {
+        pass.set_texture(name, texture_handle);
+    }
+}
+
+fn is_effect_pass(pass_name: &str, effect_name: &str) -> bool {
+    match pass_name.strip_prefix(effect_name) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_match_effect_passes() {
+        assert!(is_effect_pass("bloom", "bloom"));
+        assert!(is_effect_pass("bloom/blur_h", "bloom"));
+        assert!(!is_effect_pass("bloomy", "bloom"));
+        assert!(!is_effect_pass("vignette", "bloom"));
+        assert!(!is_effect_pass("bloom", "bloom/blur_h"));
+    }
+}
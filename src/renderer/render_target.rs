@@ -71,16 +71,21 @@ impl RenderTarget {
     }
 
     pub fn update_fbo_size(&mut self, width: u32, height: u32) {
-        self.frame_buffer.texture.set_size(width, height);
+        self.frame_buffer.set_size(width, height);
     }
 
-    pub fn render(&mut self) {
+    pub fn render(&self) {
         self.program.set_used();
         self.frame_buffer.texture.bind_to_unit(0);
         let uniform_texture = self.program.get_uniform_location("RenderTexture")
             .expect("RenderTexture Uniform Not Found");
         self.program.set_uniform_1i(uniform_texture, 0);
 
+        self.draw_quad();
+    }
+
+    /// Draws the full screen quad with whatever program and textures are currently bound
+    pub fn draw_quad(&self) {
         self.vao.bind();
 
         unsafe {
@@ -95,22 +100,24 @@ impl RenderTarget {
     }
 }
 
-fn default_program() -> Result<helpers::Program, failure::Error> {
-    let vert_src = r#"
-        #version 330 core
+pub const QUAD_VERTEX_SOURCE: &str = r#"
+    #version 330 core
 
-        layout (location = 0) in vec2 Position;
-        layout (location = 1) in vec2 UV;
+    layout (location = 0) in vec2 Position;
+    layout (location = 1) in vec2 UV;
 
-        out VS_OUTPUT {
-            vec2 UV;
-        } OUT;
+    out VS_OUTPUT {
+        vec2 UV;
+    } OUT;
 
-        void main() {
-            gl_Position = vec4(Position.x, Position.y, 0.0, 1.0);
-            OUT.UV = UV;
-        }
-    "#;
+    void main() {
+        gl_Position = vec4(Position.x, Position.y, 0.0, 1.0);
+        OUT.UV = UV;
+    }
+"#;
+
+fn default_program() -> Result<helpers::Program, failure::Error> {
+    let vert_src = QUAD_VERTEX_SOURCE;
     let frag_src = r#"
         #version 330 core
 
@@ -0,0 +1,56 @@
+use crate::renderer::backend::{RenderBackend};
+use crate::textures::texture::{Texture};
+
+/// Offscreen target the batch renderer can draw into with `Renderer2D::render_to_texture`,
+/// its texture can then be used as the texture of a `Sprite` or any `Renderable2D`.
+/// Created through the `RenderBackend` of the renderer drawing into it, which owns the texture.
+/// Dropping it doesn't free anything, it has to be given back with `delete`
+#[must_use = "a RenderTexture has to be freed with `delete`"]
+#[derive(Debug)]
+pub struct RenderTexture {
+    texture_handle: u32,
+    width: u32,
+    height: u32,
+}
+
+impl RenderTexture {
+    pub fn new(backend: &mut dyn RenderBackend, width: u32, height: u32) -> Result<RenderTexture, failure::Error> {
+        Ok(RenderTexture {
+            texture_handle: backend.create_render_target(width, height)?,
+            width,
+            height,
+        })
+    }
+
+    pub fn get_dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn get_texture_handle(&self) -> u32 {
+        self.texture_handle
+    }
+
+    /// A `Texture` sharing this target's texture, it does not own it
+    /// and has to be recreated if the target is resized
+    pub fn as_texture(&self) -> Texture {
+        Texture::from_handle(self.texture_handle, self.width, self.height)
+    }
+
+    /// Replaces the target with a transparent one of the new size, which has a new texture handle
+    pub fn resize(&mut self, backend: &mut dyn RenderBackend, width: u32, height: u32) -> Result<(), failure::Error> {
+        let texture_handle = backend.create_render_target(width, height)?;
+
+        backend.delete_texture(self.texture_handle);
+
+        self.texture_handle = texture_handle;
+        self.width = width;
+        self.height = height;
+
+        Ok(())
+    }
+
+    /// Frees the target's framebuffer and texture, `backend` has to be the one it was created with
+    pub fn delete(self, backend: &mut dyn RenderBackend) {
+        backend.delete_texture(self.texture_handle);
+    }
+}
@@ -18,6 +18,137 @@ pub trait RenderVertex {
     }
 }
 
+/// Vertex for shapes that only need a position, uv and colour, drawn with the renderer's white texture
+/// when their `Renderable2D::texture` is 0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BasicVertex {
+    pub pos: (f32, f32, f32),
+    pub uv: (f32, f32),
+    pub color: (f32, f32, f32, f32),
+}
+
+impl RenderVertex for BasicVertex {
+    fn position(&self) -> data::f32_f32_f32 {
+        self.pos.into()
+    }
+    fn uv(&self) -> data::f32_f32 {
+        self.uv.into()
+    }
+    fn color(&self) -> data::f32_f32_f32_f32 {
+        self.color.into()
+    }
+    fn texture_scale(&self) -> data::f32_f32_f32 {
+        (1.0, 1.0, 1.0).into()
+    }
+}
+
+/// Plain vertex data as `VertexSink` keeps it, read once from a `RenderVertex`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vertex2D {
+    pub pos: (f32, f32, f32),
+    pub uv: (f32, f32),
+    pub color: (f32, f32, f32, f32),
+    pub texture_translate: (f32, f32, f32),
+    pub texture_scale: (f32, f32, f32),
+}
+
+impl Vertex2D {
+    pub fn from_render_vertex<V: RenderVertex + ?Sized>(vertex: &V) -> Vertex2D {
+        let (pos, uv, color) = (vertex.position(), vertex.uv(), vertex.color());
+        let (translate, scale) = (vertex.texture_translate(), vertex.texture_scale());
+
+        Vertex2D {
+            pos: (pos.d0, pos.d1, pos.d2),
+            uv: (uv.d0, uv.d1),
+            color: (color.d0, color.d1, color.d2, color.d3),
+            texture_translate: (translate.d0, translate.d1, translate.d2),
+            texture_scale: (scale.d0, scale.d1, scale.d2),
+        }
+    }
+}
+
+impl RenderVertex for Vertex2D {
+    fn position(&self) -> data::f32_f32_f32 {
+        self.pos.into()
+    }
+    fn uv(&self) -> data::f32_f32 {
+        self.uv.into()
+    }
+    fn color(&self) -> data::f32_f32_f32_f32 {
+        self.color.into()
+    }
+    fn texture_translate(&self) -> data::f32_f32_f32 {
+        self.texture_translate.into()
+    }
+    fn texture_scale(&self) -> data::f32_f32_f32 {
+        self.texture_scale.into()
+    }
+}
+
+/// Where `Renderable2D::write_vertices` puts a renderable's vertices. The renderer keeps one and
+/// clears it for every submission, so after the first few frames submitting doesn't allocate
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VertexSink {
+    vertices: Vec<Vertex2D>,
+    indices: Vec<u32>,
+}
+
+impl VertexSink {
+    pub fn new() -> VertexSink {
+        VertexSink::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    pub fn push<V: RenderVertex + ?Sized>(&mut self, vertex: &V) {
+        self.vertices.push(Vertex2D::from_render_vertex(vertex));
+    }
+
+    pub fn extend<V: RenderVertex>(&mut self, vertices: &[V]) {
+        self.vertices.extend(vertices.iter().map(Vertex2D::from_render_vertex));
+    }
+
+    /// Triangles as indices into the vertices written since the sink was cleared. Without any
+    /// indices the vertices are quads of 4 corners (top left, top right, bottom left, bottom right)
+    pub fn extend_indices(&mut self, indices: &[u32]) {
+        self.indices.extend_from_slice(indices);
+    }
+
+    pub fn vertices(&self) -> &[Vertex2D] {
+        &self.vertices
+    }
+
+    /// `None` when the vertices are quads
+    pub fn indices(&self) -> Option<&[u32]> {
+        if self.indices.is_empty() {
+            None
+        } else {
+            Some(&self.indices)
+        }
+    }
+}
+
+/// A quad drawn by instanced rendering: the unit square (0, 0) to (1, 1) moved into place by `transform`,
+/// whose rows take a corner (x, y, 1) to its world x and y. `uv_rect` is (u, v) at (0, 0) and (u2, v2) at (1, 1)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QuadInstance {
+    pub transform: [(f32, f32, f32); 2],
+    pub uv_rect: (f32, f32, f32, f32),
+    pub color: (f32, f32, f32, f32),
+}
+
+impl QuadInstance {
+    /// World position of a corner of the unit square
+    pub fn corner(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [(ax, bx, cx), (ay, by, cy)] = self.transform;
+
+        (ax * x + bx * y + cx, ay * x + by * y + cy)
+    }
+}
+
 impl std::fmt::Debug for dyn RenderVertex {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f,
@@ -38,7 +169,79 @@ pub trait Renderable2D {
         0
     }
 
+    /// Writes the vertices, and the indices of triangles if it isn't made of quads, into `out`.
+    /// Renderables written against `vertices` and `indices` keep working through this default
+    #[allow(deprecated)]
+    fn write_vertices(&self, out: &mut VertexSink) {
+        for vertex in self.vertices() {
+            out.push(vertex.as_ref());
+        }
+
+        if let Some(indices) = self.indices() {
+            out.extend_indices(&indices);
+        }
+    }
+
+    /// The old way to give the renderer vertices, a heap allocation per vertex. Only read by the
+    /// default `write_vertices`, so renderables that implement `write_vertices` return nothing here
+    #[deprecated(note = "implement `write_vertices` instead, renderables that do return nothing here")]
     fn vertices(&self) -> Vec<Box<dyn RenderVertex>> {
         Vec::new()
     }
+
+    /// Triangles as indices into `vertices`, `None` means the vertices are quads of 4 corners
+    /// (top left, top right, bottom left, bottom right) like `Sprite`. Goes with `vertices`
+    #[deprecated(note = "implement `write_vertices` instead, renderables that do return `None` here")]
+    fn indices(&self) -> Option<Vec<u32>> {
+        None
+    }
+
+    /// The renderable as a single `QuadInstance`, drawn instanced without writing its vertices
+    /// when `Renderer2D::set_instancing` is on. `None` always goes through `write_vertices`
+    fn quad_instance(&self) -> Option<QuadInstance> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct OldTriangle;
+
+    impl Renderable2D for OldTriangle {
+        fn vertices(&self) -> Vec<Box<dyn RenderVertex>> {
+            (0..3)
+                .map(|i| Box::new(BasicVertex { pos: (i as f32, 0.0, 0.0), uv: (0.0, 0.0), color: (1.0, 1.0, 1.0, 1.0) }) as Box<dyn RenderVertex>)
+                .collect()
+        }
+
+        fn indices(&self) -> Option<Vec<u32>> {
+            Some(vec![0, 1, 2])
+        }
+    }
+
+    #[test]
+    fn old_renderables_write_through_the_default() {
+        let mut sink = VertexSink::new();
+
+        OldTriangle.write_vertices(&mut sink);
+
+        assert_eq!(sink.vertices().len(), 3);
+        assert_eq!(sink.vertices()[2].pos, (2.0, 0.0, 0.0));
+        assert_eq!(sink.vertices()[2].texture_scale, (1.0, 1.0, 1.0));
+        assert_eq!(sink.indices(), Some(&[0, 1, 2][..]));
+    }
+
+    #[test]
+    fn sinks_without_indices_are_quads() {
+        let mut sink = VertexSink::new();
+        let vertex = BasicVertex { pos: (0.0, 0.0, 0.0), uv: (0.0, 0.0), color: (1.0, 1.0, 1.0, 1.0) };
+
+        sink.extend(&[vertex; 4]);
+        assert_eq!(sink.indices(), None);
+
+        sink.clear();
+        assert!(sink.vertices().is_empty());
+    }
 }
@@ -0,0 +1,417 @@
+use std::f32::consts::PI;
+
+use super::Renderer2D;
+use super::renderable::{Renderable2D, BasicVertex, VertexSink};
+
+// how far a miter join can stick out, as a multiple of half the thickness, before it's cut short
+const MITER_LIMIT: f32 = 2.0;
+
+/// Triangles tessellated on the cpu, drawn with the renderer's white texture so they go in the
+/// same batch as sprites. Submit it like any other `Renderable2D` or use the `Renderer2D::draw_*`
+/// functions, positions are in world coordinates with `pos` being the top left like `Rectangle`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapeMesh {
+    pub vertices: Vec<BasicVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl ShapeMesh {
+    pub fn new() -> ShapeMesh {
+        ShapeMesh::default()
+    }
+
+    /// Adds another shape so both can be kept and submitted as one
+    pub fn append(&mut self, other: &ShapeMesh) {
+        let offset = self.vertices.len() as u32;
+
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices.extend(other.indices.iter().map(|index| index + offset));
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn add_vertex(&mut self, (x, y): (f32, f32), color: (f32, f32, f32, f32)) -> u32 {
+        self.vertices.push(BasicVertex { pos: (x, y, 0.0), uv: (0.0, 0.0), color });
+
+        self.vertices.len() as u32 - 1
+    }
+}
+
+impl Renderable2D for ShapeMesh {
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&self.vertices);
+        out.extend_indices(&self.indices);
+    }
+}
+
+/// Enough segments for a curve of `radius` to look round, between 8 and 128
+pub fn segments_for_radius(radius: f32) -> u32 {
+    ((radius.abs().sqrt() * 4.0).ceil() as u32).clamp(8, 128)
+}
+
+pub fn line(from: (f32, f32), to: (f32, f32), thickness: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    polyline(&[from, to], thickness, color, false)
+}
+
+/// A line through every point, with mitered joins. `closed` joins the last point back to the first
+pub fn polyline(points: &[(f32, f32)], thickness: f32, color: (f32, f32, f32, f32), closed: bool) -> ShapeMesh {
+    let mut mesh = ShapeMesh::new();
+    let mut points: Vec<(f32, f32)> = points.to_vec();
+
+    points.dedup();
+
+    if closed && points.len() > 2 && points.first() == points.last() {
+        points.pop();
+    }
+
+    if points.len() < 2 {
+        return mesh;
+    }
+
+    let half = thickness / 2.0;
+    let count = points.len();
+
+    for i in 0..count {
+        let previous = match (i, closed) {
+            (0, false) => None,
+            (0, true) => Some(points[count - 1]),
+            _ => Some(points[i - 1]),
+        };
+        let next = match (i + 1 == count, closed) {
+            (true, false) => None,
+            (true, true) => Some(points[0]),
+            _ => Some(points[i + 1]),
+        };
+        let (ox, oy) = join_offset(previous, points[i], next, half);
+        let (x, y) = points[i];
+
+        mesh.add_vertex((x + ox, y + oy), color);
+        mesh.add_vertex((x - ox, y - oy), color);
+    }
+
+    let segments = if closed { count } else { count - 1 };
+
+    for i in 0..segments {
+        let a = (i * 2) as u32;
+        let b = (((i + 1) % count) * 2) as u32;
+
+        mesh.indices.extend_from_slice(&[a, a + 1, b, b, a + 1, b + 1]);
+    }
+
+    mesh
+}
+
+pub fn circle(center: (f32, f32), radius: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    let mut mesh = ShapeMesh::new();
+    let center_index = mesh.add_vertex(center, color);
+    let segments = segments_for_radius(radius);
+
+    for point in circle_points(center, radius, segments) {
+        mesh.add_vertex(point, color);
+    }
+
+    for i in 0..segments {
+        mesh.indices.extend_from_slice(&[center_index, 1 + i, 1 + (i + 1) % segments]);
+    }
+
+    mesh
+}
+
+pub fn circle_outline(center: (f32, f32), radius: f32, thickness: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    polyline(&circle_points(center, radius, segments_for_radius(radius)), thickness, color, true)
+}
+
+/// Part of a circle outline going from `start_angle` to `end_angle` in radians, 0 points along +x
+/// and angles increase towards +y
+pub fn arc(
+    center: (f32, f32),
+    radius: f32,
+    start_angle: f32,
+    end_angle: f32,
+    thickness: f32,
+    color: (f32, f32, f32, f32),
+) -> ShapeMesh {
+    let sweep = (end_angle - start_angle).abs() / (2.0 * PI);
+    let segments = ((segments_for_radius(radius) as f32 * sweep).ceil() as u32).max(2);
+
+    polyline(&arc_points(center, radius, start_angle, end_angle, segments), thickness, color, false)
+}
+
+/// Filled as a fan from the first point, so the points have to go around a convex shape
+pub fn convex_polygon(points: &[(f32, f32)], color: (f32, f32, f32, f32)) -> ShapeMesh {
+    let mut mesh = ShapeMesh::new();
+
+    if points.len() < 3 {
+        return mesh;
+    }
+
+    for &point in points {
+        mesh.add_vertex(point, color);
+    }
+
+    for i in 1..(points.len() as u32 - 1) {
+        mesh.indices.extend_from_slice(&[0, i, i + 1]);
+    }
+
+    mesh
+}
+
+pub fn polygon_outline(points: &[(f32, f32)], thickness: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    polyline(points, thickness, color, true)
+}
+
+pub fn rect_outline(pos: (f32, f32), size: (f32, f32), thickness: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    polygon_outline(&rounded_rect_points(pos, size, 0.0), thickness, color)
+}
+
+/// `radius` is clamped to half of the smaller side
+pub fn rounded_rect(pos: (f32, f32), size: (f32, f32), radius: f32, color: (f32, f32, f32, f32)) -> ShapeMesh {
+    convex_polygon(&rounded_rect_points(pos, size, radius), color)
+}
+
+pub fn rounded_rect_outline(
+    pos: (f32, f32),
+    size: (f32, f32),
+    radius: f32,
+    thickness: f32,
+    color: (f32, f32, f32, f32),
+) -> ShapeMesh {
+    polygon_outline(&rounded_rect_points(pos, size, radius), thickness, color)
+}
+
+fn circle_points(center: (f32, f32), radius: f32, segments: u32) -> Vec<(f32, f32)> {
+    let mut points = arc_points(center, radius, 0.0, 2.0 * PI, segments);
+
+    // the last point is the first one again
+    points.pop();
+
+    points
+}
+
+// `segments + 1` points, both ends included
+fn arc_points(center: (f32, f32), radius: f32, start_angle: f32, end_angle: f32, segments: u32) -> Vec<(f32, f32)> {
+    let (cx, cy) = center;
+    let step = (end_angle - start_angle) / segments as f32;
+
+    (0..=segments)
+        .map(|i| {
+            let angle = start_angle + step * i as f32;
+
+            (cx + radius * angle.cos(), cy + radius * angle.sin())
+        })
+        .collect()
+}
+
+// corners go clockwise on screen from the top left, a radius of 0 is just the 4 corners
+fn rounded_rect_points(pos: (f32, f32), size: (f32, f32), radius: f32) -> Vec<(f32, f32)> {
+    let (x, y) = pos;
+    let (width, height) = size;
+    let radius = radius.min(width.min(height) / 2.0);
+
+    if radius <= 0.0 {
+        return vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)];
+    }
+
+    let segments = (segments_for_radius(radius) / 4).max(2);
+    let corners = [
+        ((x + radius, y + radius), PI),
+        ((x + width - radius, y + radius), 1.5 * PI),
+        ((x + width - radius, y + height - radius), 0.0),
+        ((x + radius, y + height - radius), 0.5 * PI),
+    ];
+
+    corners.iter()
+        .flat_map(|&(center, start_angle)| arc_points(center, radius, start_angle, start_angle + 0.5 * PI, segments))
+        .collect()
+}
+
+fn normal((x, y): (f32, f32), (x2, y2): (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (x2 - x, y2 - y);
+    let length = (dx * dx + dy * dy).sqrt();
+
+    (-dy / length, dx / length)
+}
+
+// how far the two sides of the line are from `point`, mitered between the segments on either side
+fn join_offset(previous: Option<(f32, f32)>, point: (f32, f32), next: Option<(f32, f32)>, half: f32) -> (f32, f32) {
+    let (nx, ny) = match (previous, next) {
+        (Some(previous), Some(next)) => {
+            let (ax, ay) = normal(previous, point);
+            let (bx, by) = normal(point, next);
+            let (mx, my) = (ax + bx, ay + by);
+            let length = (mx * mx + my * my).sqrt();
+
+            // the line turns back on itself, there is no sensible miter
+            if length < 1e-4 {
+                return (ax * half, ay * half);
+            }
+
+            let (mx, my) = (mx / length, my / length);
+            let scale = (1.0 / (mx * bx + my * by)).min(MITER_LIMIT);
+
+            return (mx * half * scale, my * half * scale);
+        },
+        (Some(previous), None) => normal(previous, point),
+        (None, Some(next)) => normal(point, next),
+        (None, None) => (0.0, 0.0),
+    };
+
+    (nx * half, ny * half)
+}
+
+/// Immediate mode shape drawing, everything goes to the default layer like `submit`
+impl Renderer2D {
+    pub fn draw_line(&mut self, from: (f32, f32), to: (f32, f32), thickness: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&line(from, to, thickness, color));
+    }
+
+    pub fn draw_polyline(&mut self, points: &[(f32, f32)], thickness: f32, color: (f32, f32, f32, f32), closed: bool) {
+        self.submit(&polyline(points, thickness, color, closed));
+    }
+
+    pub fn draw_circle(&mut self, center: (f32, f32), radius: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&circle(center, radius, color));
+    }
+
+    pub fn draw_circle_outline(&mut self, center: (f32, f32), radius: f32, thickness: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&circle_outline(center, radius, thickness, color));
+    }
+
+    pub fn draw_arc(
+        &mut self,
+        center: (f32, f32),
+        radius: f32,
+        (start_angle, end_angle): (f32, f32),
+        thickness: f32,
+        color: (f32, f32, f32, f32),
+    ) {
+        self.submit(&arc(center, radius, start_angle, end_angle, thickness, color));
+    }
+
+    pub fn draw_polygon(&mut self, points: &[(f32, f32)], color: (f32, f32, f32, f32)) {
+        self.submit(&convex_polygon(points, color));
+    }
+
+    pub fn draw_polygon_outline(&mut self, points: &[(f32, f32)], thickness: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&polygon_outline(points, thickness, color));
+    }
+
+    pub fn draw_rect_outline(&mut self, pos: (f32, f32), size: (f32, f32), thickness: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&rect_outline(pos, size, thickness, color));
+    }
+
+    pub fn draw_rounded_rect(&mut self, pos: (f32, f32), size: (f32, f32), radius: f32, color: (f32, f32, f32, f32)) {
+        self.submit(&rounded_rect(pos, size, radius, color));
+    }
+
+    pub fn draw_rounded_rect_outline(
+        &mut self,
+        pos: (f32, f32),
+        size: (f32, f32),
+        radius: f32,
+        thickness: f32,
+        color: (f32, f32, f32, f32),
+    ) {
+        self.submit(&rounded_rect_outline(pos, size, radius, thickness, color));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+
+    fn positions(mesh: &ShapeMesh) -> Vec<(f32, f32)> {
+        mesh.vertices.iter().map(|vertex| (vertex.pos.0, vertex.pos.1)).collect()
+    }
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+    }
+
+    #[test]
+    fn line_is_a_quad_around_the_segment() {
+        let mesh = line((0.0, 0.0), (10.0, 0.0), 2.0, WHITE);
+
+        assert_eq!(positions(&mesh), vec![(0.0, 1.0), (0.0, -1.0), (10.0, 1.0), (10.0, -1.0)]);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 2, 1, 3]);
+    }
+
+    #[test]
+    fn polyline_miters_corners() {
+        let mesh = polyline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], 2.0, WHITE, false);
+        let corner = positions(&mesh);
+
+        assert!(close(corner[2], (9.0, 1.0)));
+        assert!(close(corner[3], (11.0, -1.0)));
+        assert_eq!(mesh.triangle_count(), 4);
+    }
+
+    #[test]
+    fn closed_polyline_joins_last_point_to_first() {
+        let mesh = polygon_outline(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)], 2.0, WHITE);
+
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.triangle_count(), 8);
+        assert_eq!(&mesh.indices[18..], &[6, 7, 0, 0, 7, 1]);
+    }
+
+    #[test]
+    fn degenerate_lines_are_empty() {
+        assert_eq!(line((1.0, 1.0), (1.0, 1.0), 2.0, WHITE), ShapeMesh::new());
+        assert_eq!(convex_polygon(&[(0.0, 0.0), (1.0, 0.0)], WHITE), ShapeMesh::new());
+    }
+
+    #[test]
+    fn circle_is_a_fan_around_its_center() {
+        let mesh = circle((5.0, 5.0), 16.0, WHITE);
+        let segments = segments_for_radius(16.0) as usize;
+
+        assert_eq!(mesh.vertices.len(), segments + 1);
+        assert_eq!(mesh.triangle_count(), segments);
+        assert!(positions(&mesh)[1..].iter().all(|&(x, y)| ((x - 5.0).hypot(y - 5.0) - 16.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn segments_are_clamped() {
+        assert_eq!(segments_for_radius(0.5), 8);
+        assert_eq!(segments_for_radius(16.0), 16);
+        assert_eq!(segments_for_radius(100_000.0), 128);
+    }
+
+    #[test]
+    fn arc_starts_and_ends_at_its_angles() {
+        let points = arc_points((0.0, 0.0), 10.0, 0.0, 0.5 * PI, 4);
+
+        assert_eq!(points.len(), 5);
+        assert!(close(points[0], (10.0, 0.0)));
+        assert!(close(points[4], (0.0, 10.0)));
+    }
+
+    #[test]
+    fn convex_polygon_is_a_fan() {
+        let mesh = convex_polygon(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (-1.0, 0.5)], WHITE);
+
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn rounded_rect_radius_is_clamped_to_the_rect() {
+        let points = rounded_rect_points((0.0, 0.0), (20.0, 10.0), 50.0);
+
+        assert!(points.iter().all(|&(x, y)| (-1e-4..=20.0001).contains(&x) && (-1e-4..=10.0001).contains(&y)));
+        assert!(close(points[0], (0.0, 5.0)));
+        assert_eq!(rounded_rect_points((0.0, 0.0), (20.0, 10.0), 0.0).len(), 4);
+    }
+
+    #[test]
+    fn append_offsets_indices() {
+        let mut mesh = line((0.0, 0.0), (1.0, 0.0), 1.0, WHITE);
+        mesh.append(&line((0.0, 1.0), (1.0, 1.0), 1.0, WHITE));
+
+        assert_eq!(&mesh.indices[6..], &[4, 5, 6, 6, 5, 7]);
+    }
+}
@@ -0,0 +1,17 @@
+/// What the batch renderer drew since `begin_batch`. The extra passes of the lighting aren't counted
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: usize,
+    /// Quads submitted, shapes made of triangles count one quad per two triangles
+    pub quads: usize,
+    pub triangles: usize,
+    pub texture_binds: usize,
+    pub flushes: usize,
+    pub vertex_bytes: usize,
+}
+
+impl RenderStats {
+    pub fn reset(&mut self) {
+        *self = RenderStats::default();
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="TextureArray is out of layers, more than {} textures were used in one pass", max_layers)]
+    OutOfLayers { max_layers: u32 },
+}
+
+/// Where a texture ended up in the array, `uv_scale` maps the texture's uvs onto the part of the
+/// layer it was copied into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArrayLayer {
+    pub index: u32,
+    pub uv_scale: (f32, f32),
+}
+
+#[derive(Debug)]
+struct CachedLayer {
+    texture_handle: u32,
+    layer: ArrayLayer,
+    last_used: u64,
+}
+
+// keeps track of which texture is in which layer, textures stay in their layer between frames
+// and layers are only reused for textures that haven't been used this pass. Layers of invalidated
+// textures are free, but only to the same texture until the pass is over
+#[derive(Debug)]
+struct LayerAllocator {
+    max_layers: u32,
+    layers: Vec<CachedLayer>,
+    by_handle: HashMap<u32, usize>,
+    free: Vec<usize>,
+    pass: u64,
+}
+
+impl LayerAllocator {
+    fn new(max_layers: u32) -> LayerAllocator {
+        LayerAllocator {
+            max_layers,
+            layers: Vec::new(),
+            by_handle: HashMap::new(),
+            free: Vec::new(),
+            pass: 0,
+        }
+    }
+
+    fn next_pass(&mut self) {
+        self.pass += 1;
+    }
+
+    fn get(&mut self, texture_handle: u32) -> Option<ArrayLayer> {
+        let pass = self.pass;
+        let cached = &mut self.layers[*self.by_handle.get(&texture_handle)?];
+        cached.last_used = pass;
+
+        Some(cached.layer)
+    }
+
+    fn allocate(&mut self, texture_handle: u32, uv_scale: (f32, f32)) -> Result<ArrayLayer, Error> {
+        let pass = self.pass;
+
+        if (self.layers.len() as u32) < self.max_layers {
+            let layer = ArrayLayer { index: self.layers.len() as u32, uv_scale };
+
+            self.by_handle.insert(texture_handle, self.layers.len());
+            self.layers.push(CachedLayer { texture_handle, layer, last_used: pass });
+
+            return Ok(layer);
+        }
+
+        let free = &self.free;
+        let slot = match free.iter().find(|&&slot| self.layers[slot].texture_handle == texture_handle) {
+            Some(&slot) => slot,
+            None => self.layers.iter()
+                .enumerate()
+                .filter(|(_, cached)| cached.last_used < pass)
+                .min_by_key(|(slot, cached)| (!free.contains(slot), cached.last_used))
+                .map(|(slot, _)| slot)
+                .ok_or(Error::OutOfLayers { max_layers: self.max_layers })?,
+        };
+
+        self.free.retain(|&free_slot| free_slot != slot);
+
+        let cached = &mut self.layers[slot];
+        let layer = ArrayLayer { index: cached.layer.index, uv_scale };
+
+        if self.by_handle.get(&cached.texture_handle) == Some(&slot) {
+            self.by_handle.remove(&cached.texture_handle);
+        }
+        self.by_handle.insert(texture_handle, slot);
+        *cached = CachedLayer { texture_handle, layer, last_used: pass };
+
+        Ok(layer)
+    }
+
+    fn invalidate(&mut self, texture_handle: u32) {
+        if let Some(slot) = self.by_handle.remove(&texture_handle) {
+            self.free.push(slot);
+        }
+    }
+}
+
+// textures that fit are copied 1:1 into the corner of a layer, bigger ones are scaled down to fill it
+fn copy_region(texture_size: (u32, u32), layer_size: (u32, u32)) -> ((u32, u32), (f32, f32)) {
+    let (width, height) = texture_size;
+    let (layer_width, layer_height) = layer_size;
+
+    if width <= layer_width && height <= layer_height {
+        let uv_scale = (width as f32 / layer_width as f32, height as f32 / layer_height as f32);
+
+        return ((width, height), uv_scale);
+    }
+
+    ((layer_width, layer_height), (1.0, 1.0))
+}
+
+/// A `GL_TEXTURE_2D_ARRAY` the batch renderer copies sprite textures into, so a single binding can
+/// serve any number of textures. Layers are `layer_width` x `layer_height`, textures smaller than
+/// that are padded and bigger ones are scaled down. Textures are given their layer when submitted
+/// and copied into it with `copy_layers` just before a batch sampling them is drawn
+#[derive(Debug)]
+pub struct TextureArray {
+    texture_handle: gl::types::GLuint,
+    read_fbo: gl::types::GLuint,
+    draw_fbo: gl::types::GLuint,
+    layer_width: u32,
+    layer_height: u32,
+    allocator: LayerAllocator,
+    // the texture each layer holds right now, layers can change hands between batches of a frame
+    resident: Vec<Option<u32>>,
+    texture_sizes: HashMap<u32, (u32, u32)>,
+}
+
+impl TextureArray {
+    pub fn new(layer_width: u32, layer_height: u32, max_layers: u32) -> TextureArray {
+        let mut texture_handle: gl::types::GLuint = 0;
+        let mut fbos: [gl::types::GLuint; 2] = [0; 2];
+
+        unsafe {
+            gl::GenTextures(1, &mut texture_handle);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture_handle);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                gl::RGBA as i32,
+                layer_width as i32,
+                layer_height as i32,
+                max_layers as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null()
+            );
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+            gl::GenFramebuffers(2, fbos.as_mut_ptr());
+        }
+
+        TextureArray {
+            texture_handle,
+            read_fbo: fbos[0],
+            draw_fbo: fbos[1],
+            layer_width,
+            layer_height,
+            allocator: LayerAllocator::new(max_layers),
+            resident: vec![None; max_layers as usize],
+            texture_sizes: HashMap::new(),
+        }
+    }
+
+    pub fn get_texture_handle(&self) -> gl::types::GLuint {
+        self.texture_handle
+    }
+
+    pub fn get_layer_dimensions(&self) -> (u32, u32) {
+        (self.layer_width, self.layer_height)
+    }
+
+    /// Layers used before this can be given to other textures
+    pub fn next_pass(&mut self) {
+        self.allocator.next_pass();
+    }
+
+    /// The layer `texture_handle` is given for this pass, it's copied in by `copy_layers`.
+    /// Fails when every layer is used by a texture of this pass
+    pub fn layer_for(&mut self, texture_handle: u32) -> Result<ArrayLayer, Error> {
+        if let Some(layer) = self.allocator.get(texture_handle) {
+            return Ok(layer);
+        }
+
+        let texture_size = texture_dimensions(texture_handle);
+        let (_, uv_scale) = copy_region(texture_size, (self.layer_width, self.layer_height));
+        let layer = self.allocator.allocate(texture_handle, uv_scale)?;
+
+        self.texture_sizes.insert(texture_handle, texture_size);
+
+        Ok(layer)
+    }
+
+    /// Copies each texture handle into its layer index, unless the layer already holds it
+    pub fn copy_layers(&mut self, textures: &[(u32, u32)]) {
+        for &(texture_handle, layer) in textures {
+            if self.resident[layer as usize] == Some(texture_handle) {
+                continue;
+            }
+
+            let texture_size = *self.texture_sizes.entry(texture_handle)
+                .or_insert_with(|| texture_dimensions(texture_handle));
+
+            self.copy_into_layer(texture_handle, texture_size, layer);
+            self.resident[layer as usize] = Some(texture_handle);
+        }
+    }
+
+    /// Copies `texture_handle` again the next time it's used, for textures that are drawn to (RenderTexture)
+    /// or deleted and their handle reused
+    pub fn invalidate(&mut self, texture_handle: u32) {
+        self.allocator.invalidate(texture_handle);
+        self.texture_sizes.remove(&texture_handle);
+
+        for resident in self.resident.iter_mut().filter(|resident| **resident == Some(texture_handle)) {
+            *resident = None;
+        }
+    }
+
+    fn copy_into_layer(&self, texture_handle: u32, texture_size: (u32, u32), layer: u32) {
+        let (width, height) = texture_size;
+        let ((copy_width, copy_height), _) = copy_region(texture_size, (self.layer_width, self.layer_height));
+        let mut previous_read: gl::types::GLint = 0;
+        let mut previous_draw: gl::types::GLint = 0;
+
+        unsafe {
+            // copies are made between batches, whose clip rects would cut them off
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous_read);
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_draw);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
+            gl::FramebufferTexture2D(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture_handle, 0);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.draw_fbo);
+            gl::FramebufferTextureLayer(gl::DRAW_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, self.texture_handle, 0, layer as i32);
+            gl::BlitFramebuffer(
+                0, 0, width as i32, height as i32,
+                0, 0, copy_width as i32, copy_height as i32,
+                gl::COLOR_BUFFER_BIT,
+                gl::LINEAR
+            );
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous_read as u32);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, previous_draw as u32);
+        }
+    }
+}
+
+impl Drop for TextureArray {
+    fn drop(&mut self) {
+        let fbos = [self.read_fbo, self.draw_fbo];
+
+        unsafe {
+            gl::DeleteFramebuffers(2, fbos.as_ptr());
+            gl::DeleteTextures(1, &self.texture_handle);
+        }
+    }
+}
+
+fn texture_dimensions(texture_handle: u32) -> (u32, u32) {
+    let mut width: gl::types::GLint = 0;
+    let mut height: gl::types::GLint = 0;
+
+    unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, texture_handle);
+        gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+        gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    (width as u32, height as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_textures_are_padded_into_layer() {
+        assert_eq!(copy_region((64, 32), (128, 128)), ((64, 32), (0.5, 0.25)));
+        assert_eq!(copy_region((128, 128), (128, 128)), ((128, 128), (1.0, 1.0)));
+    }
+
+    #[test]
+    fn large_textures_are_scaled_into_layer() {
+        assert_eq!(copy_region((256, 64), (128, 128)), ((128, 128), (1.0, 1.0)));
+    }
+
+    #[test]
+    fn allocator_reuses_layer_for_same_texture() {
+        let mut allocator = LayerAllocator::new(4);
+        let first = allocator.allocate(10, (1.0, 1.0)).unwrap();
+        let second = allocator.allocate(11, (0.5, 0.5)).unwrap();
+
+        assert_eq!(first.index, 0);
+        assert_eq!(second.index, 1);
+        assert_eq!(allocator.get(10), Some(first));
+        assert_eq!(allocator.get(12), None);
+    }
+
+    #[test]
+    fn allocator_is_full_within_a_pass() {
+        let mut allocator = LayerAllocator::new(2);
+        allocator.allocate(1, (1.0, 1.0)).unwrap();
+        allocator.allocate(2, (1.0, 1.0)).unwrap();
+
+        assert!(matches!(allocator.allocate(3, (1.0, 1.0)), Err(Error::OutOfLayers { max_layers: 2 })));
+    }
+
+    #[test]
+    fn allocator_evicts_textures_from_earlier_passes() {
+        let mut allocator = LayerAllocator::new(2);
+        allocator.allocate(1, (1.0, 1.0)).unwrap();
+        allocator.allocate(2, (1.0, 1.0)).unwrap();
+        allocator.next_pass();
+        allocator.get(2);
+
+        let layer = allocator.allocate(3, (1.0, 1.0)).unwrap();
+
+        assert_eq!(layer.index, 0);
+        assert_eq!(allocator.get(1), None);
+        assert_eq!(allocator.get(3), Some(layer));
+    }
+
+    #[test]
+    fn invalidated_layers_are_not_reused_within_a_pass() {
+        let mut allocator = LayerAllocator::new(1);
+        allocator.allocate(1, (1.0, 1.0)).unwrap();
+        allocator.invalidate(1);
+
+        assert!(matches!(allocator.allocate(2, (1.0, 1.0)), Err(Error::OutOfLayers { max_layers: 1 })));
+
+        allocator.next_pass();
+
+        assert_eq!(allocator.allocate(2, (1.0, 1.0)).unwrap().index, 0);
+        assert_eq!(allocator.get(1), None);
+    }
+
+    #[test]
+    fn invalidated_textures_are_copied_again() {
+        let mut allocator = LayerAllocator::new(1);
+        allocator.allocate(1, (1.0, 1.0)).unwrap();
+        allocator.invalidate(1);
+
+        assert_eq!(allocator.get(1), None);
+        assert_eq!(allocator.allocate(1, (1.0, 1.0)).unwrap().index, 0);
+    }
+}
@@ -2,7 +2,8 @@ use crate::helpers::{data};
 use crate::resources::*;
 use crate::textures::texture::{Texture};
 use crate::textures::transform::{TextureTransform};
-use crate::renderer::renderable::{Renderable2D, RenderVertex};
+use crate::image::{Direction};
+use crate::renderer::renderable::{Renderable2D, RenderVertex, QuadInstance, VertexSink};
 
 #[derive(VertexAttribPointers)]
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -61,11 +62,14 @@ impl Default for SpriteProps {
     }
 }
 
+/// Position, rotation and scale of a sprite. Rotation (about Z) and scale happen around
+/// `origin`, which is relative to the sprite's top left corner
 #[derive(PartialEq, Debug)]
 pub struct SpriteTransform {
     translation: glm::Mat4,
     scale: glm::Mat4,
     rotation: glm::Mat4,
+    origin: glm::Vec3,
     model: glm::Mat4
 }
 
@@ -78,6 +82,7 @@ impl Default for SpriteTransform {
             translation,
             scale,
             rotation,
+            origin: glm::vec3(0.0, 0.0, 0.0),
             model: translation,
         }
     }
@@ -109,7 +114,17 @@ impl SpriteTransform {
     }
 
     pub fn set_rotation(&mut self, rads: f32) {
-        self.rotation = glm::rotate(&glm::identity(), rads, &glm::vec3(0.0, 1.0, 0.0)); // what's normalized rotation?
+        self.rotation = glm::rotate(&glm::identity(), rads, &glm::vec3(0.0, 0.0, 1.0));
+        self.set_model();
+    }
+
+    pub fn get_origin(&self) -> (f32, f32) {
+        (self.origin.x, self.origin.y)
+    }
+
+    pub fn set_origin(&mut self, origin: (f32, f32)) {
+        let (ox, oy) = origin;
+        self.origin = glm::vec3(ox, oy, 0.0);
         self.set_model();
     }
 
@@ -118,13 +133,32 @@ impl SpriteTransform {
     }
 
     fn set_model(&mut self) {
-        self.model = self.translation * self.rotation * self.scale;
+        let to_origin = glm::translate(&glm::identity(), &-self.origin);
+        let from_origin = glm::translate(&glm::identity(), &self.origin);
+
+        self.model = self.translation * from_origin * self.rotation * self.scale * to_origin;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct SpriteOrientation {
+    horizontal: Direction,
+    vertical: Direction,
+}
+
+impl Default for SpriteOrientation {
+    fn default() -> SpriteOrientation {
+        SpriteOrientation {
+            horizontal: Direction::Normal,
+            vertical: Direction::Normal,
+        }
     }
 }
 
 #[derive(PartialEq, Debug)]
 pub struct Sprite {
     transform: SpriteTransform,
+    orientation: SpriteOrientation,
     vertices: Vec<SpriteVertex>,
     pub texture: Texture,
     texture_transform: TextureTransform,
@@ -137,13 +171,12 @@ impl Renderable2D for Sprite {
         self.texture.texture_handle
     }
 
-    fn vertices(&self) -> Vec<Box<dyn RenderVertex>> {
-        let mut v = Vec::new();
-        for sv in &self.vertices {
-            v.push(Box::new(*sv) as Box<dyn RenderVertex>);
-        }
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&self.vertices);
+    }
 
-        v
+    fn quad_instance(&self) -> Option<QuadInstance> {
+        Some(quad_instance(&self.texture, &self.props, &self.texture_transform, &self.transform, self.orientation))
     }
 }
 
@@ -158,9 +191,10 @@ impl Sprite {
         transform.set_translation((px, py, pz));
 
         Ok(Sprite {
-            vertices: update_vertices(&texture, &props, &texture_transform),
+            vertices: update_vertices(&texture, &props, &texture_transform, &transform, SpriteOrientation::default()),
             texture,
             transform,
+            orientation: SpriteOrientation::default(),
             texture_transform,
             image_path,
             props,
@@ -175,9 +209,10 @@ impl Sprite {
         transform.set_translation(props.pos);
 
         Ok(Sprite {
-            vertices: update_vertices(&texture, &props, &texture_transform),
+            vertices: update_vertices(&texture, &props, &texture_transform, &transform, SpriteOrientation::default()),
             texture: texture.clone(),//TODO: how to handle only wantin ga ref if using an existing texture vs creating a sprite that creates it's own texture???
             transform,
+            orientation: SpriteOrientation::default(),
             texture_transform,
             image_path: texture.image_path.to_string(), // TODO: need to probably use &strs
             props,
@@ -186,18 +221,85 @@ impl Sprite {
 
     pub fn transform(&mut self, transform: SpriteTransform) {
         self.transform = transform;
+        self.update();
+    }
+
+    pub fn get_transform(&self) -> &SpriteTransform {
+        &self.transform
+    }
+
+    pub fn get_position(&self) -> (f32, f32, f32) {
+        self.props.pos
+    }
+
+    pub fn set_position(&mut self, pos: (f32, f32, f32)) {
+        self.props.pos = pos;
+        self.transform.set_translation(pos);
+        self.update();
+    }
+
+    /// Rotation about Z in radians, around the origin set with `set_origin`
+    pub fn set_rotation(&mut self, rads: f32) {
+        self.transform.set_rotation(rads);
+        self.update();
+    }
+
+    pub fn set_scale(&mut self, scale: (f32, f32)) {
+        self.transform.set_scale(scale);
+        self.update();
+    }
+
+    /// Pivot for rotation and scale relative to the top left corner of the sprite, defaults to (0, 0)
+    pub fn set_origin(&mut self, origin: (f32, f32)) {
+        self.transform.set_origin(origin);
+        self.update();
+    }
+
+    pub fn set_origin_center(&mut self) {
+        let (width, height) = self.props.dim;
+
+        self.set_origin((width as f32 / 2.0, height as f32 / 2.0));
+    }
+
+    /// Mirrors the texture in place, the sprite's position doesn't change. `false` puts it back
+    pub fn set_flip_h(&mut self, flipped: bool) {
+        self.orientation.horizontal = direction(flipped);
+        self.update();
+    }
+
+    pub fn set_flip_v(&mut self, flipped: bool) {
+        self.orientation.vertical = direction(flipped);
+        self.update();
+    }
+
+    pub fn set_orientation(&mut self, h: Direction, v: Direction) {
+        self.orientation = SpriteOrientation {
+            horizontal: h,
+            vertical: v,
+        };
+        self.update();
     }
 
     pub fn set_texture_scale(&mut self, scale: (f32, f32)) {
         let (sx, sy) = scale;
         self.texture_transform.set_scale(sx, sy);
-        self.vertices = update_vertices(&self.texture, &self.props, &self.texture_transform);
+        self.update();
     }
 
     pub fn set_frame(&mut self, pos: (f32, f32)) {
         let (x, y) = pos;
         self.texture_transform.set_frame(x, y);
-        self.vertices = update_vertices(&self.texture, &self.props, &self.texture_transform);
+        self.update();
+    }
+
+    fn update(&mut self) {
+        self.vertices = update_vertices(
+            &self.texture,
+            &self.props,
+            &self.texture_transform,
+            &self.transform,
+            self.orientation
+        );
     }
 
     pub fn get_vertices(&self) -> &Vec<SpriteVertex> {
@@ -205,29 +307,104 @@ impl Sprite {
     }
 }
 
-fn update_vertices(texture: &Texture, props: &SpriteProps, texture_transform: &TextureTransform) -> Vec<SpriteVertex> {
-    let (tw, th) = texture.get_dimensions();
-    let (x, y, _) = props.pos; // TODO: exclude z for now
+fn update_vertices(
+    texture: &Texture,
+    props: &SpriteProps,
+    texture_transform: &TextureTransform,
+    transform: &SpriteTransform,
+    orientation: SpriteOrientation,
+) -> Vec<SpriteVertex> {
     let (width, height) = props.dim;
-    let x2 = x + (width as f32);
-    let y2 = y + (height as f32);
-    let tx = width as f32 / tw as f32;
-    let ty = height as f32 / th as f32;
+    let w = width as f32;
+    let h = height as f32;
+    let [(u, v), (u2, v2)] = corner_uvs(texture, props, orientation);
     let tex_translate = texture_transform.get_raw_translate();
     let tex_scale = texture_transform.get_raw_scale();
     let color = normalize_color(props.color);
-    let vertices: Vec<SpriteVertex> = vec![
-       SpriteVertex { pos: (x, y, 0.0).into(), tex: (0.0, 0.0).into(), color: color.into(), tex_translate: tex_translate.into(), tex_scale: tex_scale.into() },
-       SpriteVertex { pos: (x2, y, 0.0).into(), tex: (tx, 0.0).into(), color: color.into(), tex_translate: tex_translate.into(), tex_scale: tex_scale.into() },
-       SpriteVertex { pos: (x, y2, 0.0).into(), tex: (0.0, ty).into(), color: color.into(), tex_translate: tex_translate.into(), tex_scale: tex_scale.into() },
-       // second triangle
-       SpriteVertex { pos: (x2, y2, 0.0).into(), tex: (tx, ty).into(), color: color.into(), tex_translate: tex_translate.into(), tex_scale: tex_scale.into() }
+    let model = transform.get_model();
+    // corners are in sprite space, the model moves them to where the sprite is in the world
+    let corners = [
+        (0.0, 0.0, u, v),
+        (w, 0.0, u2, v),
+        (0.0, h, u, v2),
+        // second triangle
+        (w, h, u2, v2),
     ];
 
-    vertices
+    corners.iter()
+        .map(|&(x, y, tu, tv)| {
+            let pos = model * glm::vec4(x, y, 0.0, 1.0);
+
+            SpriteVertex {
+                pos: (pos.x, pos.y, 0.0).into(), // TODO: exclude z for now
+                tex: (tu, tv).into(),
+                color: color.into(),
+                tex_translate: tex_translate.into(),
+                tex_scale: tex_scale.into(),
+            }
+        })
+        .collect()
+}
+
+fn direction(flipped: bool) -> Direction {
+    if flipped {
+        Direction::Flipped
+    } else {
+        Direction::Normal
+    }
 }
+
+// uvs of the top left and bottom right corners before the texture transform
+fn corner_uvs(texture: &Texture, props: &SpriteProps, orientation: SpriteOrientation) -> [(f32, f32); 2] {
+    let (tw, th) = texture.get_dimensions();
+    let (width, height) = props.dim;
+    let tx = width as f32 / tw as f32;
+    let ty = height as f32 / th as f32;
+    let (u, u2) = match orientation.horizontal {
+        Direction::Normal => (0.0, tx),
+        Direction::Flipped => (tx, 0.0),
+    };
+    let (v, v2) = match orientation.vertical {
+        Direction::Normal => (0.0, ty),
+        Direction::Flipped => (ty, 0.0),
+    };
+
+    [(u, v), (u2, v2)]
+}
+
+// the same quad as `update_vertices` with the texture transform applied to the uvs up front
+fn quad_instance(
+    texture: &Texture,
+    props: &SpriteProps,
+    texture_transform: &TextureTransform,
+    transform: &SpriteTransform,
+    orientation: SpriteOrientation,
+) -> QuadInstance {
+    let (width, height) = props.dim;
+    let (w, h) = (width as f32, height as f32);
+    let [(u, v), (u2, v2)] = corner_uvs(texture, props, orientation);
+    let tex_translate = texture_transform.get_raw_translate();
+    let tex_scale = texture_transform.get_raw_scale();
+    let model = transform.get_model();
+
+    QuadInstance {
+        // the model with the unit quad scaled up to the sprite's size first
+        transform: [
+            (model[(0, 0)] * w, model[(0, 1)] * h, model[(0, 3)]),
+            (model[(1, 0)] * w, model[(1, 1)] * h, model[(1, 3)]),
+        ],
+        uv_rect: (
+            u * tex_scale.x + tex_translate.x,
+            v * tex_scale.y + tex_translate.y,
+            u2 * tex_scale.x + tex_translate.x,
+            v2 * tex_scale.y + tex_translate.y,
+        ),
+        color: normalize_color(props.color),
+    }
+}
+
 // TODO: this is clearly used a lot, need to find better single place for this type of thing
-fn normalize_color(color: (u8, u8, u8, f32)) -> (f32, f32, f32, f32) {
+pub(crate) fn normalize_color(color: (u8, u8, u8, f32)) -> (f32, f32, f32, f32) {
     let (r, g, b, a) = color;
     (
         r as f32 / 255.0,
@@ -271,12 +448,11 @@ mod test {
 
     #[test]
     fn can_set_rotation() {
-        // TODO: not sure about rotation
         let mut transform = SpriteTransform::default();
         let expected_transform = [
-            0.921061, 0.0, -0.38941833, 0.0,
-            0.0, 1.0, 0.0, 0.0,
-            0.38941833, 0.0, 0.921061, 0.0,
+            0.921061, 0.38941833, 0.0, 0.0,
+            -0.38941833, 0.921061, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
             0.0, 0.0, 0.0, 1.0
         ];
         transform.set_rotation(0.40);
@@ -288,9 +464,9 @@ mod test {
     fn model_is_updated_on_change() {
         let mut transform = SpriteTransform::default();
         let expected_transform = [
-            1.842122, 0.0, -0.77883667, 0.0,
-            0.0, 2.0, 0.0, 0.0,
-            0.38941833, 0.0, 0.921061, 0.0,
+            1.842122, 0.77883667, 0.0, 0.0,
+            -0.77883667, 1.842122, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
             2.5, 2.5, 1.5, 1.0
         ];
         transform.set_scale((2.0, 2.0));
@@ -299,5 +475,108 @@ mod test {
 
         assert_eq!(expected_transform, transform.get_model().data.as_slice());
     }
+
+    fn test_props() -> SpriteProps {
+        SpriteProps {
+            pos: (10.0, 10.0, 0.0),
+            dim: (4, 2),
+            ..SpriteProps::default()
+        }
+    }
+
+    fn positions(vertices: &[SpriteVertex]) -> Vec<(f32, f32)> {
+        vertices.iter()
+            .map(|vertex| {
+                let pos = vertex.pos;
+
+                ((pos.d0 * 1000.0).round() / 1000.0, (pos.d1 * 1000.0).round() / 1000.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vertices_follow_scale() {
+        let texture = Texture::from_handle(0, 4, 2);
+        let props = test_props();
+        let mut transform = SpriteTransform::default();
+        transform.set_translation(props.pos);
+        transform.set_scale((2.0, 3.0));
+        let vertices = update_vertices(&texture, &props, &TextureTransform::new(4, 2), &transform, SpriteOrientation::default());
+
+        assert_eq!(positions(&vertices), vec![(10.0, 10.0), (18.0, 10.0), (10.0, 16.0), (18.0, 16.0)]);
+    }
+
+    #[test]
+    fn vertices_rotate_about_origin() {
+        let texture = Texture::from_handle(0, 4, 2);
+        let props = test_props();
+        let mut transform = SpriteTransform::default();
+        transform.set_translation(props.pos);
+        transform.set_origin((2.0, 1.0));
+        transform.set_rotation(std::f32::consts::FRAC_PI_2);
+        let vertices = update_vertices(&texture, &props, &TextureTransform::new(4, 2), &transform, SpriteOrientation::default());
+
+        assert_eq!(positions(&vertices), vec![(13.0, 9.0), (13.0, 13.0), (11.0, 9.0), (11.0, 13.0)]);
+    }
+
+    #[test]
+    fn flipping_mirrors_uvs_in_place() {
+        let texture = Texture::from_handle(0, 4, 2);
+        let props = test_props();
+        let mut transform = SpriteTransform::default();
+        transform.set_translation(props.pos);
+        let orientation = SpriteOrientation { horizontal: Direction::Flipped, vertical: Direction::Normal };
+        let vertices = update_vertices(&texture, &props, &TextureTransform::new(4, 2), &transform, orientation);
+        let uvs: Vec<(f32, f32)> = vertices.iter().map(|vertex| (vertex.tex.d0, vertex.tex.d1)).collect();
+
+        assert_eq!(positions(&vertices), vec![(10.0, 10.0), (14.0, 10.0), (10.0, 12.0), (14.0, 12.0)]);
+        assert_eq!(uvs, vec![(1.0, 0.0), (0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+    }
+
+    #[test]
+    fn flipping_can_be_undone() {
+        let texture = Texture::from_handle(0, 4, 2);
+        let mut sprite = Sprite::from_texture(&texture, test_props()).unwrap();
+        let uvs = |sprite: &Sprite| sprite.get_vertices().iter().map(|vertex| (vertex.tex.d0, vertex.tex.d1)).collect::<Vec<_>>();
+        let unflipped = uvs(&sprite);
+
+        sprite.set_flip_h(true);
+        sprite.set_flip_v(true);
+
+        assert_eq!(uvs(&sprite), vec![(1.0, 1.0), (0.0, 1.0), (1.0, 0.0), (0.0, 0.0)]);
+
+        sprite.set_flip_h(false);
+        sprite.set_flip_v(false);
+
+        assert_eq!(uvs(&sprite), unflipped);
+    }
+
+    #[test]
+    fn quad_instance_matches_vertices() {
+        let texture = Texture::from_handle(0, 8, 4);
+        let props = test_props();
+        let mut transform = SpriteTransform::default();
+        let mut texture_transform = TextureTransform::new(8, 4);
+        transform.set_translation(props.pos);
+        transform.set_origin((2.0, 1.0));
+        transform.set_scale((2.0, 1.5));
+        transform.set_rotation(0.7);
+        texture_transform.set_scale(0.5, 2.0);
+        let orientation = SpriteOrientation { horizontal: Direction::Flipped, vertical: Direction::Normal };
+        let vertices = update_vertices(&texture, &props, &texture_transform, &transform, orientation);
+        let instance = quad_instance(&texture, &props, &texture_transform, &transform, orientation);
+        let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+        let instance_positions: Vec<(f32, f32)> = corners.iter()
+            .map(|&corner| {
+                let (x, y) = instance.corner(corner);
+
+                ((x * 1000.0).round() / 1000.0, (y * 1000.0).round() / 1000.0)
+            })
+            .collect();
+        let (u, v, u2, v2) = instance.uv_rect;
+
+        assert_eq!(positions(&vertices), instance_positions);
+        assert_eq!((u, v, u2, v2), (0.25, 0.0, 0.0, 1.0));
+    }
 }
 
@@ -1,35 +1,240 @@
-// TODO: putting this here as it may prove to be a useful feature for taking screenshots
-// or just debugging dynamically created textures/render targets/frame buffers
-pub fn snapshot_texture(texture: &FontTexture, file_name: &str) {
-    let size = texture.width * texture.height * 4;
-    texture.bind();
+use std::path::{Path, PathBuf};
 
-    let mut img_buf: Vec<u8> = Vec::with_capacity(size as usize);// as *mut std::ffi::c_void;
-    unsafe { img_buf.set_len(size as usize);}
-    let mut img_ptr = img_buf.as_mut_ptr();
-    let mut img_container = image::DynamicImage::new_rgba8(texture.width, texture.height).to_rgba();
+use image::{RgbaImage};
+
+/// Set to regenerate reference images instead of comparing against them
+pub const UPDATE_REFERENCES_ENV: &str = "YARGE_UPDATE_SNAPSHOTS";
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display="No reference image at {:?}, set YARGE_UPDATE_SNAPSHOTS to write it", reference_path)]
+    MissingReference {
+        reference_path: PathBuf,
+    },
+    #[fail(display="Image is {:?} but the reference is {:?}", actual, expected)]
+    SizeMismatch {
+        actual: (u32, u32),
+        expected: (u32, u32),
+    },
+    #[fail(
+        display="{} pixels differ by more than the tolerance (allowed {}, largest difference {}), actual image saved to {:?}",
+        mismatched_pixels, allowed, max_difference, actual_path
+    )]
+    TooManyDifferences {
+        mismatched_pixels: usize,
+        allowed: usize,
+        max_difference: u8,
+        actual_path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImageDiff {
+    /// Pixels where any channel differs by more than the tolerance
+    pub mismatched_pixels: usize,
+    /// Largest difference of any channel of any pixel
+    pub max_difference: u8,
+}
+
+/// Reads back a region of the currently bound framebuffer, flipped so the first row is the top of the screen
+pub fn read_pixels(x: i32, y: i32, width: u32, height: u32) -> RgbaImage {
+    let mut pixels: Vec<u8> = vec![0; (width * height * 4) as usize];
 
     unsafe {
         gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
-        gl::GetTexImage(gl::TEXTURE_2D, 0, gl::RGBA, gl::UNSIGNED_BYTE, img_ptr as *mut std::ffi::c_void);
+        gl::ReadPixels(
+            x,
+            y,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut gl::types::GLvoid
+        );
+    }
+
+    RgbaImage::from_raw(width, height, flip_rows(&pixels, width, height))
+        .expect("Pixel buffer does not match image size")
+}
+
+/// Reads back a whole texture (e.g. a RenderTexture or a FontTexture) in the order its rows are stored
+pub fn snapshot_texture(texture_handle: gl::types::GLuint, width: u32, height: u32) -> RgbaImage {
+    let mut pixels: Vec<u8> = vec![0; (width * height * 4) as usize];
+
+    unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, texture_handle);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::GetTexImage(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut gl::types::GLvoid
+        );
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    RgbaImage::from_raw(width, height, pixels)
+        .expect("Pixel buffer does not match image size")
+}
+
+pub fn compare_images(actual: &RgbaImage, expected: &RgbaImage, tolerance: u8) -> Result<ImageDiff, Error> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(Error::SizeMismatch {
+            actual: actual.dimensions(),
+            expected: expected.dimensions(),
+        });
+    }
+
+    let mut diff = ImageDiff::default();
+
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        let difference = a.0.iter()
+            .zip(e.0.iter())
+            .map(|(a, e)| (i16::from(*a) - i16::from(*e)).unsigned_abs() as u8)
+            .max()
+            .unwrap_or(0);
+
+        if difference > tolerance {
+            diff.mismatched_pixels += 1;
+        }
+
+        diff.max_difference = diff.max_difference.max(difference);
+    }
+
+    Ok(diff)
+}
+
+/// Compares `actual` against the PNG at `reference_path`. The reference is only written, instead of
+/// compared against, when `YARGE_UPDATE_SNAPSHOTS` is set, a missing reference is an error. On failure
+/// the actual image is saved next to the reference as `<name>.actual.png`
+pub fn assert_matches_reference(
+    actual: &RgbaImage,
+    reference_path: &Path,
+    tolerance: u8,
+    allowed_mismatched_pixels: usize,
+) -> Result<ImageDiff, failure::Error> {
+    let update = std::env::var_os(UPDATE_REFERENCES_ENV).is_some();
+
+    match_reference(actual, reference_path, tolerance, allowed_mismatched_pixels, update)
+}
+
+// `assert_matches_reference` without reading the environment, `update` writes the reference
+fn match_reference(
+    actual: &RgbaImage,
+    reference_path: &Path,
+    tolerance: u8,
+    allowed_mismatched_pixels: usize,
+    update: bool,
+) -> Result<ImageDiff, failure::Error> {
+    if update {
+        actual.save(reference_path)?;
+
+        return Ok(ImageDiff::default());
     }
-    texture.unbind();
 
-    let mut x = 0;
-    let mut y = 0;
-    let len = img_buf.len() - 4;
-    for pxc in (0..len).step_by(4) {
-        x += 1;
-        if x >= texture.width { x = 0; y += 1; }
-        let px = pxc as u32;
+    if !reference_path.exists() {
+        return Err(Error::MissingReference { reference_path: reference_path.to_path_buf() }.into());
+    }
+
+    let expected = image::open(reference_path)?.to_rgba();
+    let diff = compare_images(actual, &expected, tolerance)?;
+
+    if diff.mismatched_pixels > allowed_mismatched_pixels {
+        let actual_path = reference_path.with_extension("actual.png");
+        actual.save(&actual_path)?;
+
+        return Err(Error::TooManyDifferences {
+            mismatched_pixels: diff.mismatched_pixels,
+            allowed: allowed_mismatched_pixels,
+            max_difference: diff.max_difference,
+            actual_path,
+        }.into());
+    }
+
+    Ok(diff)
+}
+
+fn flip_rows(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let row_length = (width * 4) as usize;
+
+    pixels.chunks(row_length)
+        .take(height as usize)
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, image::Rgba(color))
+    }
+
+    #[test]
+    fn can_flip_rows() {
+        let pixels = vec![
+            1, 1, 1, 1, 2, 2, 2, 2,
+            3, 3, 3, 3, 4, 4, 4, 4,
+        ];
+        let expected = vec![
+            3, 3, 3, 3, 4, 4, 4, 4,
+            1, 1, 1, 1, 2, 2, 2, 2,
+        ];
+
+        assert_eq!(flip_rows(&pixels, 2, 2), expected);
+    }
+
+    #[test]
+    fn compare_allows_differences_within_tolerance() {
+        let actual = filled(4, 4, [100, 100, 100, 255]);
+        let expected = filled(4, 4, [102, 98, 100, 255]);
+        let diff = compare_images(&actual, &expected, 2).unwrap();
+
+        assert_eq!(diff, ImageDiff { mismatched_pixels: 0, max_difference: 2 });
+    }
+
+    #[test]
+    fn compare_counts_pixels_outside_tolerance() {
+        let actual = filled(4, 4, [100, 100, 100, 255]);
+        let mut expected = actual.clone();
+        expected.put_pixel(1, 1, image::Rgba([100, 100, 100, 0]));
+        expected.put_pixel(2, 3, image::Rgba([110, 100, 100, 255]));
+        let diff = compare_images(&actual, &expected, 5).unwrap();
+
+        assert_eq!(diff, ImageDiff { mismatched_pixels: 2, max_difference: 255 });
+    }
+
+    #[test]
+    fn compare_fails_on_size_mismatch() {
+        let result = compare_images(&filled(4, 4, [0; 4]), &filled(4, 2, [0; 4]), 0);
 
-        img_container.put_pixel(x, y, image::Rgba([
-           img_buf[pxc + 0],
-           img_buf[pxc + 1],
-           img_buf[pxc + 2],
-           255//img_buf[pxc + 3]
-        ]));
+        assert!(matches!(result, Err(Error::SizeMismatch { actual: (4, 4), expected: (4, 2) })));
     }
 
-    img_container.save(file_name).unwrap();
+    #[test]
+    fn fails_on_missing_reference_then_compares_against_it() {
+        let reference_path = std::env::temp_dir().join(format!("yarge_snapshot_{}.png", std::process::id()));
+        let image = filled(3, 3, [10, 20, 30, 255]);
+
+        assert!(match_reference(&image, &reference_path, 0, 0, false).is_err());
+        assert!(!reference_path.exists());
+
+        match_reference(&image, &reference_path, 0, 0, true).unwrap();
+
+        assert!(match_reference(&image, &reference_path, 0, 0, false).is_ok());
+
+        let changed = filled(3, 3, [10, 20, 90, 255]);
+        let result = match_reference(&changed, &reference_path, 0, 0, false);
+        let actual_path = reference_path.with_extension("actual.png");
+
+        assert!(result.is_err());
+        assert!(actual_path.exists());
+
+        std::fs::remove_file(reference_path).unwrap();
+        std::fs::remove_file(actual_path).unwrap();
+    }
 }
@@ -3,12 +3,15 @@ use image::{DynamicImage, GenericImageView};
 use std::fmt;
 
 use crate::resources::*;
+use crate::renderer::backend::{RenderBackend};
 
 #[derive(Clone)]
 pub struct Texture {
     pub texture_handle: gl::types::GLuint,
     pub image_data: DynamicImage,
     pub image_path: String,
+    dimensions: (u32, u32),
+    owned: bool,
 }
 
 impl fmt::Debug for Texture {
@@ -39,11 +42,56 @@ impl Texture {
             texture_handle,
             image_data,
             image_path,
+            dimensions: (iw, ih),
+            owned: true,
         })
     }
 
+    /// Loads `image_path` into `backend`, for a `Renderer2D` made `with_backend`. The backend owns
+    /// the texture, it isn't deleted when this is dropped
+    pub fn with_backend(res: &Resources, image_path: String, backend: &mut dyn RenderBackend) -> Result<Texture, failure::Error> {
+        let image_data = res.load_image_from_path(&image_path)?;
+        let (iw, ih) = image_data.dimensions();
+        let texture_handle = backend.create_texture(iw, ih, &image_data.to_rgba());
+
+        Ok(Texture {
+            texture_handle,
+            image_data,
+            image_path,
+            dimensions: (iw, ih),
+            owned: false,
+        })
+    }
+
+    /// Creates a texture from raw RGBA pixels, `pixels` has to be `width * height * 4` bytes
+    pub fn from_pixels(width: u32, height: u32, pixels: &[u8]) -> Texture {
+        let image_rgba = image::RgbaImage::from_raw(width, height, pixels.to_vec())
+            .expect("Texture pixels do not match its size");
+        let texture_handle = create_texture(width, height, pixels);
+
+        Texture {
+            texture_handle,
+            image_data: DynamicImage::ImageRgba8(image_rgba),
+            image_path: "".to_string(),
+            dimensions: (width, height),
+            owned: true,
+        }
+    }
+
+    /// Wraps a gl texture created elsewhere, ie a `RenderTexture`. The texture is not deleted
+    /// when this is dropped and `image_data` is empty, only its dimensions are kept
+    pub fn from_handle(texture_handle: gl::types::GLuint, width: u32, height: u32) -> Texture {
+        Texture {
+            texture_handle,
+            image_data: DynamicImage::new_rgba8(0, 0),
+            image_path: "".to_string(),
+            dimensions: (width, height),
+            owned: false,
+        }
+    }
+
     pub fn get_dimensions(&self) -> (u32, u32) {
-        self.image_data.dimensions()
+        self.dimensions
     }
 
     pub fn get_texture_handle(&self) -> gl::types::GLuint {
@@ -70,14 +118,11 @@ impl Texture {
     }
 }
 
-fn create_texture(width: u32, height: u32, image_raw: &[u8]) -> gl::types::GLuint {
+pub(crate) fn create_texture(width: u32, height: u32, image_raw: &[u8]) -> gl::types::GLuint {
     let image_ptr = image_raw.as_ptr() as *const gl::types::GLvoid;
     let mut texture_handle: gl::types::GLuint = 1;
 
     unsafe {
-        gl::Enable(gl::BLEND);
-        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-
         gl::GenTextures(1, &mut texture_handle);
         gl::BindTexture(gl::TEXTURE_2D, texture_handle);
 
@@ -107,6 +152,10 @@ fn create_texture(width: u32, height: u32, image_raw: &[u8]) -> gl::types::GLuin
 
 impl Drop for Texture {
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+
         unsafe {
             gl::DeleteTextures(1, &self.texture_handle);
         }
@@ -4,8 +4,9 @@ use std::collections::HashMap;
 
 use crate::resources::*;
 use crate::textures::texture::*;
-use crate::renderer::renderable::{Renderable2D, RenderVertex};
+use crate::renderer::renderable::{Renderable2D, VertexSink};
 use crate::sprite::{Sprite, SpriteProps};
+use crate::lighting::{Occluder};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tile {
@@ -77,16 +78,10 @@ impl Renderable2D for Tilemap {
         self.tileset.get_texture().texture_handle
     }
 
-    fn vertices(&self) -> Vec<Box<dyn RenderVertex>> {
-        let mut v = Vec::new();
-        for ts in &self.vertices {
-            let sv = ts.vertices();
-            for vs in sv {
-                v.push(vs);
-            }
+    fn write_vertices(&self, out: &mut VertexSink) {
+        for tile in &self.vertices {
+            tile.write_vertices(out);
         }
-
-        v
     }
 }
 
@@ -118,6 +113,30 @@ impl Tilemap {
     pub fn get_vertices(&self) -> &Vec<Sprite> {
         &self.vertices
     }
+
+    /// A square occluder for every tile of `tile_type` (ie "ground") on any layer, to cast shadows from solid tiles
+    pub fn solid_occluders(&self, tile_type: &str) -> Vec<Occluder> {
+        let tileset = &self.tileset;
+
+        self.layers.iter()
+            .flat_map(|layer| tile_positions(layer, tileset))
+            .filter(|(tile_id, _)| matches!(tileset.tiles.get(&tile_id.to_string()), Some(tile) if tile.tile_type == tile_type))
+            .map(|(_, (x, y))| Occluder::rect(x, y, tileset.tile_width as f32, tileset.tile_height as f32))
+            .collect()
+    }
+}
+
+// every tile id of a layer with the world position of its tile, going along the rows.
+// A layer narrower than a tile is one column
+fn tile_positions<'a>(layer: &'a TileLayer, tileset: &Tileset) -> impl Iterator<Item = (u32, (f32, f32))> + 'a {
+    let cols = (layer.width / tileset.tile_width.max(1)).max(1);
+    let (tile_width, tile_height) = (tileset.tile_width, tileset.tile_height);
+
+    layer.data.iter().enumerate().map(move |(i, tile_id)| {
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+
+        (*tile_id, ((tile_width * col) as f32, (tile_height * row) as f32))
+    })
 }
 
 fn generate_vertices_from_layer(layer: &TileLayer, tileset: &Tileset) -> Vec<Sprite> {
@@ -1,6 +1,8 @@
 mod triangle_shaders;
 
+use std::cell::OnceCell;
 use crate::helpers::{self, data, buffer};
+use crate::renderer::renderable::{Renderable2D, BasicVertex, VertexSink};
 use triangle_shaders::{VERTEX_SOURCE, FRAGMENT_SOURCE};
 
 #[derive(VertexAttribPointers)]
@@ -13,26 +15,39 @@ struct Vertex {
     clr: data::u2_u10_u10_u10_rev_float,
 }
 
-pub struct Triangle {
+/// Points are in normalized device coordinates when drawn with `render`, and world coordinates
+/// when submitted to the batch renderer
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriangleProps {
+    pub points: [(f32, f32); 3],
+    pub colors: [(f32, f32, f32, f32); 3],
+}
+
+impl Default for TriangleProps {
+    fn default() -> Self {
+        Self {
+            points: [(-0.5, -0.5), (0.5, -0.5), (0.0, 0.5)],
+            colors: [(1.0, 0.0, 0.0, 1.0), (0.0, 1.0, 0.0, 1.0), (0.0, 0.0, 1.0, 1.0)],
+        }
+    }
+}
+
+// the gl objects `Triangle::render` draws with, batched triangles never need them
+struct TrianglePipeline {
     program: helpers::Program,
-    _vbo: buffer::ArrayBuffer,
+    vbo: buffer::ArrayBuffer,
     vao: buffer::VertexArray,
 }
 
-impl Triangle {
-    pub fn new() -> Result<Triangle, failure::Error> {
+impl TrianglePipeline {
+    fn new(props: &TriangleProps) -> Result<TrianglePipeline, failure::Error> {
         let shaders = vec![
             helpers::Shader::from_raw(&VERTEX_SOURCE, gl::VERTEX_SHADER)?,
             helpers::Shader::from_raw(&FRAGMENT_SOURCE, gl::FRAGMENT_SHADER)?,
         ];
         let program = helpers::Program::from_shaders(&shaders[..], "internal/shaders/triangle")
             .expect("Failed to create Triangle Shader Program");
-        let vertices: Vec<Vertex> = vec![
-           Vertex { pos: (-0.5, -0.5, 0.0).into(), clr: (1.0, 0.0, 0.0, 1.0).into() }, // bottom right
-           Vertex { pos: (0.5, -0.5, 0.0).into(), clr: (0.0, 1.0, 0.0, 1.0).into() }, // bottom left
-           Vertex { pos: (0.0, 0.5, 0.0).into(), clr: (0.0, 0.0, 1.0, 1.0).into() } // top
-        ];
-
+        let vertices = triangle_vertices(props);
         let vbo = buffer::ArrayBuffer::new();
 
         vbo.bind();
@@ -49,16 +64,73 @@ impl Triangle {
         vbo.unbind();
         vao.unbind();
 
-        Ok(Triangle {
+        Ok(TrianglePipeline {
             program,
-            _vbo: vbo,
+            vbo,
             vao,
         })
     }
+}
+
+pub struct Triangle {
+    pipeline: OnceCell<TrianglePipeline>,
+    props: TriangleProps,
+}
+
+impl Triangle {
+    pub fn new() -> Result<Triangle, failure::Error> {
+        Triangle::from_props(TriangleProps::default())
+    }
+
+    /// Doesn't touch gl, the shader program `render` draws with is only built the first time it's called
+    pub fn from_props(props: TriangleProps) -> Result<Triangle, failure::Error> {
+        Ok(Triangle {
+            pipeline: OnceCell::new(),
+            props,
+        })
+    }
 
-    pub fn render(&self) {
-        self.program.set_used();
-        self.vao.bind();
+    pub fn get_props(&self) -> &TriangleProps {
+        &self.props
+    }
+
+    pub fn set_points(&mut self, points: [(f32, f32); 3]) {
+        self.props.points = points;
+        self.update_vertices();
+    }
+
+    pub fn set_colors(&mut self, colors: [(f32, f32, f32, f32); 3]) {
+        self.props.colors = colors;
+        self.update_vertices();
+    }
+
+    /// Same colour for every point
+    pub fn set_color(&mut self, color: (f32, f32, f32, f32)) {
+        self.set_colors([color; 3]);
+    }
+
+    // without a pipeline there's nothing to upload to, it's built from the current props
+    fn update_vertices(&self) {
+        if let Some(pipeline) = self.pipeline.get() {
+            pipeline.vbo.bind();
+            pipeline.vbo.static_draw_data(&triangle_vertices(&self.props));
+            pipeline.vbo.unbind();
+        }
+    }
+
+    /// Draws the triangle straight away with its own shader program, fails when the program can't be built
+    pub fn render(&self) -> Result<(), failure::Error> {
+        let pipeline = match self.pipeline.get() {
+            Some(pipeline) => pipeline,
+            None => {
+                let pipeline = TrianglePipeline::new(&self.props)?;
+
+                self.pipeline.get_or_init(|| pipeline)
+            },
+        };
+
+        pipeline.program.set_used();
+        pipeline.vao.bind();
 
         unsafe {
             gl::DrawArrays(
@@ -67,6 +139,46 @@ impl Triangle {
                 3
             );
         }
+
+        Ok(())
     }
 }
 
+
+/// Batched with the renderer's white texture
+impl Renderable2D for Triangle {
+    fn write_vertices(&self, out: &mut VertexSink) {
+        out.extend(&batch_vertices(&self.props));
+        out.extend_indices(&[0, 1, 2]);
+    }
+}
+
+fn triangle_vertices(props: &TriangleProps) -> Vec<Vertex> {
+    props.points.iter()
+        .zip(props.colors.iter())
+        .map(|(&(x, y), &color)| Vertex { pos: (x, y, 0.0).into(), clr: color.into() })
+        .collect()
+}
+
+fn batch_vertices(props: &TriangleProps) -> [BasicVertex; 3] {
+    [0, 1, 2].map(|i| {
+        let (x, y) = props.points[i];
+
+        BasicVertex { pos: (x, y, 0.0), uv: (0.0, 0.0), color: props.colors[i] }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_vertices_match_points() {
+        let props = TriangleProps::default();
+        let vertices = batch_vertices(&props);
+        let positions: Vec<(f32, f32, f32)> = vertices.iter().map(|vertex| vertex.pos).collect();
+
+        assert_eq!(positions, vec![(-0.5, -0.5, 0.0), (0.5, -0.5, 0.0), (0.0, 0.5, 0.0)]);
+        assert_eq!(vertices[2].color, props.colors[2]);
+    }
+}